@@ -0,0 +1,112 @@
+// Blocks per second update_with_block() validates and connects, for blocks
+// of ordinary one-in, one-out transactions
+use blockchainlib::*;
+use blockchainlib::transaction::{address, Input, OutPoint, Output};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use ed25519_dalek::SigningKey;
+
+const TRANSACTION_COUNTS: [usize; 3] = [10, 100, 1_000];
+
+fn key (seed: usize) -> SigningKey {
+    let mut bytes = [0; 32];
+    bytes[..8].copy_from_slice(&(seed as u64).to_le_bytes());
+    SigningKey::from_bytes(&bytes)
+}
+
+fn empty_chain () -> Blockchain {
+    Blockchain::new(u128::MAX)
+        .with_block_reward(10_000, u32::MAX)
+        .with_coinbase_maturity(0)
+        // Room for the fan-out's change on top of one output per transaction
+        .with_max_inputs_outputs(1_000, 1_001)
+}
+
+fn mine (chain: &Blockchain, transactions: Vec<Transaction>) -> Block {
+    // Blocks mined in the same millisecond still need rising timestamps
+    let timestamp = now().max(chain.tip().unwrap().header.timestamp + 1);
+    let mut block = Block::new(chain.block_count() as u32, timestamp, chain.last_hash(), transactions, u128::MAX);
+    block.header.utxo_root = chain.utxo_root_for(&block).unwrap();
+    block.mine(u128::MAX).unwrap();
+    block
+}
+
+// Committing to the height keeps coinbases paying the same key apart
+fn coinbase (key: &SigningKey, height: u32) -> Transaction {
+    Transaction {
+        inputs: vec![],
+        outputs: vec![Output { to_addr: address(&key.verifying_key()), value: 1, script: vec![] }],
+        coinbase: true,
+        coinbase_data: height.to_le_bytes().to_vec(),
+        lock_height: 0,
+    }
+}
+
+// A chain holding one coin for each of `count` keys, and a block spending
+// every one of them in its own transaction
+fn setup (count: usize) -> (Vec<Block>, Block) {
+    let funder = key(0);
+    let mut chain = empty_chain();
+    chain.mine_next_block(&address(&funder.verifying_key()), &mut Mempool::new()).unwrap();
+
+    let funding = &chain.block(0).unwrap().transactions[0];
+    let funds = OutPoint { txid: funding.hash(), index: 0 };
+    let change = Output { to_addr: funding.outputs[0].to_addr, value: funding.outputs[0].value - count as u64, script: vec![] };
+    let mut fan_out = Transaction {
+        inputs: vec![Input::new(funds)],
+        outputs: (1..=count)
+            .map(|seed| Output { to_addr: address(&key(seed).verifying_key()), value: 1, script: vec![] })
+            .chain(std::iter::once(change))
+            .collect(),
+        coinbase: false,
+        coinbase_data: vec![],
+        lock_height: 0,
+    };
+    fan_out.sign(&funder);
+
+    let block = mine(&chain, vec![coinbase(&key(0), 1), fan_out.clone()]);
+    chain.update_with_block(block).unwrap();
+
+    let spends = fan_out.outpoints()[..count]
+        .iter()
+        .enumerate()
+        .map(|(i, outpoint)| {
+            let mut spend = Transaction {
+                inputs: vec![Input::new(*outpoint)],
+                outputs: vec![Output { to_addr: address(&key(count + i + 1).verifying_key()), value: 1, script: vec![] }],
+                coinbase: false,
+                coinbase_data: vec![],
+                lock_height: 0,
+            };
+            spend.sign(&key(i + 1));
+            spend
+        })
+        .collect();
+
+    let mut transactions = vec![coinbase(&key(0), 2)];
+    transactions.extend(canonical_order(spends, &Sha256));
+    let block = mine(&chain, transactions);
+
+    (chain.blocks().cloned().collect(), block)
+}
+
+fn update_with_block (c: &mut Criterion) {
+    let mut group = c.benchmark_group("update_with_block");
+    group.throughput(Throughput::Elements(1));
+
+    for count in TRANSACTION_COUNTS {
+        let (blocks, block) = setup(count);
+
+        group.bench_with_input(BenchmarkId::from_parameter(count), &block, |b, block| {
+            b.iter_batched(
+                || (empty_chain().with_blocks(blocks.clone()).unwrap(), block.clone()),
+                |(mut chain, block)| chain.update_with_block(block).unwrap(),
+                BatchSize::LargeInput,
+            );
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, update_with_block);
+criterion_main!(benches);
@@ -0,0 +1,211 @@
+use super::*;
+use std::convert::TryFrom;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::str::FromStr;
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error;
+
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+// Bytes of double SHA-256 appended to the key before encoding
+const CHECKSUM_SIZE: usize = 4;
+
+// Who an output pays: the SHA-256 hash of an ed25519 public key. The key
+// itself only comes out when the output is spent, in the spender's witness,
+// see script::pay_to_address(). Written as base58 of the hash and a
+// checksum, so a mistyped address fails to parse rather than paying nobody.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Address([u8; 32]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseAddressError {
+    // A character outside the base58 alphabet
+    InvalidCharacter,
+    InvalidLength,
+    InvalidChecksum,
+}
+
+impl Address {
+    pub fn from_pubkey (public_key: &VerifyingKey) -> Self {
+        Address::from_key_bytes(public_key.as_bytes())
+    }
+
+    fn from_key_bytes (key: &[u8; 32]) -> Self {
+        Address(*Hash::digest(key).as_bytes())
+    }
+
+    // The public key hash's bytes
+    pub fn as_bytes (&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+fn checksum (key_hash: &[u8; 32]) -> [u8; CHECKSUM_SIZE] {
+    let digest = Hash::digest(Hash::digest(key_hash).as_bytes());
+    let mut checksum = [0; CHECKSUM_SIZE];
+    checksum.copy_from_slice(&digest.as_bytes()[..CHECKSUM_SIZE]);
+    checksum
+}
+
+// Addresses hash the way they did as hex strings, before they had a type of
+// their own, so outputs keep the hashes they always had
+impl Hashable for Address {
+    fn bytes (&self) -> Vec<u8> {
+        hex::encode(self.0).into_bytes()
+    }
+}
+
+impl Display for Address {
+    fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+        let mut bytes = self.0.to_vec();
+        bytes.extend(&checksum(&self.0));
+
+        write!(f, "{}", base58_encode(&bytes))
+    }
+}
+
+impl Debug for Address {
+    fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Address({})", self)
+    }
+}
+
+impl FromStr for Address {
+    type Err = ParseAddressError;
+
+    fn from_str (s: &str) -> Result<Self, Self::Err> {
+        let bytes = base58_decode(s).ok_or(ParseAddressError::InvalidCharacter)?;
+
+        if bytes.len() != 32 + CHECKSUM_SIZE {
+            return Err(ParseAddressError::InvalidLength);
+        }
+
+        let mut key_hash = [0; 32];
+        key_hash.copy_from_slice(&bytes[..32]);
+
+        if bytes[32..] != checksum(&key_hash) {
+            return Err(ParseAddressError::InvalidChecksum);
+        }
+
+        Ok(Address(key_hash))
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer> (&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+// Also reads the bare hex keys addresses were stored as before, which are
+// longer than any base58 address so can't be mistaken for one, as the
+// addresses of those keys
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>> (deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+
+        if let Ok(key) = hex::decode(&s) {
+            if let Ok(key) = <[u8; 32]>::try_from(key.as_slice()) {
+                return Ok(Address::from_key_bytes(&key));
+            }
+        }
+
+        s.parse().map_err(|err| D::Error::custom(format!("invalid address: {:?}", err)))
+    }
+}
+
+// Leading zero bytes become leading '1's, the rest is the number in base 58
+fn base58_encode (bytes: &[u8]) -> String {
+    let zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+    // Base 58 digits, least significant first
+    let mut digits: Vec<u8> = vec![];
+
+    for byte in &bytes[zeros..] {
+        let mut carry = *byte as u32;
+
+        for digit in &mut digits {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+
+    std::iter::repeat_n('1', zeros)
+        .chain(digits.iter().rev().map(|digit| BASE58_ALPHABET[*digit as usize] as char))
+        .collect()
+}
+
+fn base58_decode (s: &str) -> Option<Vec<u8>> {
+    let zeros = s.bytes().take_while(|c| *c == b'1').count();
+    // Least significant first
+    let mut bytes: Vec<u8> = vec![];
+
+    for c in s.bytes().skip(zeros) {
+        let mut carry = BASE58_ALPHABET.iter().position(|letter| *letter == c)? as u32;
+
+        for byte in &mut bytes {
+            carry += *byte as u32 * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+
+    let mut decoded = vec![0; zeros];
+    decoded.extend(bytes.iter().rev());
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+
+    fn address (seed: u8) -> Address {
+        Address::from_pubkey(&SigningKey::from_bytes(&[seed; 32]).verifying_key())
+    }
+
+    #[test]
+    fn is_the_hash_of_the_key () {
+        let key = SigningKey::from_bytes(&[1; 32]).verifying_key();
+
+        assert_eq!(Address::from_pubkey(&key).as_bytes(), Hash::digest(key.as_bytes()).as_bytes());
+        assert_ne!(address(1), address(2));
+    }
+
+    #[test]
+    fn round_trips_through_its_string () {
+        let address = address(1);
+
+        assert_eq!(address.to_string().parse::<Address>(), Ok(address));
+    }
+
+    #[test]
+    fn rejects_a_corrupted_string () {
+        let mut encoded = address(1).to_string().into_bytes();
+        let last = encoded.len() - 1;
+        encoded[last] = if encoded[last] == b'2' { b'3' } else { b'2' };
+        let encoded = String::from_utf8(encoded).unwrap();
+
+        assert_eq!(encoded.parse::<Address>(), Err(ParseAddressError::InvalidChecksum));
+        assert_eq!("0OIl".parse::<Address>(), Err(ParseAddressError::InvalidCharacter));
+        assert_eq!("2".parse::<Address>(), Err(ParseAddressError::InvalidLength));
+    }
+
+    #[test]
+    fn reads_legacy_hex_keys_as_their_address () {
+        let key = SigningKey::from_bytes(&[1; 32]).verifying_key();
+        let stored = bincode::serialize(&hex::encode(key.as_bytes())).unwrap();
+        let read: Address = bincode::deserialize(&stored).unwrap();
+
+        assert_eq!(read, Address::from_pubkey(&key));
+    }
+}
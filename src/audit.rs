@@ -0,0 +1,57 @@
+use super::*;
+
+// Where audit_supply() found the coin supply not adding up. `block` is the
+// block's index.
+#[derive(Debug)]
+pub enum SupplyError {
+    // Pruned blocks have lost the transactions the audit needs
+    Pruned,
+    // A coinbase pays less than its block's fees, or mints more than the
+    // block reward on top of them
+    InvalidIssuance { block: u32 },
+    // What the blocks minted doesn't match what is left unspent
+    UnspentValueMismatch { minted: u64, unspent: u64 },
+    Overflow,
+}
+
+impl Blockchain {
+    // Re-adds every block's issuance from its coinbase and the fees
+    // validation recorded for it, see transaction_fee(): each coinbase may
+    // mint at most the block reward beyond the fees it collects, and since
+    // fees only move coins, everything minted must still be somewhere in the
+    // UTXO set. Returns the circulating supply.
+    pub fn audit_supply (&self) -> Result<u64, SupplyError> {
+        if self.is_pruned() {
+            return Err(SupplyError::Pruned);
+        }
+
+        let mut minted: u64 = 0;
+
+        for block in self.blocks() {
+            let index = block.header.index;
+            let coinbase = match block.transactions.first() {
+                Some(coinbase) => coinbase,
+                None => continue,
+            };
+            let fees = self.block_fees(index).ok_or(SupplyError::Overflow)?;
+
+            let issued = coinbase.output_value().ok_or(SupplyError::Overflow)?
+                .checked_sub(fees)
+                .filter(|issued| *issued <= self.block_reward(index))
+                .ok_or(SupplyError::InvalidIssuance { block: index })?;
+
+            minted = minted.checked_add(issued).ok_or(SupplyError::Overflow)?;
+        }
+
+        let unspent = self.utxo_snapshot()
+            .iter()
+            .try_fold(0u64, |sum, (_, output)| sum.checked_add(output.value))
+            .ok_or(SupplyError::Overflow)?;
+
+        if minted != unspent {
+            return Err(SupplyError::UnspentValueMismatch { minted, unspent });
+        }
+
+        Ok(minted)
+    }
+}
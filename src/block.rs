@@ -1,25 +1,79 @@
-use std::fmt::{self, Debug, Formatter};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::{self, Debug, Display, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
 use super::*;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::ser::SerializeStruct;
+#[cfg(feature = "async")]
+use tokio_util::sync::CancellationToken;
 
+// Network magic values, so blocks from one network can't end up on another
+pub const MAINNET: u32 = 0xd9b4_bef9;
+pub const TESTNET: u32 = 0x0709_110b;
 
-pub struct Block {
+// The version Block::new() stamps on blocks
+pub const BLOCK_VERSION: u32 = 1;
+
+// Everything a block's hash covers. The transactions are committed to through
+// merkle_root, so a header can be checked without them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockHeader {
     pub index: u32,
     pub timestamp: u128,
-    pub hash: Hash,
     pub prev_block_hash: Hash,
+    // Root of the Merkle tree over the transactions' wtxids, see Block::merkle_root()
+    pub merkle_root: Hash,
+    // Root of the UTXO set the block leaves behind, see
+    // Blockchain::utxo_commitment() and Blockchain::utxo_root_for()
+    pub utxo_root: Hash,
     pub nonce: u64,
-    pub transactions: Vec<Transaction>,
     pub difficulty: u128,
+    // Raised to activate new rules, see Blockchain::with_min_version()
+    pub version: u32,
+    pub network: u32,
+}
+
+#[derive(Clone)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub transactions: Vec<Transaction>,
 }
 
+#[derive(Debug)]
+pub enum MineError {
+    // Every nonce was tried; change the timestamp or transactions and retry
+    NonceExhausted,
+    // Mining was cancelled, see mine_async()
+    Cancelled,
+}
+
+// Nonces mine_async() tries between yields
+#[cfg(feature = "async")]
+const ASYNC_MINE_BATCH: u64 = 1_000;
+
 impl Debug for Block {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(f, "Block[{}]: {} at: {} with: {} nonce: {}",
-            &self.index,
-            &hex::encode(&self.hash),
-            &self.timestamp,
+            &self.header.index,
+            &self.hash(),
+            &self.header.timestamp,
             &self.transactions.len(),
-            &self.nonce,
+            &self.header.nonce,
+        )
+    }
+}
+
+impl Display for Block {
+    fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Block #{} {} (prev {}) at {} nonce {}, {} transaction(s)",
+            self.header.index,
+            self.hash(),
+            self.header.prev_block_hash,
+            self.header.timestamp,
+            self.header.nonce,
+            self.transactions.len(),
         )
     }
 }
@@ -27,47 +81,426 @@ impl Debug for Block {
 impl Block {
     pub fn new(index: u32, timestamp: u128, prev_block_hash: Hash,
                transactions: Vec<Transaction>, difficulty: u128) -> Self {
-        Block {
-            index,
-            timestamp,
-            hash: vec![0; 32],
-            prev_block_hash,
-            nonce: 0,
+        let mut block = Block {
+            header: BlockHeader {
+                index,
+                timestamp,
+                prev_block_hash,
+                merkle_root: Hash::zero(),
+                utxo_root: Hash::zero(),
+                nonce: 0,
+                difficulty,
+                version: BLOCK_VERSION,
+                network: MAINNET,
+            },
             transactions,
-            difficulty,
+        };
+        block.header.merkle_root = block.merkle_root();
+        block
+    }
+
+    // Over the transactions' wtxids, so the block's hash covers their
+    // signatures as well
+    pub fn merkle_root (&self) -> Hash {
+        self.merkle_root_with(&Sha256)
+    }
+
+    // The merkle root of a block on a chain using `hasher`
+    pub fn merkle_root_with (&self, hasher: &dyn Hasher<Output = Hash>) -> Hash {
+        merkle::merkle_root_with(
+            self.transactions
+                .iter()
+                .map(|transaction| transaction.wtxid_with(hasher))
+                .collect(),
+            hasher,
+        )
+    }
+
+    // Proves a transaction is in this block against its merkle_root, see
+    // verify_merkle_proof(). The tree's leaves are wtxids, so the proof is for
+    // the transaction's wtxid(), though it is looked up by its txid. None if
+    // the transaction isn't in the block.
+    pub fn merkle_proof (&self, tx_hash: &Hash) -> Option<Vec<(Hash, bool)>> {
+        self.merkle_proof_with(tx_hash, &Sha256)
+    }
+
+    // merkle_proof() for a block on a chain using `hasher`, to check with
+    // verify_merkle_proof_with()
+    pub fn merkle_proof_with (&self, tx_hash: &Hash, hasher: &dyn Hasher<Output = Hash>) -> Option<Vec<(Hash, bool)>> {
+        let index = self.transactions
+            .iter()
+            .position(|transaction| transaction.hash_with(hasher) == *tx_hash)?;
+        let leaves = self.transactions
+            .iter()
+            .map(|transaction| transaction.wtxid_with(hasher))
+            .collect::<Vec<Hash>>();
+
+        merkle::merkle_proof_with(leaves, index, hasher)
+    }
+
+    // The checks a block can be given without a chain: it has a coinbase,
+    // first and only there, its merkle root commits to its transactions, and
+    // those are distinct and in canonical_order(). Passing says nothing about
+    // whether it fits on any chain. There's no stored hash to hold against
+    // hash(): a block's hash is always computed from its header, and the one
+    // a stored block carries is ignored when read, so the two can't disagree.
+    // SHA-256 chains only, see validate_self_with().
+    pub fn validate_self (&self) -> Result<(), BlockValidationErr> {
+        self.validate_self_with(&Sha256)
+    }
+
+    // validate_self() for a block on a chain using `hasher`
+    pub fn validate_self_with (&self, hasher: &dyn Hasher<Output = Hash>) -> Result<(), BlockValidationErr> {
+        let (coinbase, transactions) = self.transactions
+            .split_first()
+            .ok_or(BlockValidationErr::MissingCoinbase)?;
+
+        // Marked as the coinbase, shaped like one, and the only one marked
+        let invalid_coinbase = !coinbase.is_coinbase() || !coinbase.inputs.is_empty() || coinbase.outputs.is_empty()
+            || transactions.iter().any(|transaction| transaction.is_coinbase());
+
+        if invalid_coinbase {
+            return Err(BlockValidationErr::InvalidCoinbaseTransaction);
+        } else if coinbase.coinbase_data.len() > transaction::MAX_COINBASE_DATA {
+            return Err(BlockValidationErr::InvalidCoinbaseData { tx_index: 0 });
+        } else if let Some(position) = transactions.iter().position(|transaction| transaction.inputs.is_empty()) {
+            return Err(BlockValidationErr::NoInputs { tx_index: position + 1 });
+        } else if self.header.merkle_root != self.merkle_root_with(hasher) {
+            return Err(BlockValidationErr::InvalidMerkleRoot);
+        }
+
+        let mut transaction_hashes = HashSet::new();
+        if !self.transactions.iter().all(|transaction| transaction_hashes.insert(transaction.hash_with(hasher))) {
+            return Err(BlockValidationErr::DuplicateTransaction);
+        }
+
+        let positions = canonical_positions(transactions.iter(), hasher);
+        if positions.iter().enumerate().any(|(expected, &position)| position != expected) {
+            return Err(BlockValidationErr::NonCanonicalOrdering);
+        }
+
+        Ok(())
+    }
+
+    // Whether any output in the block pays an address or has a hash that is
+    // in the filter. May be a false positive, see BloomFilter.
+    pub fn matches_filter (&self, filter: &BloomFilter) -> bool {
+        self.transactions
+            .iter()
+            .flat_map(|transaction| transaction.outputs.iter())
+            .any(|output| filter.contains(&output.to_addr.bytes())
+                || filter.contains(output.hash().as_bytes()))
+    }
+
+    pub fn mine(&mut self, difficulty: u128) -> Result<(), MineError> {
+        self.mine_with(difficulty, &Sha256)
+    }
+
+    // mine() for a chain using `hasher`. mine_async() and mine_parallel()
+    // only mine SHA-256 blocks.
+    pub fn mine_with (&mut self, difficulty: u128, hasher: &dyn Hasher<Output = Hash>) -> Result<(), MineError> {
+        self.header.difficulty = difficulty;
+        // The transactions may have changed since the block was created
+        self.header.merkle_root = self.merkle_root_with(hasher);
+
+        for nonce_attempt in 0..=u64::MAX {
+            self.header.nonce = nonce_attempt;
+            if check_difficulty(&self.header.hash_with(hasher), difficulty) {
+                return Ok(());
+            }
         }
+
+        Err(MineError::NonceExhausted)
     }
 
-    pub fn mine(&mut self) {
-        for nonce_attempt in 0..(u64::max_value()) {
-            self.nonce = nonce_attempt;
-            let hash = self.hash();
-            if check_difficulty(&hash, self.difficulty) {
-                self.hash = hash;
-                return;
+    // Like mine(), but yields to the executor every ASYNC_MINE_BATCH nonces
+    // and gives up with MineError::Cancelled once `cancel` is cancelled,
+    // leaving the block's nonce and difficulty as they were.
+    #[cfg(feature = "async")]
+    pub async fn mine_async (&mut self, difficulty: u128, cancel: CancellationToken) -> Result<(), MineError> {
+        let (nonce, previous_difficulty) = (self.header.nonce, self.header.difficulty);
+        self.header.difficulty = difficulty;
+        self.header.merkle_root = self.merkle_root();
+
+        for nonce_attempt in 0..=u64::MAX {
+            if nonce_attempt % ASYNC_MINE_BATCH == 0 {
+                tokio::task::yield_now().await;
+
+                if cancel.is_cancelled() {
+                    self.header.nonce = nonce;
+                    self.header.difficulty = previous_difficulty;
+                    return Err(MineError::Cancelled);
+                }
+            }
+
+            self.header.nonce = nonce_attempt;
+            if check_difficulty(&self.header.hash(), difficulty) {
+                return Ok(());
             }
         }
+
+        Err(MineError::NonceExhausted)
+    }
+
+    // Like mine(), but splits the nonce space into `threads` ranges searched
+    // at once. Whichever thread finds a valid nonce first wins, so the nonce
+    // found may differ from run to run.
+    pub fn mine_parallel (&mut self, difficulty: u128, threads: usize) -> Result<(), MineError> {
+        self.header.difficulty = difficulty;
+        self.header.merkle_root = self.merkle_root();
+
+        let threads = threads.max(1) as u128;
+        let range_size = (u64::MAX as u128 + 1) / threads;
+        let found = AtomicBool::new(false);
+        let solution = Mutex::new(None);
+
+        thread::scope(|scope| {
+            for worker in 0..threads {
+                let start = (worker * range_size) as u64;
+                let end = if worker == threads - 1 {
+                    u64::MAX
+                } else {
+                    ((worker + 1) * range_size - 1) as u64
+                };
+                let mut header = self.header;
+                let (found, solution) = (&found, &solution);
+
+                scope.spawn(move || {
+                    for nonce_attempt in start..=end {
+                        if found.load(Ordering::Relaxed) {
+                            return;
+                        }
+
+                        header.nonce = nonce_attempt;
+                        if check_difficulty(&header.hash(), difficulty) {
+                            if !found.swap(true, Ordering::Relaxed) {
+                                *solution.lock().unwrap() = Some(nonce_attempt);
+                            }
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+
+        match solution.into_inner().unwrap() {
+            Some(nonce) => {
+                self.header.nonce = nonce;
+                Ok(())
+            },
+            None => Err(MineError::NonceExhausted),
+        }
     }
 }
 
-impl Hashable for Block {
+impl Hashable for BlockHeader {
     fn bytes (&self) -> Vec<u8> {
         let mut bytes = vec![];
         bytes.extend(&u32_bytes(&self.index));
         bytes.extend(&u128_bytes(&self.timestamp));
-        bytes.extend(&self.prev_block_hash);
+        bytes.extend(self.prev_block_hash.as_bytes());
         bytes.extend(&u64_bytes(&self.nonce));
-        bytes.extend(
-            self.transactions
-                .iter()
-                .flat_map(|transaction| transaction.bytes())
-                .collect::<Vec<u8>>()
-        );
+        bytes.extend(self.merkle_root.as_bytes());
+        bytes.extend(self.utxo_root.as_bytes());
         bytes.extend(&u128_bytes(&self.difficulty));
+        bytes.extend(&u32_bytes(&self.version));
+        bytes.extend(&u32_bytes(&self.network));
         bytes
     }
 }
 
+// A block hashes to its header's hash
+impl Hashable for Block {
+    fn bytes (&self) -> Vec<u8> {
+        self.header.bytes()
+    }
+}
+
+// Blocks are equal if their hashes are, which is what a chain means by the
+// same block. The transactions aren't compared, only committed to through the
+// header's merkle_root, so two different blocks whose headers collide under
+// SHA-256 would compare equal.
+impl PartialEq for Block {
+    fn eq (&self, other: &Block) -> bool {
+        self.hash() == other.hash()
+    }
+}
+
+impl Eq for Block {}
+
+impl std::hash::Hash for Block {
+    fn hash<H: std::hash::Hasher> (&self, state: &mut H) {
+        std::hash::Hash::hash(&Hashable::hash(self), state);
+    }
+}
+
+// Blocks are stored in the layout they had before the header was split out,
+// hash included, with the fields added since at the end
+#[derive(Deserialize)]
+#[serde(rename = "Block")]
+struct StoredBlock {
+    index: u32,
+    timestamp: u128,
+    // Derived from the header, so it is ignored when reading
+    #[serde(rename = "hash")]
+    _hash: Hash,
+    prev_block_hash: Hash,
+    merkle_root: Hash,
+    nonce: u64,
+    transactions: Vec<Transaction>,
+    difficulty: u128,
+    version: u32,
+    network: u32,
+    utxo_root: Hash,
+}
+
+impl Serialize for Block {
+    fn serialize<S: Serializer> (&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Block", 11)?;
+        state.serialize_field("index", &self.header.index)?;
+        state.serialize_field("timestamp", &self.header.timestamp)?;
+        state.serialize_field("hash", &self.hash())?;
+        state.serialize_field("prev_block_hash", &self.header.prev_block_hash)?;
+        state.serialize_field("merkle_root", &self.header.merkle_root)?;
+        state.serialize_field("nonce", &self.header.nonce)?;
+        state.serialize_field("transactions", &self.transactions)?;
+        state.serialize_field("difficulty", &self.header.difficulty)?;
+        state.serialize_field("version", &self.header.version)?;
+        state.serialize_field("network", &self.header.network)?;
+        state.serialize_field("utxo_root", &self.header.utxo_root)?;
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Block {
+    fn deserialize<D: Deserializer<'de>> (deserializer: D) -> Result<Self, D::Error> {
+        let stored = StoredBlock::deserialize(deserializer)?;
+
+        Ok(Block {
+            header: BlockHeader {
+                index: stored.index,
+                timestamp: stored.timestamp,
+                prev_block_hash: stored.prev_block_hash,
+                merkle_root: stored.merkle_root,
+                utxo_root: stored.utxo_root,
+                nonce: stored.nonce,
+                difficulty: stored.difficulty,
+                version: stored.version,
+                network: stored.network,
+            },
+            transactions: stored.transactions,
+        })
+    }
+}
+
+// The chain's difficulty is a u128 threshold on the hash's top 128 bits,
+// which any value can be, not just a power of two, so retargeting can move it
+// by less than a doubling, see difficulty_to_target()
 pub fn check_difficulty (hash: &Hash, difficulty: u128) -> bool {
-    difficulty > difficulty_bytes_as_u128(&hash)
+    target::meets_target(hash, &target::difficulty_to_target(difficulty))
+}
+
+// Checks a header chain, e.g. from Blockchain::export_headers(), without any
+// transactions: it must start at `expected_genesis`, each header must follow
+// on from the one before it, and each must meet the difficulty it states
+// (whether that difficulty was the right one takes the full chain to tell).
+// On failure, returns the index of the first header that doesn't check out;
+// an empty chain fails at 0. SHA-256 chains only.
+pub fn verify_header_chain (headers: &[BlockHeader], expected_genesis: &Hash) -> Result<(), usize> {
+    match headers.first() {
+        Some(genesis) if genesis.hash() == *expected_genesis => (),
+        _ => return Err(0),
+    }
+
+    for (index, header) in headers.iter().enumerate() {
+        let linked = match index.checked_sub(1) {
+            Some(prev) => header.prev_block_hash == headers[prev].hash(),
+            None => header.prev_block_hash == Hash::zero(),
+        };
+
+        if header.index != index as u32 || !linked || !check_difficulty(&header.hash(), header.difficulty) {
+            return Err(index);
+        }
+    }
+
+    Ok(())
+}
+
+// Expected number of hashes needed to meet `difficulty`. A hash passes when its
+// top 128 bits are below the difficulty, so roughly 2^128 / difficulty tries.
+pub fn work (difficulty: u128) -> u128 {
+    u128::MAX / difficulty.max(1)
+}
+
+// The order the transactions after a block's coinbase must be in: any
+// transaction spending another's output comes after it, and otherwise lower
+// hashes come first. There is exactly one such order for a set of
+// transactions, so every miner orders the same set the same way.
+pub fn canonical_order (transactions: Vec<Transaction>, hasher: &dyn Hasher<Output = Hash>) -> Vec<Transaction> {
+    let mut transactions = transactions.into_iter().map(Some).collect::<Vec<Option<Transaction>>>();
+
+    canonical_positions(transactions.iter().flatten(), hasher)
+        .into_iter()
+        .filter_map(|position| transactions[position].take())
+        .collect()
+}
+
+// Positions of the transactions in canonical_order()
+pub(crate) fn canonical_positions<'a, I: Iterator<Item = &'a Transaction>> (transactions: I, hasher: &dyn Hasher<Output = Hash>) -> Vec<usize> {
+    let transactions = transactions.collect::<Vec<&Transaction>>();
+    let hashes = transactions
+        .iter()
+        .map(|transaction| transaction.hash_with(hasher))
+        .collect::<Vec<Hash>>();
+
+    // Txid -> position of the transaction
+    let creators = hashes
+        .iter()
+        .enumerate()
+        .map(|(position, hash)| (*hash, position))
+        .collect::<HashMap<Hash, usize>>();
+
+    let mut dependents = vec![vec![]; transactions.len()];
+    let mut unmet = vec![0; transactions.len()];
+    for (position, transaction) in transactions.iter().enumerate() {
+        let parents = transaction
+            .spent_outpoints()
+            .filter_map(|outpoint| creators.get(&outpoint.txid).cloned())
+            .filter(|&parent| parent != position)
+            .collect::<BTreeSet<usize>>();
+
+        unmet[position] = parents.len();
+        for parent in parents {
+            dependents[parent].push(position);
+        }
+    }
+
+    let mut ready = (0..transactions.len())
+        .filter(|&position| unmet[position] == 0)
+        .map(|position| (hashes[position], position))
+        .collect::<BTreeSet<(Hash, usize)>>();
+    let mut order = vec![];
+
+    while let Some(next) = ready.iter().next().cloned() {
+        ready.remove(&next);
+        order.push(next.1);
+
+        for &dependent in &dependents[next.1] {
+            unmet[dependent] -= 1;
+            if unmet[dependent] == 0 {
+                ready.insert((hashes[dependent], dependent));
+            }
+        }
+    }
+
+    // Transactions spending each other in a cycle are invalid anyway; they
+    // go last so every position still appears once
+    let mut cycle = (0..transactions.len())
+        .filter(|&position| unmet[position] > 0)
+        .map(|position| (hashes[position], position))
+        .collect::<Vec<(Hash, usize)>>();
+    cycle.sort_unstable();
+    order.extend(cycle.into_iter().map(|(_, position)| position));
+
+    order
 }
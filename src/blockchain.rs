@@ -1,98 +1,2453 @@
 use super::*;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::Arc;
+use crate::store::BlockStore;
+use crate::transaction::{OutPoint, Output, MAX_COINBASE_DATA};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error;
+use serde::ser::SerializeStruct;
 
-#[derive(Debug)]
+// `tx_index` is the position of the offending transaction within the block,
+// counting the coinbase as 0
+#[derive(Debug, Clone)]
 pub enum BlockValidationErr {
     InvalidHash,
-    InvalidInput,
+    // The header states a difficulty other than the one the chain expects
+    // of it on its branch
+    UnexpectedDifficulty,
+    InvalidMerkleRoot,
+    // Under ProofOfStake, the block's producer wasn't eligible for its slot
+    IneligibleProducer,
+    // The input was already spent by an earlier transaction in the block
+    DoubleSpendWithinBlock { tx_index: usize },
+    // The input is neither in the UTXO set nor created earlier in the block
+    SpendOfUnknownOutput { tx_index: usize, outpoint: OutPoint },
+    // A transaction other than the coinbase spends nothing
+    NoInputs { tx_index: usize },
+    // The transaction lists the same input more than once
+    DuplicateInput { tx_index: usize },
+    // The transaction's outputs land on outpoints that are still unspent,
+    // which would overwrite them, e.g. a coinbase identical to an earlier one
+    OverwritesUnspentOutput { tx_index: usize },
+    TimestampTooFarInFuture,
+    // Stamped before the chain's min_timestamp
+    TimestampTooOld,
     MismatchedIndex,
     MismatchedPreviousHash,
-    AchronologicalTimestamp,
+    TimestampBeforeMedian,
+    // A side-branch block stamped no later than its parent
+    TimestampNotAfterParent,
     InvalidCoinbaseTransaction,
+    // The block has no transactions at all
+    MissingCoinbase,
+    // Coinbase data on a transaction other than the coinbase, or too much of it
+    InvalidCoinbaseData { tx_index: usize },
     InvalidGenesisBlockFormat,
-    InsufficientInputValue,
+    // The transaction's lock_height is above the block's index
+    TransactionNotYetValid { tx_index: usize },
+    InsufficientInputValue { tx_index: usize, shortfall: u64 },
+    // An output below the chain's dust threshold
+    DustOutput { tx_index: usize },
+    // More inputs or outputs than the chain allows a transaction
+    TooManyInputsOrOutputs { tx_index: usize },
+    InvalidSignature { tx_index: usize, outpoint: OutPoint },
+    // The input's witness doesn't satisfy the spent output's script
+    ScriptFailure { tx_index: usize, outpoint: OutPoint },
+    ImmatureCoinbaseSpend { tx_index: usize, outpoint: OutPoint },
+    BlockTooLarge,
+    DuplicateTransaction,
+    // The transactions after the coinbase aren't in canonical_order()
+    NonCanonicalOrdering,
+    // The chain's UTXO set doesn't match the one its blocks produce
+    UnspentOutputsMismatch,
+    // The header's utxo_root isn't the root of the UTXO set the block leaves
+    // behind
+    UtxoRootMismatch,
+    // Switching chains would roll back more blocks than max_reorg_depth, or
+    // blocks from_checkpoint() loaded
+    ReorgTooDeep,
+    // reorganize_to() was given a block that isn't the last of a known branch
+    UnknownBranchTip,
+    // reorganize_to() was given a branch whose blocks only passed the header
+    // checks, so switching to it could fail half way
+    UnvalidatedBranchTip,
+    // The block is at a checkpointed index but isn't the checkpointed block
+    CheckpointMismatch,
+    // The coinbase would mint more than the chain's max_supply allows
+    SupplyCapExceeded,
+    // A genesis block other than the one the chain was started with
+    GenesisMismatch,
+    // The block is for another network, e.g. a testnet block on mainnet
+    WrongNetwork,
+    // The block's version is below the minimum required at its height
+    UnsupportedVersion,
+    Overflow,
+}
+
+impl Display for BlockValidationErr {
+    fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            BlockValidationErr::InvalidHash => write!(f, "block hash does not meet the difficulty"),
+            BlockValidationErr::UnexpectedDifficulty => write!(f, "block states the wrong difficulty for its height"),
+            BlockValidationErr::IneligibleProducer => write!(f, "block producer is not eligible for the slot"),
+            BlockValidationErr::InvalidMerkleRoot => write!(f, "merkle root does not match the transactions"),
+            BlockValidationErr::DoubleSpendWithinBlock { tx_index } => write!(f,
+                "transaction {} spends an output already spent in the block", tx_index),
+            BlockValidationErr::SpendOfUnknownOutput { tx_index, outpoint } => write!(f,
+                "transaction {} spends {}, which is not an unspent output", tx_index, outpoint),
+            BlockValidationErr::NoInputs { tx_index } => write!(f,
+                "transaction {} has no inputs", tx_index),
+            BlockValidationErr::DuplicateInput { tx_index } => write!(f,
+                "transaction {} spends the same input twice", tx_index),
+            BlockValidationErr::OverwritesUnspentOutput { tx_index } => write!(f,
+                "transaction {} would overwrite outputs that are still unspent", tx_index),
+            BlockValidationErr::TimestampTooFarInFuture => write!(f, "timestamp is too far in the future"),
+            BlockValidationErr::TimestampTooOld => write!(f, "timestamp is before the chain's earliest allowed time"),
+            BlockValidationErr::MismatchedIndex => write!(f, "block index does not follow the chain tip"),
+            BlockValidationErr::MismatchedPreviousHash => write!(f, "previous block hash does not match the chain tip"),
+            BlockValidationErr::TimestampBeforeMedian => write!(f, "timestamp is not after the median of recent blocks"),
+            BlockValidationErr::TimestampNotAfterParent => write!(f, "timestamp is not after the parent block's"),
+            BlockValidationErr::InvalidCoinbaseTransaction => write!(f, "invalid coinbase transaction"),
+            BlockValidationErr::MissingCoinbase => write!(f, "block has no coinbase transaction"),
+            BlockValidationErr::InvalidCoinbaseData { tx_index } => write!(f,
+                "transaction {} has invalid coinbase data", tx_index),
+            BlockValidationErr::InvalidGenesisBlockFormat => write!(f, "invalid genesis block"),
+            BlockValidationErr::TransactionNotYetValid { tx_index } => write!(f,
+                "transaction {} is locked until a later block", tx_index),
+            BlockValidationErr::InsufficientInputValue { tx_index, shortfall } => write!(f,
+                "transaction {} spends {} more than its inputs are worth", tx_index, shortfall),
+            BlockValidationErr::DustOutput { tx_index } => write!(f,
+                "transaction {} creates an output below the dust threshold", tx_index),
+            BlockValidationErr::TooManyInputsOrOutputs { tx_index } => write!(f,
+                "transaction {} has too many inputs or outputs", tx_index),
+            BlockValidationErr::InvalidSignature { tx_index, outpoint } => write!(f,
+                "transaction {} has an invalid signature for input {}", tx_index, outpoint),
+            BlockValidationErr::ScriptFailure { tx_index, outpoint } => write!(f,
+                "transaction {} fails the script of input {}", tx_index, outpoint),
+            BlockValidationErr::ImmatureCoinbaseSpend { tx_index, outpoint } => write!(f,
+                "transaction {} spends immature coinbase output {}", tx_index, outpoint),
+            BlockValidationErr::BlockTooLarge => write!(f, "block has too many transactions"),
+            BlockValidationErr::DuplicateTransaction => write!(f, "block contains the same transaction twice"),
+            BlockValidationErr::NonCanonicalOrdering => write!(f, "block transactions are not in canonical order"),
+            BlockValidationErr::UnspentOutputsMismatch => write!(f, "unspent outputs don't match the blocks"),
+            BlockValidationErr::UtxoRootMismatch => write!(f, "utxo root does not match the outputs the block leaves unspent"),
+            BlockValidationErr::ReorgTooDeep => write!(f, "reorganization is deeper than allowed"),
+            BlockValidationErr::UnknownBranchTip => write!(f, "block is not the tip of a known branch"),
+            BlockValidationErr::UnvalidatedBranchTip => write!(f, "branch has not been fully validated"),
+            BlockValidationErr::CheckpointMismatch => write!(f, "block does not match the checkpoint at its index"),
+            BlockValidationErr::SupplyCapExceeded => write!(f, "coinbase exceeds the maximum coin supply"),
+            BlockValidationErr::GenesisMismatch => write!(f, "genesis block does not match the chain's"),
+            BlockValidationErr::WrongNetwork => write!(f, "block is for a different network"),
+            BlockValidationErr::UnsupportedVersion => write!(f, "block version is too old for its height"),
+            BlockValidationErr::Overflow => write!(f, "value overflow"),
+        }
+    }
+}
+
+impl error::Error for BlockValidationErr {}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Deserialize(bincode::Error),
+    InvalidBlock(BlockValidationErr),
+}
+
+#[derive(Debug)]
+pub enum MineBlockError {
+    Mining(MineError),
+    InvalidBlock(BlockValidationErr),
+}
+
+// What accepting a block did to the UTXO set, see accept_block(). Both lists
+// are sorted by outpoint.
+#[derive(Clone, Default)]
+pub struct BlockUpdate {
+    // Outputs that became unspent
+    pub created: Vec<(OutPoint, Output)>,
+    // Outputs that were unspent before and no longer are
+    pub spent: Vec<OutPoint>,
+    // The tip's index afterwards
+    pub height: u32,
+}
+
+// What reorganize_to() did to the active chain
+#[derive(Clone, Default)]
+pub struct ReorgResult {
+    // The blocks taken off the active chain, lowest first
+    pub disconnected: Vec<Block>,
+    // The target branch's blocks that replaced them, lowest first
+    pub connected: Vec<Block>,
+}
+
+// Why from_checkpoint() refused a checkpoint
+#[derive(Debug)]
+pub enum CheckpointError {
+    // The header doesn't meet the difficulty it states
+    InvalidHeader,
+    // The snapshot isn't the UTXO set the checkpoint commits to
+    CommitmentMismatch,
+    Overflow,
+}
+
+// Why set_difficulty() refused a difficulty
+#[derive(Debug)]
+pub enum DifficultyError {
+    // No hash is below a zero threshold, so no block could ever be mined
+    Zero,
+    // Easier than the chain's min_difficulty, which retargeting never goes past
+    EasierThanMinimum,
+}
+
+impl From<MineError> for MineBlockError {
+    fn from(err: MineError) -> Self {
+        MineBlockError::Mining(err)
+    }
+}
+
+impl From<BlockValidationErr> for MineBlockError {
+    fn from(err: BlockValidationErr) -> Self {
+        MineBlockError::InvalidBlock(err)
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> Self {
+        LoadError::Io(err)
+    }
+}
+
+impl From<bincode::Error> for LoadError {
+    fn from(err: bincode::Error) -> Self {
+        LoadError::Deserialize(err)
+    }
+}
+
+impl From<BlockValidationErr> for LoadError {
+    fn from(err: BlockValidationErr) -> Self {
+        LoadError::InvalidBlock(err)
+    }
+}
+
+// Consensus parameters a chain was created with. They are persisted along with
+// the blocks so that a loaded chain is replayed under the same rules.
+#[derive(Clone, Serialize, Deserialize)]
+struct ChainConfig {
+    difficulty: u128,
+    // What the genesis block is held to instead of `difficulty`, if set
+    genesis_difficulty: Option<u128>,
+    // Milliseconds, as are block timestamps
+    target_block_time: u64,
+    // 0 disables retargeting
+    retarget_interval: u32,
+    // The easiest difficulty, i.e. highest threshold, retargeting may reach
+    min_difficulty: u128,
+    // How far ahead of this node's clock a block timestamp may be, in ms
+    max_future_drift: u64,
+    // Earliest timestamp any block may have, in ms
+    min_timestamp: u128,
+    initial_reward: u64,
+    // 0 disables halving
+    halving_interval: u32,
+    // Blocks that must be mined on top of a coinbase before it can be spent
+    coinbase_maturity: u32,
+    // Including the coinbase
+    max_block_transactions: u32,
+    // Per transaction; the coinbase has its own output limit
+    max_inputs: u32,
+    max_outputs: u32,
+    max_coinbase_outputs: u32,
+    // Most coins that may ever be minted, fees excluded
+    max_supply: u64,
+    // Smallest output value a non-coinbase transaction may create
+    dust_threshold: u64,
+    // Most blocks switching branches may roll back from the tip
+    max_reorg_depth: u32,
+    // Block index -> the only hash a block at that index may have
+    checkpoints: HashMap<u32, Hash>,
+    // Set once the first genesis block is accepted and never changed after,
+    // so the chain, chains replayed from it and reloaded copies of it can't
+    // take on another network's genesis
+    genesis_hash: Option<Hash>,
+    // Whether to keep the transaction and spender indexes
+    indexing: bool,
+    // Network magic every block must carry
+    network: u32,
+    // Activation height -> minimum block version from that height on
+    min_versions: BTreeMap<u32, u32>,
+}
+
+#[derive(Deserialize)]
+struct StoredBlockchain {
+    config: ChainConfig,
+    blocks: Vec<Block>,
+}
+
+const DEFAULT_MAX_FUTURE_DRIFT: u64 = 2 * 60 * 60 * 1000;
+const DEFAULT_INITIAL_REWARD: u64 = 50;
+const DEFAULT_HALVING_INTERVAL: u32 = 210_000;
+pub const COINBASE_MATURITY: u32 = 100;
+const DEFAULT_MAX_BLOCK_TRANSACTIONS: u32 = 10_000;
+const DEFAULT_MAX_INPUTS: u32 = 1_000;
+const DEFAULT_MAX_OUTPUTS: u32 = 1_000;
+const DEFAULT_MAX_COINBASE_OUTPUTS: u32 = 100;
+const DEFAULT_MAX_SUPPLY: u64 = 21_000_000;
+const DEFAULT_DUST_THRESHOLD: u64 = 1;
+
+// Everything needed to reverse a block's effect on the chain state
+#[derive(Default)]
+struct BlockUndo {
+    // Outputs the block spent, with their coinbase height if they had one
+    spent: Vec<(OutPoint, Output, Option<u32>)>,
+    // Outputs the block added and didn't spend again
+    created: Vec<OutPoint>,
+    // The difficulty before the block was accepted
+    difficulty: u128,
+    // Coins the block's coinbase minted
+    minted: u64,
+    // The fee each transaction after the coinbase paid, in block order
+    fees: Vec<u64>,
+}
+
+// A block disconnect_tip() took off, with what it needs to go back on as
+// it was, see reconnect()
+struct DisconnectedBlock {
+    block: Block,
+    undo: BlockUndo,
+    // The outputs undo.created lists, with their coinbase heights
+    created: Vec<(OutPoint, Output, Option<u32>)>,
+}
+
+// What a block's transactions do to the UTXO set, see block_effects()
+struct BlockEffects {
+    // Outputs spent, whether they were unspent before the block or created
+    // earlier in it
+    spent: HashSet<OutPoint>,
+    // Outputs created and not spent again within the block
+    created: HashMap<OutPoint, Output>,
+    coinbase_outpoints: Vec<OutPoint>,
+    fees: Vec<u64>,
+    minted: u64,
 }
 
+// Number of recent blocks whose median timestamp a new block must exceed
+const MEDIAN_TIME_SPAN: usize = 11;
+
+// How many blocks import_blocks() validates between progress reports
+const IMPORT_PROGRESS_INTERVAL: usize = 100;
+
+// Most blocks add_block() holds on to while their parents are missing
+const MAX_ORPHANS: usize = 100;
+
+// Retargeting never moves the difficulty by more than this factor at once
+const MAX_RETARGET_FACTOR: u128 = 4;
+
+// See Blockchain::on_block_added() and Blockchain::on_reorg()
+type BlockAddedCallback = Box<dyn Fn(&Block) + Send + Sync>;
+type ReorgCallback = Box<dyn Fn(&[Block], &[Block]) + Send + Sync>;
+type Clock = Arc<dyn Fn() -> u128 + Send + Sync>;
+
 pub struct Blockchain {
-    pub blocks: Vec<Block>,
-    unspent_outputs: HashSet<Hash>,
-    difficulty: u128
+    // The blocks, the UTXO set and the block index are shared with any
+    // snapshot() still alive, and copied on the first write after one. Only
+    // the chunk of blocks written to is copied, see BlockStore.
+    blocks: BlockStore,
+    unspent_outputs: Arc<HashMap<OutPoint, Output>>,
+    // Unspent coinbase outpoint -> index of the block that created it
+    coinbase_heights: HashMap<OutPoint, u32>,
+    // Block hash -> block index
+    block_index: Arc<HashMap<Hash, usize>>,
+    // Transaction hash -> (block index, position within the block)
+    transaction_index: HashMap<Hash, (usize, usize)>,
+    // Spent outpoint -> (block index, position) of the transaction spending it
+    spender_index: HashMap<OutPoint, (usize, usize)>,
+    // Blocks below this index have had their transactions pruned
+    pruned: usize,
+    // Blocks below this index were loaded by from_checkpoint() and have no
+    // undo data, so can't be rolled back
+    fast_forwarded: usize,
+    // One entry per block, see rollback()
+    undo: Vec<BlockUndo>,
+    difficulty: u128,
+    // Sum of work() over the difficulty each block was accepted at
+    total_work: u128,
+    // Coinbase value minus fees, summed over every block
+    total_minted: u64,
+    // Blocks received ahead of their parent, keyed by the parent's hash
+    orphans: HashMap<Hash, Vec<Block>>,
+    // The active chain plus any side branches off it
+    tree: BlockTree,
+    // Headers accepted ahead of their blocks, continuing on from the tip
+    pending_headers: VecDeque<BlockHeader>,
+    block_added_callbacks: Vec<BlockAddedCallback>,
+    reorg_callbacks: Vec<ReorgCallback>,
+    config: ChainConfig,
+    // Shared so that chains replayed from this one follow the same rules
+    consensus: Arc<dyn Consensus>,
+    // Shared the same way, so a chain and the chains replayed from it never
+    // hash differently
+    hasher: Arc<dyn Hasher<Output = Hash>>,
+    clock: Clock,
 }
 
 impl Blockchain {
     pub fn new(difficulty: u128) -> Self {
-        Blockchain {
-            blocks: vec![],
-            unspent_outputs: HashSet::new(),
+        Blockchain::with_config(ChainConfig {
             difficulty,
+            genesis_difficulty: None,
+            target_block_time: 0,
+            retarget_interval: 0,
+            min_difficulty: difficulty,
+            max_future_drift: DEFAULT_MAX_FUTURE_DRIFT,
+            min_timestamp: 0,
+            initial_reward: DEFAULT_INITIAL_REWARD,
+            halving_interval: DEFAULT_HALVING_INTERVAL,
+            coinbase_maturity: COINBASE_MATURITY,
+            max_block_transactions: DEFAULT_MAX_BLOCK_TRANSACTIONS,
+            max_inputs: DEFAULT_MAX_INPUTS,
+            max_outputs: DEFAULT_MAX_OUTPUTS,
+            max_coinbase_outputs: DEFAULT_MAX_COINBASE_OUTPUTS,
+            max_supply: DEFAULT_MAX_SUPPLY,
+            dust_threshold: DEFAULT_DUST_THRESHOLD,
+            max_reorg_depth: u32::MAX,
+            checkpoints: HashMap::new(),
+            genesis_hash: None,
+            indexing: true,
+            network: MAINNET,
+            min_versions: BTreeMap::new(),
+        })
+    }
+
+    // A new chain seeded with `genesis`, e.g. one made by build_genesis()
+    pub fn with_genesis (difficulty: u128, genesis: Block) -> Result<Self, BlockValidationErr> {
+        let mut blockchain = Blockchain::new(difficulty);
+        blockchain.update_with_block(genesis)?;
+
+        Ok(blockchain)
+    }
+
+    // Builds a chain from blocks received from a peer, validating each one in
+    // turn. On failure, returns the position and error of the first bad block.
+    pub fn from_blocks (difficulty: u128, blocks: Vec<Block>) -> Result<Self, (usize, BlockValidationErr)> {
+        Blockchain::new(difficulty).with_blocks(blocks)
+    }
+
+    // Starts a chain at a trusted checkpoint instead of replaying every block
+    // from genesis: `header` is the checkpoint block's, and `utxo_snapshot`
+    // the UTXO set as of that block, which must match the utxo_root the
+    // header commits to, and `difficulty` is what the block after it must
+    // meet. Only the checkpoint's header is kept, as prune() leaves it; the
+    // blocks below it are unknown, so genesis() is None and blocks() starts
+    // at the checkpoint. New blocks then extend the checkpoint as usual. The
+    // snapshot doesn't say which outputs are coinbases, so all of them count
+    // as mature. SHA-256 chains only.
+    pub fn from_checkpoint (header: BlockHeader, mut utxo_snapshot: Vec<(OutPoint, Output)>,
+                            difficulty: u128) -> Result<Self, CheckpointError> {
+        let hash = header.hash();
+        if !block::check_difficulty(&hash, header.difficulty) {
+            return Err(CheckpointError::InvalidHeader);
+        }
+
+        let mut blockchain = Blockchain::new(difficulty);
+
+        utxo_snapshot.sort_unstable_by_key(|(outpoint, _)| *outpoint);
+        if snapshot_commitment(&utxo_snapshot, blockchain.hasher()) != header.utxo_root {
+            return Err(CheckpointError::CommitmentMismatch);
         }
+
+        // Fees only move coins, so everything minted is still unspent
+        blockchain.total_minted = utxo_snapshot
+            .iter()
+            .try_fold(0u64, |sum, (_, output)| sum.checked_add(output.value))
+            .ok_or(CheckpointError::Overflow)?;
+
+        // The work below the checkpoint is unknown, so it counts from there
+        let index = header.index as usize;
+        blockchain.total_work = block::work(header.difficulty);
+        blockchain.blocks = BlockStore::starting_at(index);
+        Arc::make_mut(&mut blockchain.block_index).insert(hash, index);
+        blockchain.tree.insert(hash, header, blockchain.total_work, None);
+        blockchain.undo.push(BlockUndo { difficulty: header.difficulty, ..BlockUndo::default() });
+        blockchain.blocks.push(Block { header, transactions: vec![] });
+
+        blockchain.unspent_outputs = Arc::new(utxo_snapshot.into_iter().collect());
+        blockchain.pruned = index + 1;
+        blockchain.fast_forwarded = index + 1;
+        blockchain.config.checkpoints.insert(header.index, hash);
+
+        Ok(blockchain)
     }
 
-    pub fn update_with_block (&mut self, block: Block) -> Result<(), BlockValidationErr> {
-        let i = self.blocks.len();
-        if block.index != i as u32 {
-            return Err(BlockValidationErr::MismatchedIndex);
-        } else if !block::check_difficulty(&block.hash(), self.difficulty) {
-            return Err(BlockValidationErr::InvalidHash);
-        } else if i != 0 {
-            // Not genesis block
-            let prev_block = &self.blocks[i - 1];
-            if block.timestamp <= prev_block.timestamp {
-                return Err(BlockValidationErr::AchronologicalTimestamp);
-            } else if block.prev_block_hash != prev_block.hash {
-                return Err(BlockValidationErr::InvalidHash);
-            }
-        } else {
-            // Genesis block
-            if block.prev_block_hash != vec![0; 32] {
-                return Err(BlockValidationErr::InvalidGenesisBlockFormat);
-            }
+    // Like from_blocks(), but under the rules this chain was configured with,
+    // e.g. Blockchain::new(difficulty).with_checkpoint(..).with_blocks(blocks)
+    pub fn with_blocks (mut self, blocks: Vec<Block>) -> Result<Self, (usize, BlockValidationErr)> {
+        for (index, block) in blocks.into_iter().enumerate() {
+            self.update_with_block(block).map_err(|err| (index, err))?;
         }
 
-        if let Some((coinbase, transactions)) = block.transactions.split_first() {
-            if !coinbase.is_coinbase() {
-                return Err(BlockValidationErr::InvalidCoinbaseTransaction);
+        Ok(self)
+    }
+
+    // Appends the blocks in order, e.g. for an initial sync, calling
+    // `progress(done, total)` every IMPORT_PROGRESS_INTERVAL blocks and once
+    // at the end. Stops at the first block that fails to validate, returning
+    // its position and error; the blocks before it stay imported.
+    pub fn import_blocks<F: FnMut(usize, usize)> (&mut self, blocks: impl IntoIterator<Item = Block>, mut progress: F) -> Result<usize, (usize, BlockValidationErr)> {
+        let blocks = blocks.into_iter().collect::<Vec<Block>>();
+        let total = blocks.len();
+
+        for (index, block) in blocks.into_iter().enumerate() {
+            self.update_with_block(block).map_err(|err| (index, err))?;
+
+            let done = index + 1;
+            if done.is_multiple_of(IMPORT_PROGRESS_INTERVAL) || done == total {
+                progress(done, total);
             }
+        }
 
-            let mut block_spent: HashSet<Hash> = HashSet::new();
-            let mut block_created: HashSet<Hash> = HashSet::new();
-            let mut total_fee: u64 = 0;
+        Ok(total)
+    }
 
-            for transaction in transactions {
-                let input_hashes = transaction.input_hashes();
-                let output_hashes = transaction.output_hashes();
+    fn with_config (config: ChainConfig) -> Self {
+        Blockchain {
+            blocks: BlockStore::default(),
+            unspent_outputs: Arc::new(HashMap::new()),
+            coinbase_heights: HashMap::new(),
+            block_index: Arc::new(HashMap::new()),
+            transaction_index: HashMap::new(),
+            spender_index: HashMap::new(),
+            pruned: 0,
+            fast_forwarded: 0,
+            undo: vec![],
+            difficulty: config.difficulty,
+            total_work: 0,
+            total_minted: 0,
+            orphans: HashMap::new(),
+            tree: BlockTree::default(),
+            pending_headers: VecDeque::new(),
+            block_added_callbacks: vec![],
+            reorg_callbacks: vec![],
+            config,
+            consensus: Arc::new(ProofOfWork),
+            hasher: Arc::new(Sha256),
+            clock: Arc::new(now),
+        }
+    }
 
-                if !(&input_hashes - &self.unspent_outputs).is_empty() ||
-                    !(&input_hashes & &block_spent).is_empty() {
-                    return Err(BlockValidationErr::InvalidInput);
-                }
+    // Defaults to ProofOfWork. Not persisted: a chain loaded from a file is
+    // validated with ProofOfWork.
+    pub fn with_consensus<C: Consensus + 'static> (mut self, consensus: C) -> Self {
+        self.consensus = Arc::new(consensus);
+        self
+    }
 
-                let input_value = transaction.input_value();
-                let output_value = transaction.output_value();
+    // Defaults to Sha256. Every block, transaction and output hash the chain
+    // works with comes from this one hasher, so it can only be chosen while
+    // the chain is empty; panics otherwise. Like the consensus engine it is
+    // not persisted, so a chain loaded from a file hashes with Sha256.
+    pub fn with_hasher<H: Hasher<Output = Hash> + 'static> (mut self, hasher: H) -> Self {
+        assert!(self.blocks.is_empty(), "a chain's hasher can't change once it has blocks");
 
-                if output_value > input_value {
-                    return Err(BlockValidationErr::InsufficientInputValue);
-                }
+        self.hasher = Arc::new(hasher);
+        self
+    }
+
+    // Where the chain gets the current time, in milliseconds since the Unix
+    // epoch, for the future-timestamp bound and for mine_next_block().
+    // Defaults to the system clock; a fixed clock makes the timestamp rules
+    // deterministic. Not persisted.
+    pub fn with_clock<F: Fn() -> u128 + Send + Sync + 'static> (mut self, clock: F) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    // Lets the genesis block be mined at a different (usually easier)
+    // difficulty than the one the chain starts running at
+    pub fn with_genesis_difficulty (mut self, genesis_difficulty: u128) -> Self {
+        self.config.genesis_difficulty = Some(genesis_difficulty);
+        self
+    }
+
+    // Recompute the difficulty every `retarget_interval` blocks so that blocks
+    // arrive roughly every `target_block_time` milliseconds
+    pub fn with_retargeting (mut self, target_block_time: u64, retarget_interval: u32) -> Self {
+        self.config.target_block_time = target_block_time;
+        self.config.retarget_interval = retarget_interval;
+        self
+    }
+
+    // Slow blocks ease the difficulty, and left unchecked a long enough run
+    // of them would ease it until any hash will do. Defaults to the
+    // difficulty the chain starts at, so mining never gets easier than that.
+    pub fn with_min_difficulty (mut self, min_difficulty: u128) -> Self {
+        self.config.min_difficulty = min_difficulty;
+        self
+    }
+
+    // Defaults to two hours
+    pub fn with_max_future_drift (mut self, max_future_drift: u64) -> Self {
+        self.config.max_future_drift = max_future_drift;
+        self
+    }
+
+    // Rejects blocks, the genesis block included, stamped before
+    // `min_timestamp`, e.g. the genesis block's own timestamp. Defaults to 0.
+    pub fn with_min_timestamp (mut self, min_timestamp: u128) -> Self {
+        self.config.min_timestamp = min_timestamp;
+        self
+    }
+
+    // Coinbase reward starts at `initial_reward` and halves every
+    // `halving_interval` blocks. Defaults to 50, halving every 210000 blocks.
+    pub fn with_block_reward (mut self, initial_reward: u64, halving_interval: u32) -> Self {
+        self.config.initial_reward = initial_reward;
+        self.config.halving_interval = halving_interval;
+        self
+    }
+
+    // On by default. The transaction and spender indexes behind
+    // find_transaction(), contains_transaction() and find_spender() hold an
+    // entry for every transaction and input ever mined, which an explorer
+    // needs but a node that only validates doesn't. With indexing off they
+    // aren't kept, so those lookups always come back empty (None or false);
+    // validation doesn't use them and is unaffected.
+    pub fn with_indexing (mut self, indexing: bool) -> Self {
+        self.config.indexing = indexing;
+        self
+    }
+
+    // Defaults to COINBASE_MATURITY
+    pub fn with_coinbase_maturity (mut self, coinbase_maturity: u32) -> Self {
+        self.config.coinbase_maturity = coinbase_maturity;
+        self
+    }
+
+    // Defaults to 10000, coinbase included
+    pub fn with_max_block_transactions (mut self, max_block_transactions: u32) -> Self {
+        self.config.max_block_transactions = max_block_transactions;
+        self
+    }
+
+    // Caps a transaction's inputs and outputs, keeping the cost of validating
+    // one bounded. Both default to 1000.
+    pub fn with_max_inputs_outputs (mut self, max_inputs: u32, max_outputs: u32) -> Self {
+        self.config.max_inputs = max_inputs;
+        self.config.max_outputs = max_outputs;
+        self
+    }
+
+    // Most outputs the coinbase may pay, e.g. 1 to only allow paying the
+    // miner. Defaults to 100. A coinbase must always have at least one.
+    pub fn with_max_coinbase_outputs (mut self, max_coinbase_outputs: u32) -> Self {
+        self.config.max_coinbase_outputs = max_coinbase_outputs;
+        self
+    }
+
+    // Defaults to MAINNET. The magic is part of each block's header, and blocks
+    // for any other network are refused, including when loading from a file.
+    pub fn with_network (mut self, network: u32) -> Self {
+        self.config.network = network;
+        self
+    }
+
+    // From block `height` on, blocks must have at least `version`, like a
+    // soft fork activating there. Blocks below the height are unaffected.
+    pub fn with_min_version (mut self, height: u32, version: u32) -> Self {
+        self.config.min_versions.insert(height, version);
+        self
+    }
+
+    // Defaults to 21 million. Blocks whose coinbase would take the total minted
+    // past this are rejected, whatever block_reward() allows.
+    pub fn with_max_supply (mut self, max_supply: u64) -> Self {
+        self.config.max_supply = max_supply;
+        self
+    }
+
+    // Non-coinbase outputs worth less than this are rejected. Defaults to 1;
+    // zero-value outputs are rejected whatever the threshold.
+    pub fn with_dust_threshold (mut self, dust_threshold: u64) -> Self {
+        self.config.dust_threshold = dust_threshold;
+        self
+    }
+
+    // Unlimited by default
+    pub fn with_max_reorg_depth (mut self, max_reorg_depth: u32) -> Self {
+        self.config.max_reorg_depth = max_reorg_depth;
+        self
+    }
+
+    // Pins the block at `index` to `hash`; any other block there is rejected
+    pub fn with_checkpoint (mut self, index: u32, hash: Hash) -> Self {
+        self.config.checkpoints.insert(index, hash);
+        self
+    }
+
+    // Called with each block update_with_block() accepts, once the chain has
+    // been updated. Callbacks run synchronously; one that panics is skipped
+    // over without affecting the chain or the other callbacks.
+    pub fn on_block_added (&mut self, callback: BlockAddedCallback) {
+        self.block_added_callbacks.push(callback);
+    }
+
+    // Called when the chain switches branches, with the blocks it
+    // disconnected and then those it connected. on_block_added() callbacks
+    // don't fire for the connected blocks.
+    pub fn on_reorg (&mut self, callback: ReorgCallback) {
+        self.reorg_callbacks.push(callback);
+    }
+
+    // None for a chain from_checkpoint() started
+    pub fn genesis (&self) -> Option<&Block> {
+        self.blocks.get(0)
+    }
+
+    // The active chain's blocks from genesis to tip, or from the checkpoint
+    // for a chain from_checkpoint() started
+    pub fn blocks (&self) -> impl DoubleEndedIterator<Item = &Block> {
+        self.blocks.iter()
+    }
+
+    // blocks() from the one at `index` on
+    pub fn blocks_from (&self, index: u32) -> impl Iterator<Item = &Block> {
+        self.blocks.iter_from(index as usize)
+    }
+
+    // The active chain's block at `index`
+    pub fn block (&self, index: u32) -> Option<&Block> {
+        self.blocks.get(index as usize)
+    }
+
+    // Number of blocks on the active chain, one more than height()
+    pub fn block_count (&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn tip (&self) -> Option<&Block> {
+        self.blocks.last()
+    }
+
+    // The tip's index, one less than the number of blocks
+    pub fn height (&self) -> Option<u32> {
+        self.tip().map(|block| block.header.index)
+    }
+
+    // The zero hash on an empty chain, which is what a genesis block's
+    // prev_block_hash must be
+    pub fn last_hash (&self) -> Hash {
+        self.tip().map(|block| block.hash_with(self.hasher())).unwrap_or_else(Hash::zero)
+    }
+
+    // The network magic the chain's blocks carry, e.g. to check that a chain
+    // loaded from a file is for the expected network
+    pub fn network (&self) -> u32 {
+        self.config.network
+    }
+
+    pub fn hasher (&self) -> &dyn Hasher<Output = Hash> {
+        &*self.hasher
+    }
+
+    // The current time according to this chain's clock
+    pub fn now (&self) -> u128 {
+        (self.clock)()
+    }
+
+    // The item's hash under this chain's hasher
+    pub fn hash_of<T: Hashable> (&self, item: &T) -> Hash {
+        item.hash_with(self.hasher())
+    }
+
+    pub fn difficulty (&self) -> u128 {
+        self.difficulty
+    }
+
+    // The easiest difficulty retargeting may reach, see with_min_difficulty()
+    pub fn min_difficulty (&self) -> u128 {
+        self.config.min_difficulty
+    }
+
+    // Overrides the difficulty the next block must be mined at. For test
+    // harnesses and admin tools only: it bypasses retargeting, which will
+    // adjust from the new value at the next retarget boundary, and nodes
+    // that don't make the same call will reject the blocks. The difficulty
+    // is a threshold, so higher is easier. Zero accepts no hash and is
+    // refused, as is anything easier than with_min_difficulty() allows.
+    pub fn set_difficulty (&mut self, difficulty: u128) -> Result<(), DifficultyError> {
+        if difficulty == 0 {
+            return Err(DifficultyError::Zero);
+        } else if difficulty > self.config.min_difficulty {
+            return Err(DifficultyError::EasierThanMinimum);
+        }
+
+        self.difficulty = difficulty;
+        Ok(())
+    }
+
+    // The difficulty the block at `index` must be mined at: the genesis
+    // difficulty for block 0, otherwise the current difficulty, so only
+    // meaningful for the next block to come
+    pub fn expected_difficulty (&self, index: u32) -> u128 {
+        match self.config.genesis_difficulty {
+            Some(genesis_difficulty) if index == 0 => genesis_difficulty,
+            _ => self.difficulty,
+        }
+    }
+
+    // The difficulty `header` must state on the branch it builds on: the
+    // expected_difficulty() of its index if it extends the tip, otherwise
+    // the one its own branch retargets to, whether that continues on from
+    // the headers accept_header() took ahead of their blocks or is a side
+    // branch of the block tree
+    pub fn expected_difficulty_for (&self, header: &BlockHeader) -> u128 {
+        let prev_block_hash = header.prev_block_hash;
+
+        if header.index == 0 {
+            return self.expected_difficulty(0);
+        }
+
+        if let Some(parent) = self.pending_headers.back().filter(|parent| parent.hash_with(self.hasher()) == prev_block_hash) {
+            let held = self.blocks.len();
+            return self.difficulty_after(parent, |index| match (index as usize).checked_sub(held) {
+                Some(position) => self.pending_headers.get(position).cloned(),
+                None => self.blocks.get(index as usize).map(|block| block.header),
+            });
+        }
+
+        if self.blocks.is_empty() || prev_block_hash == self.last_hash() {
+            return self.expected_difficulty(header.index);
+        }
+
+        match self.tree.header(&prev_block_hash) {
+            Some(parent) => self.difficulty_after(parent, |index| self.branch_header(&prev_block_hash, index)),
+            None => self.expected_difficulty(header.index),
+        }
+    }
+
+    // The difficulty of a block building on `parent`, as retarget() would
+    // work it out had `parent` been the tip. `ancestor` looks up the
+    // headers of the parent's branch by index.
+    fn difficulty_after<F: Fn(u32) -> Option<BlockHeader>> (&self, parent: &BlockHeader, ancestor: F) -> u128 {
+        let difficulty = match self.config.genesis_difficulty {
+            Some(_) if parent.index == 0 => self.config.difficulty,
+            _ => parent.difficulty,
+        };
+        let interval = self.config.retarget_interval;
+        let height = parent.index + 1;
+
+        if interval == 0 || self.config.target_block_time == 0 || !height.is_multiple_of(interval) {
+            return difficulty;
+        }
 
-                let fee = input_value - output_value;
-                total_fee += fee;
+        match ancestor((height - interval).saturating_sub(1)) {
+            Some(first) => self.retargeted(difficulty, &first, parent),
+            None => difficulty,
+        }
+    }
 
-                block_spent.extend(input_hashes);
-                block_created.extend(output_hashes);
+    // The header at `index` on the branch leading up to the block `hash`,
+    // if the chain still has it
+    fn branch_header (&self, hash: &Hash, index: u32) -> Option<BlockHeader> {
+        let mut hash = *hash;
+
+        loop {
+            if let Some(&position) = self.block_index.get(&hash) {
+                // Back on the active chain
+                return self.blocks
+                    .get(index as usize)
+                    .filter(|_| index as usize <= position)
+                    .map(|block| block.header);
             }
 
-            if coinbase.output_value() < total_fee {
-                return Err(BlockValidationErr::InvalidCoinbaseTransaction);
-            } else {
-                block_created.extend(coinbase.output_hashes());
+            let header = self.tree.header(&hash)?;
+            if header.index <= index {
+                return Some(*header).filter(|header| header.index == index);
             }
 
-            self.unspent_outputs.retain(|output| !block_spent.contains(output));
-            self.unspent_outputs.extend(block_created);
+            hash = header.prev_block_hash;
         }
+    }
 
-        self.blocks.push(block);
+    // The current difficulty as a 256-bit target, see difficulty_to_target()
+    pub fn target (&self) -> [u8; 32] {
+        target::difficulty_to_target(self.difficulty)
+    }
+
+    // The current target in compact form. Lossy, as the u128 difficulty has
+    // more precision than compact bits can hold.
+    pub fn bits (&self) -> u32 {
+        target::target_to_compact(&self.target())
+    }
+
+    pub fn total_work (&self) -> u128 {
+        self.total_work
+    }
+
+    pub fn total_minted (&self) -> u64 {
+        self.total_minted
+    }
+
+    // Replays the blocks on top of an empty chain so every one of them passes
+    // through the same validation as a freshly received block
+    fn replay (mut self, blocks: Vec<Block>) -> Result<Self, BlockValidationErr> {
+        for block in blocks {
+            self.connect_block(block)?;
+        }
+
+        Ok(self)
+    }
+
+    // An empty chain with the same rules as this one
+    fn empty_like (&self) -> Self {
+        let mut blockchain = Blockchain::with_config(self.config.clone());
+        blockchain.consensus = Arc::clone(&self.consensus);
+        blockchain.hasher = Arc::clone(&self.hasher);
+        blockchain.clock = Arc::clone(&self.clock);
+        blockchain
+    }
+
+    // Re-validates every block from scratch without touching this chain, and
+    // checks that replaying them yields the UTXO set this chain holds. On
+    // failure, returns the index of the first bad block (or of the tip, if
+    // only the UTXO set is off) and its error. Pruned blocks can't be
+    // re-validated, so this fails on a pruned chain.
+    pub fn verify (&self) -> Result<(), (usize, BlockValidationErr)> {
+        let scratch = self.rebuilt()?;
+
+        if scratch.unspent_outputs != self.unspent_outputs || scratch.coinbase_heights != self.coinbase_heights {
+            return Err((self.blocks.len().saturating_sub(1), BlockValidationErr::UnspentOutputsMismatch));
+        }
+
+        Ok(())
+    }
+
+    // Throws away the UTXO set and rebuilds it by replaying every block from
+    // genesis, for repairing a set suspected to have drifted. Nothing changes
+    // if a block fails to validate; its index and error are returned. Like
+    // verify(), this fails on a pruned chain.
+    pub fn rebuild_utxo_set (&mut self) -> Result<(), (usize, BlockValidationErr)> {
+        let scratch = self.rebuilt()?;
+
+        self.unspent_outputs = scratch.unspent_outputs;
+        self.coinbase_heights = scratch.coinbase_heights;
+        self.undo = scratch.undo;
+        self.total_minted = scratch.total_minted;
 
         Ok(())
     }
+
+    // This chain's blocks replayed onto an empty chain with the same rules
+    fn rebuilt (&self) -> Result<Self, (usize, BlockValidationErr)> {
+        let mut scratch = self.empty_like();
+
+        for block in self.blocks.iter() {
+            scratch.connect_block(block.clone()).map_err(|err| (block.header.index as usize, err))?;
+        }
+
+        Ok(scratch)
+    }
+
+    // Switches to `candidate`, a chain from genesis, if it is valid under
+    // this chain's rules and has more cumulative work than the current one.
+    // Only the blocks after the fork point are validated, see reorganize(),
+    // so on error this chain is left as it was. Candidates with a different
+    // genesis block are refused with GenesisMismatch, and ones forking off
+    // more than max_reorg_depth blocks below the tip with ReorgTooDeep.
+    pub fn try_replace (&mut self, mut candidate: Vec<Block>) -> Result<bool, BlockValidationErr> {
+        if let (Some(ours), Some(theirs)) = (self.config.genesis_hash, candidate.first()) {
+            if ours != theirs.hash_with(self.hasher()) {
+                return Err(BlockValidationErr::GenesisMismatch);
+            }
+        }
+
+        // Blocks below the first one held can't be compared, but the first
+        // one held commits to them
+        let shared = candidate
+            .iter()
+            .enumerate()
+            .take_while(|(index, theirs)| match self.blocks.get(*index) {
+                Some(ours) => ours.hash_with(self.hasher()) == theirs.hash_with(self.hasher()),
+                None => *index < self.blocks.base(),
+            })
+            .count();
+        let branch = candidate.split_off(shared);
+
+        if branch.is_empty() {
+            return Ok(false);
+        } else if self.blocks.len().saturating_sub(shared) > self.config.max_reorg_depth as usize || shared < self.fast_forwarded {
+            return Err(BlockValidationErr::ReorgTooDeep);
+        }
+
+        // Every block's stated difficulty is checked as it connects, so the
+        // work can be compared before anything is rolled back
+        let replaced_work = self.blocks
+            .iter_from(shared)
+            .fold(0, |work: u128, block| work.saturating_add(block::work(block.header.difficulty)));
+        let branch_work = branch
+            .iter()
+            .fold(0, |work: u128, block| work.saturating_add(block::work(block.header.difficulty)));
+
+        if branch_work <= replaced_work {
+            return Ok(false);
+        }
+
+        self.reorganize(branch).map_err(|(_, err)| err)?;
+        Ok(true)
+    }
+
+    // Makes the branch ending in `tip_hash` the active chain whether or not
+    // it has more work, e.g. to test reorganizations or to back out of a
+    // branch by hand. The branch is validated as it connects, see
+    // reorganize(), so on error this chain is left as it was. Refuses a
+    // block that isn't the last of a branch in the block tree with
+    // UnknownBranchTip, and, before touching the chain, a branch whose blocks
+    // were never connected in full with UnvalidatedBranchTip. Respects
+    // max_reorg_depth like try_replace().
+    // Switching to a branch with less work doesn't stick: the next block on
+    // the side branch with the most work switches back.
+    pub fn reorganize_to (&mut self, tip_hash: &Hash) -> Result<ReorgResult, BlockValidationErr> {
+        if !self.blocks.is_empty() && *tip_hash == self.last_hash() {
+            return Ok(ReorgResult::default());
+        } else if !self.tree.tips().contains(tip_hash) {
+            return Err(BlockValidationErr::UnknownBranchTip);
+        } else if !self.tree.is_validated(tip_hash) {
+            return Err(BlockValidationErr::UnvalidatedBranchTip);
+        }
+
+        let branch = self.tree.side_branch(tip_hash).ok_or(BlockValidationErr::UnknownBranchTip)?;
+        self.reorganize(branch).map_err(|(_, err)| err)
+    }
+
+    // Switches to `branch`, blocks continuing on from some block of the
+    // active chain: rolls back to that block with the undo data, which works
+    // on pruned blocks too, then connects the branch's blocks, validating
+    // each in full. If one doesn't validate, those connected so far are
+    // rolled back and the old blocks put back, leaving the chain as it was.
+    // The error comes with the hash of the block that failed, None if the
+    // switch was refused before any block was checked.
+    fn reorganize (&mut self, branch: Vec<Block>) -> Result<ReorgResult, (Option<Hash>, BlockValidationErr)> {
+        let fork = match branch.first() {
+            Some(first) => first.header.index as usize,
+            None => return Ok(ReorgResult::default()),
+        };
+
+        if self.blocks.len().saturating_sub(fork) > self.config.max_reorg_depth as usize || fork < self.fast_forwarded {
+            return Err((None, BlockValidationErr::ReorgTooDeep));
+        } else if fork > self.blocks.len() {
+            return Err((None, BlockValidationErr::MismatchedIndex));
+        }
+
+        let pruned = self.pruned;
+        let mut disconnected = vec![];
+        while self.blocks.len() > fork {
+            disconnected.push(self.disconnect_tip());
+        }
+
+        for block in branch {
+            let hash = block.hash_with(self.hasher());
+            if let Err(err) = self.apply_block(block) {
+                while self.blocks.len() > fork {
+                    let block = self.disconnect_tip().block;
+                    self.tree.detach(&block.hash_with(self.hasher()), block);
+                }
+                for block in disconnected.into_iter().rev() {
+                    self.reconnect(block);
+                }
+                self.pruned = pruned;
+
+                return Err((Some(hash), err));
+            }
+        }
+
+        let disconnected = disconnected
+            .into_iter()
+            .rev()
+            .map(|disconnected| disconnected.block)
+            .collect::<Vec<Block>>();
+        for block in &disconnected {
+            self.tree.detach(&block.hash_with(self.hasher()), block.clone());
+        }
+
+        let connected = self.blocks.iter_from(fork).cloned().collect::<Vec<Block>>();
+        for callback in &self.reorg_callbacks {
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| callback(&disconnected, &connected)));
+        }
+
+        Ok(ReorgResult { disconnected, connected })
+    }
+
+    pub fn save_to_file (&self, path: &Path) -> io::Result<()> {
+        let bytes = bincode::serialize(self)
+            .map_err(io::Error::other)?;
+
+        fs::write(path, bytes)
+    }
+
+    pub fn load_from_file (path: &Path) -> Result<Self, LoadError> {
+        let bytes = fs::read(path)?;
+        let stored: StoredBlockchain = bincode::deserialize(&bytes)?;
+
+        Ok(Blockchain::with_config(stored.config).replay(stored.blocks)?)
+    }
+
+    // Whether the output may be spent by a transaction in block `height`.
+    // Only coinbase outputs have to wait.
+    pub(crate) fn is_mature (&self, outpoint: &OutPoint, height: u32) -> bool {
+        match self.coinbase_heights.get(outpoint) {
+            Some(created) => height - created >= self.config.coinbase_maturity,
+            None => true,
+        }
+    }
+
+    // Whether a non-coinbase transaction has more inputs or outputs than allowed
+    pub(crate) fn has_too_many_inputs_or_outputs (&self, transaction: &Transaction) -> bool {
+        transaction.inputs.len() > self.config.max_inputs as usize
+            || transaction.outputs.len() > self.config.max_outputs as usize
+    }
+
+    pub(crate) fn is_dust (&self, output: &Output) -> bool {
+        output.value == 0 || output.value < self.config.dust_threshold
+    }
+
+    pub(crate) fn unspent_output (&self, outpoint: &OutPoint) -> Option<&Output> {
+        self.unspent_outputs.get(outpoint)
+    }
+
+    // The undo data of the block at `index`; there is one entry per block
+    // held, see BlockStore::base()
+    fn undo_at (&self, index: usize) -> Option<&BlockUndo> {
+        self.undo.get(index.checked_sub(self.blocks.base())?)
+    }
+
+    // The output the block at `index` spent at `outpoint`, if it was unspent
+    // before the block, as its undo data recorded it
+    pub(crate) fn spent_output (&self, index: u32, outpoint: &OutPoint) -> Option<&Output> {
+        self.undo_at(index as usize)?
+            .spent
+            .iter()
+            .find(|(spent, _, _)| spent == outpoint)
+            .map(|(_, output, _)| output)
+    }
+
+    // Drops the transactions of all but the last `keep_last` blocks. Headers
+    // and the UTXO set are kept, so new blocks validate as before, but
+    // find_transaction() no longer finds transactions in pruned blocks, and a
+    // pruned chain can't be serialized since it can no longer be replayed.
+    pub fn prune (&mut self, keep_last: usize) {
+        let prune_to = self.blocks.len().saturating_sub(keep_last);
+
+        for index in self.pruned..prune_to {
+            if let Some(block) = self.blocks.get_mut(index) {
+                block.transactions = vec![];
+            }
+        }
+
+        self.pruned = self.pruned.max(prune_to);
+        let pruned = self.pruned;
+        self.transaction_index.retain(|_, &mut (block, _)| block >= pruned);
+        self.spender_index.retain(|_, &mut (block, _)| block >= pruned);
+    }
+
+    // Whether prune() has dropped any block's transactions
+    pub fn is_pruned (&self) -> bool {
+        self.pruned > 0
+    }
+
+    // From the tip back to genesis, e.g. iter_rev().take(n) for the last n
+    // blocks
+    pub fn iter_rev (&self) -> impl Iterator<Item = &Block> {
+        self.blocks.iter().rev()
+    }
+
+    // Every transaction in chain order, paired with the block containing it
+    pub fn transactions (&self) -> impl Iterator<Item = (&Block, &Transaction)> {
+        self.blocks
+            .iter()
+            .flat_map(|block| block.transactions.iter().map(move |transaction| (block, transaction)))
+    }
+
+    // The most a coinbase at `height` may mint on top of the block's fees
+    pub fn block_reward (&self, height: u32) -> u64 {
+        if self.config.halving_interval == 0 {
+            return self.config.initial_reward;
+        }
+
+        let halvings = height / self.config.halving_interval;
+        self.config.initial_reward.checked_shr(halvings).unwrap_or(0)
+    }
+
+    pub fn get_block_by_hash (&self, hash: &Hash) -> Option<&Block> {
+        self.block_index
+            .get(hash)
+            .map(|&index| &self.blocks[index])
+    }
+
+    // Hashes of the tip and of blocks exponentially further back from it (tip,
+    // tip-1, tip-2, tip-4, tip-8, ...), always ending with genesis, or with
+    // the checkpoint for a chain from_checkpoint() started. A peer can work
+    // out where its chain diverges from this one with find_fork_point()
+    // without being sent every hash.
+    pub fn block_locator (&self) -> Vec<Hash> {
+        let mut locator = vec![];
+        let mut step = 1;
+        let mut index = match self.blocks.len().checked_sub(1) {
+            Some(tip) => tip,
+            None => return locator,
+        };
+
+        loop {
+            locator.push(self.blocks[index].hash_with(self.hasher()));
+
+            if index == self.blocks.base() {
+                break;
+            }
+
+            index = index.saturating_sub(step).max(self.blocks.base());
+            if locator.len() > 1 {
+                step *= 2;
+            }
+        }
+
+        locator
+    }
+
+    // Index of the highest block in the locator that this chain also has
+    pub fn find_fork_point (&self, locator: &[Hash]) -> Option<u32> {
+        locator
+            .iter()
+            .filter_map(|hash| self.block_index.get(hash))
+            .max()
+            .map(|&index| index as u32)
+    }
+
+    // None if the transaction isn't in the chain, is in a pruned block, or
+    // indexing is off (see with_indexing())
+    pub fn find_transaction (&self, hash: &Hash) -> Option<(u32, &Transaction)> {
+        self.transaction_index
+            .get(hash)
+            .map(|&(block, position)| (block as u32, &self.blocks[block].transactions[position]))
+    }
+
+    // How many blocks deep the transaction is, counting the block containing
+    // it, so 1 in the tip. None whenever find_transaction() is.
+    pub fn confirmations (&self, tx_hash: &Hash) -> Option<u32> {
+        let (block, _) = self.find_transaction(tx_hash)?;
+
+        Some(self.height()? - block + 1)
+    }
+
+    pub fn utxo_count (&self) -> usize {
+        self.unspent_outputs.len()
+    }
+
+    // Every unspent output, sorted by outpoint so that chains with the same
+    // UTXO set give the same snapshot
+    pub fn utxo_snapshot (&self) -> Vec<(OutPoint, Output)> {
+        let mut snapshot = self.unspent_outputs
+            .iter()
+            .map(|(outpoint, output)| (*outpoint, output.clone()))
+            .collect::<Vec<(OutPoint, Output)>>();
+
+        snapshot.sort_unstable_by_key(|(outpoint, _)| *outpoint);
+        snapshot
+    }
+
+    // Digest of utxo_snapshot(), for comparing UTXO sets between nodes
+    pub fn utxo_set_hash (&self) -> Hash {
+        let mut bytes = vec![];
+
+        for (outpoint, output) in self.utxo_snapshot() {
+            bytes.extend(outpoint.bytes());
+            bytes.extend(output.bytes());
+        }
+
+        self.hasher.digest(&bytes)
+    }
+
+    // Merkle root over utxo_snapshot(), one leaf per (outpoint, output) entry.
+    // Every block's header commits to the root of the set it leaves behind,
+    // see BlockHeader::utxo_root, so a snapshot received from a peer can be
+    // checked against utxo_commitment_at() without replaying the blocks.
+    pub fn utxo_commitment (&self) -> Hash {
+        snapshot_commitment(&self.utxo_snapshot(), self.hasher())
+    }
+
+    // The utxo_root the block at `height` committed to
+    pub fn utxo_commitment_at (&self, height: u32) -> Option<Hash> {
+        self.block(height).map(|block| block.header.utxo_root)
+    }
+
+    // Whether `snapshot`, sorted as utxo_snapshot() sorts it, is exactly the
+    // UTXO set as of the block at `height`
+    pub fn verify_utxo_snapshot (&self, snapshot: &[(OutPoint, Output)], height: u32) -> bool {
+        self.utxo_commitment_at(height) == Some(snapshot_commitment(snapshot, self.hasher()))
+    }
+
+    pub fn is_unspent (&self, outpoint: &OutPoint) -> bool {
+        self.unspent_outputs.contains_key(outpoint)
+    }
+
+    // Like find_transaction(), false for pruned blocks and with indexing off
+    pub fn contains_transaction (&self, tx_hash: &Hash) -> bool {
+        self.transaction_index.contains_key(tx_hash)
+    }
+
+    // The block index and transaction that spent the output. None if it is
+    // unspent, unknown, was spent in a pruned block, or indexing is off.
+    pub fn find_spender (&self, outpoint: &OutPoint) -> Option<(u32, &Transaction)> {
+        self.spender_index
+            .get(outpoint)
+            .map(|&(block, position)| (block as u32, &self.blocks[block].transactions[position]))
+    }
+
+    // What an unconfirmed transaction would pay in fees, judged against the
+    // UTXO set. None if an input isn't unspent or the outputs are worth more
+    // than the inputs.
+    pub fn fee (&self, transaction: &Transaction) -> Option<u64> {
+        let input_value = transaction.spent_outpoints()
+            .try_fold(0u64, |sum, outpoint| sum.checked_add(self.unspent_output(outpoint)?.value))?;
+
+        input_value.checked_sub(transaction.output_value()?)
+    }
+
+    // The fee the transaction at `position` in the block at `index` paid, as
+    // update_with_block() worked it out when it accepted the block, so 0 for
+    // the coinbase. Every fee the chain reports comes from here. Still known
+    // once the block is pruned, but not for blocks from_checkpoint() loaded.
+    pub fn transaction_fee (&self, index: u32, position: usize) -> Option<u64> {
+        let index = index as usize;
+        if index < self.fast_forwarded {
+            return None;
+        }
+
+        let undo = self.undo_at(index)?;
+        match position.checked_sub(1) {
+            Some(position) => undo.fees.get(position).cloned(),
+            None => Some(0),
+        }
+    }
+
+    // Total fees paid by the transactions of the block at `index`, see
+    // transaction_fee()
+    pub fn block_fees (&self, index: u32) -> Option<u64> {
+        if (index as usize) < self.fast_forwarded {
+            return None;
+        }
+
+        self.undo_at(index as usize)?
+            .fees
+            .iter()
+            .try_fold(0u64, |total, fee| total.checked_add(*fee))
+    }
+
+    // fee() per serialized byte
+    pub fn fee_rate (&self, transaction: &Transaction) -> Option<f64> {
+        self.fee(transaction).map(|fee| transaction::fee_rate(fee, transaction.size()))
+    }
+
+    // A read-only view of the chain as it is now, see ChainSnapshot. Only
+    // bumps reference counts; the next block accepted copies what it changes
+    // instead, if the snapshot is still alive.
+    pub fn snapshot (&self) -> ChainSnapshot {
+        ChainSnapshot {
+            blocks: self.blocks.clone(),
+            unspent_outputs: Arc::clone(&self.unspent_outputs),
+            block_index: Arc::clone(&self.block_index),
+        }
+    }
+
+    pub fn get_balance (&self, address: &Address) -> u64 {
+        self.unspent_outputs
+            .values()
+            .filter(|output| &output.to_addr == address)
+            .map(|output| output.value)
+            .sum()
+    }
+
+    // Every unspent output paid to the address, sorted by outpoint
+    pub fn utxos_for (&self, address: &Address) -> Vec<(OutPoint, Output)> {
+        let mut utxos = self.unspent_outputs
+            .iter()
+            .filter(|(_, output)| &output.to_addr == address)
+            .map(|(outpoint, output)| (*outpoint, output.clone()))
+            .collect::<Vec<(OutPoint, Output)>>();
+
+        utxos.sort_unstable_by_key(|(outpoint, _)| *outpoint);
+        utxos
+    }
+
+    // Picks the address's outputs that are spendable in the next block,
+    // largest first, until they are worth at least `target`. None if all of
+    // them together aren't enough.
+    pub fn select_coins (&self, address: &Address, target: u64) -> Option<Vec<(OutPoint, Output)>> {
+        let height = self.blocks.len() as u32;
+        let mut utxos = self.utxos_for(address);
+        utxos.retain(|(outpoint, _)| self.is_mature(outpoint, height));
+        utxos.sort_by_key(|(_, output)| std::cmp::Reverse(output.value));
+
+        let mut selected = vec![];
+        let mut total: u64 = 0;
+
+        for utxo in utxos {
+            if total >= target {
+                break;
+            }
+
+            total = total.saturating_add(utxo.1.value);
+            selected.push(utxo);
+        }
+
+        if total >= target {
+            Some(selected)
+        } else {
+            None
+        }
+    }
+
+    // Runs the checks update_with_block() runs on each transaction after the
+    // coinbase, as if the transaction were alone in the next block, and
+    // returns its fee. Nothing is changed. Errors have a tx_index of 0.
+    pub fn validate_transaction (&self, transaction: &Transaction) -> Result<u64, BlockValidationErr> {
+        self.check_transaction(transaction, 0, self.blocks.len() as u32, &HashSet::new(), &HashMap::new())
+    }
+
+    // The tree of every block known, see BlockTree
+    pub fn block_tree (&self) -> &BlockTree {
+        &self.tree
+    }
+
+    // Appends the block if it extends the tip. A block building on some
+    // other known block instead goes on a side branch, after the checks
+    // accept_header() would give its header, against its own branch's
+    // difficulty; if that branch then has more work than the active chain,
+    // the chain rolls back to where it forks off and connects its blocks,
+    // validating each in full. A side block that turns out invalid is kept
+    // as such with its descendants and its error returned, then and for it
+    // or any child of it later. Other blocks already in the tree are ignored.
+    pub fn update_with_block (&mut self, block: Block) -> Result<(), BlockValidationErr> {
+        let hash = block.hash_with(self.hasher());
+
+        if let Some(err) = self.tree.rejection(&hash) {
+            return Err(err.clone());
+        } else if self.tree.contains(&hash) {
+            return Ok(());
+        }
+
+        let index = block.header.index;
+        let transactions = block.transactions.len();
+        let result = self.insert_block(hash, block);
+
+        match &result {
+            Ok(()) => log_info!("accepted block {} at height {} with {} transaction(s)", hash, index, transactions),
+            Err(err) => log_warn!("rejected block {} at height {}: {:?}", hash, index, err),
+        }
+
+        result
+    }
+
+    // update_with_block() for a block not already in the tree
+    fn insert_block (&mut self, hash: Hash, block: Block) -> Result<(), BlockValidationErr> {
+        let prev_block_hash = block.header.prev_block_hash;
+
+        if let Some(err) = self.tree.rejection(&prev_block_hash) {
+            return Err(err.clone());
+        } else if self.blocks.is_empty() || prev_block_hash == self.last_hash() || !self.tree.contains(&prev_block_hash) {
+            return self.connect_block(block);
+        }
+
+        let parent = *self.tree.header(&prev_block_hash).unwrap();
+        if block.header.index != parent.index + 1 {
+            return Err(BlockValidationErr::MismatchedIndex);
+        } else if block.header.timestamp <= parent.timestamp {
+            return Err(BlockValidationErr::TimestampNotAfterParent);
+        } else if block.header.merkle_root != block.merkle_root_with(self.hasher()) {
+            return Err(BlockValidationErr::InvalidMerkleRoot);
+        }
+
+        self.check_timestamp(&block.header)?;
+        self.check_version(&block.header)?;
+        self.check_checkpoint(&block.header)?;
+        self.consensus.validate_header(&block.header, self)?;
+
+        let work = self.tree.cumulative_work(&prev_block_hash).unwrap()
+            .saturating_add(block::work(block.header.difficulty));
+        self.tree.insert(hash, block.header, work, Some(block));
+
+        if work <= self.total_work {
+            return Ok(());
+        }
+
+        // None if the branch leads back to a block the tree no longer knows
+        let branch = match self.tree.side_branch(&hash) {
+            Some(branch) => branch,
+            None => {
+                self.tree.remove(&hash);
+                return Err(BlockValidationErr::MismatchedPreviousHash);
+            },
+        };
+        // The block that failed stays known as invalid along with the rest
+        // of the branch built on it, so none of it is validated again
+        match self.reorganize(branch) {
+            Ok(_) => Ok(()),
+            Err((Some(failed), err)) => {
+                self.tree.invalidate(&failed, err.clone());
+                Err(err)
+            },
+            Err((None, err)) => {
+                self.tree.remove(&hash);
+                Err(err)
+            },
+        }
+    }
+
+    // update_with_block(), returning what the block did to the UTXO set so a
+    // wallet can follow it without rescanning. A block that was already known
+    // or only goes on a side branch changes nothing; one that makes the chain
+    // switch branches yields the net change across the switch.
+    pub fn accept_block (&mut self, block: Block) -> Result<BlockUpdate, BlockValidationErr> {
+        let hash = block.hash_with(self.hasher());
+
+        if self.tree.contains(&hash) {
+            return Ok(self.block_update(vec![], vec![]));
+        } else if self.blocks.is_empty() || block.header.prev_block_hash == self.last_hash() {
+            self.update_with_block(block)?;
+
+            let undo = self.undo.last().unwrap();
+            let created = undo.created
+                .iter()
+                .map(|outpoint| (*outpoint, self.unspent_outputs[outpoint].clone()))
+                .collect();
+            let spent = undo.spent.iter().map(|(outpoint, _, _)| *outpoint).collect();
+
+            return Ok(self.block_update(created, spent));
+        }
+
+        // Holding on to the old set makes the chain copy it before changing
+        // it, so it's only done when the block may cause a reorganization
+        let before = Arc::clone(&self.unspent_outputs);
+        self.update_with_block(block)?;
+
+        let created = self.unspent_outputs
+            .iter()
+            .filter(|(outpoint, _)| !before.contains_key(*outpoint))
+            .map(|(outpoint, output)| (*outpoint, output.clone()))
+            .collect();
+        let spent = before
+            .keys()
+            .filter(|outpoint| !self.unspent_outputs.contains_key(*outpoint))
+            .cloned()
+            .collect();
+
+        Ok(self.block_update(created, spent))
+    }
+
+    fn block_update (&self, mut created: Vec<(OutPoint, Output)>, mut spent: Vec<OutPoint>) -> BlockUpdate {
+        created.sort_unstable_by_key(|(outpoint, _)| *outpoint);
+        spent.sort_unstable();
+
+        BlockUpdate {
+            created,
+            spent,
+            height: self.blocks.len().saturating_sub(1) as u32,
+        }
+    }
+
+    fn connect_block (&mut self, block: Block) -> Result<(), BlockValidationErr> {
+        self.apply_block(block)?;
+
+        let block = self.blocks.last().unwrap();
+        for callback in &self.block_added_callbacks {
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| callback(block)));
+        }
+
+        Ok(())
+    }
+
+    // connect_block() without the on_block_added() callbacks
+    fn apply_block (&mut self, block: Block) -> Result<(), BlockValidationErr> {
+        let i = self.blocks.len();
+        if block.header.index != i as u32 {
+            return Err(BlockValidationErr::MismatchedIndex);
+        } else if i != 0 && block.header.prev_block_hash != self.last_hash() {
+            // Checked before the proof of work, so a block that doesn't build
+            // on the tip is never reported as failing the difficulty
+            return Err(BlockValidationErr::MismatchedPreviousHash);
+        } else if block.transactions.len() > self.config.max_block_transactions as usize {
+            return Err(BlockValidationErr::BlockTooLarge);
+        }
+
+        self.check_timestamp(&block.header)?;
+        self.check_version(&block.header)?;
+        self.check_checkpoint(&block.header)?;
+        self.consensus.validate(&block, self)?;
+
+        if block.header.merkle_root != block.merkle_root_with(self.hasher()) {
+            return Err(BlockValidationErr::InvalidMerkleRoot);
+        } else if i != 0 {
+            // Not genesis block
+            if block.header.timestamp <= self.median_time_past() {
+                return Err(BlockValidationErr::TimestampBeforeMedian);
+            }
+        } else {
+            // Genesis block
+            if block.header.prev_block_hash != Hash::zero() {
+                return Err(BlockValidationErr::InvalidGenesisBlockFormat);
+            }
+        }
+
+        let mut transaction_hashes = HashSet::new();
+        if !block.transactions.iter().all(|transaction| transaction_hashes.insert(transaction.hash_with(self.hasher()))) {
+            return Err(BlockValidationErr::DuplicateTransaction);
+        }
+
+        let positions = block::canonical_positions(block.transactions.iter().skip(1), self.hasher());
+        if positions.iter().enumerate().any(|(expected, &position)| position != expected) {
+            return Err(BlockValidationErr::NonCanonicalOrdering);
+        }
+
+        let effects = self.block_effects(&block)?;
+        if block.header.utxo_root != self.commitment_after(&effects) {
+            return Err(BlockValidationErr::UtxoRootMismatch);
+        }
+
+        let undo = BlockUndo {
+            spent: effects.spent
+                .iter()
+                .filter(|outpoint| self.unspent_outputs.contains_key(*outpoint))
+                .map(|outpoint| (
+                    *outpoint,
+                    self.unspent_outputs[outpoint].clone(),
+                    self.coinbase_heights.get(outpoint).cloned(),
+                ))
+                .collect(),
+            created: effects.created.keys().cloned().collect(),
+            difficulty: self.difficulty,
+            minted: effects.minted,
+            fees: effects.fees,
+        };
+
+        let unspent_outputs = Arc::make_mut(&mut self.unspent_outputs);
+        for outpoint in &effects.spent {
+            unspent_outputs.remove(outpoint);
+            self.coinbase_heights.remove(outpoint);
+        }
+        unspent_outputs.extend(effects.created);
+        self.coinbase_heights.extend(effects.coinbase_outpoints.into_iter().map(|outpoint| (outpoint, block.header.index)));
+
+        if self.config.indexing {
+            for (position, transaction) in block.transactions.iter().enumerate() {
+                self.transaction_index.insert(transaction.hash_with(self.hasher()), (i, position));
+                for outpoint in transaction.spent_outpoints() {
+                    self.spender_index.insert(*outpoint, (i, position));
+                }
+            }
+        }
+
+        let hash = block.hash_with(self.hasher());
+        if self.pending_headers.front().map(|header| header.hash_with(self.hasher())) == Some(hash) {
+            self.pending_headers.pop_front();
+        } else {
+            // The headers were for some other chain
+            self.pending_headers.clear();
+        }
+
+        Arc::make_mut(&mut self.block_index).insert(hash, i);
+        if i == 0 {
+            self.config.genesis_hash.get_or_insert(hash);
+        }
+        self.total_work = self.total_work.saturating_add(block::work(self.expected_difficulty(i as u32)));
+        self.tree.insert(hash, block.header, self.total_work, None);
+        self.blocks.push(block);
+        self.total_minted += undo.minted;
+        self.undo.push(undo);
+        self.retarget();
+
+        Ok(())
+    }
+
+    // Validates the block's transactions as the ones of the next block,
+    // working out what they would do to the UTXO set without changing it
+    fn block_effects (&self, block: &Block) -> Result<BlockEffects, BlockValidationErr> {
+        // A block with nothing else in it still needs its coinbase
+        let (coinbase, transactions) = match block.transactions.split_first() {
+            Some(split) => split,
+            None => return Err(BlockValidationErr::MissingCoinbase),
+        };
+
+        // The mark isn't taken on trust: a coinbase that also spent existing
+        // outputs would count their value twice
+        if !coinbase.is_coinbase() || !coinbase.inputs.is_empty() || coinbase.outputs.is_empty() {
+            return Err(BlockValidationErr::InvalidCoinbaseTransaction);
+        } else if coinbase.outputs.len() > self.config.max_coinbase_outputs as usize {
+            return Err(BlockValidationErr::TooManyInputsOrOutputs { tx_index: 0 });
+        } else if coinbase.coinbase_data.len() > MAX_COINBASE_DATA {
+            return Err(BlockValidationErr::InvalidCoinbaseData { tx_index: 0 });
+        }
+
+        // The coinbase's outputs are only added to block_created once the
+        // fees it collects are known, so nothing in its own block can spend
+        // them, whatever the coinbase maturity
+        let mut block_spent: HashSet<OutPoint> = HashSet::new();
+        let mut block_created: HashMap<OutPoint, Output> = HashMap::new();
+        let mut total_fee: u64 = 0;
+        let mut fees = vec![];
+
+        for (tx_index, transaction) in transactions.iter().enumerate() {
+            // Count the coinbase
+            let tx_index = tx_index + 1;
+
+            let fee = self.check_transaction(transaction, tx_index, block.header.index, &block_spent, &block_created)?;
+            log_debug!("transaction {} in block {} pays a fee of {}", tx_index, block.header.index, fee);
+            total_fee = total_fee.checked_add(fee)
+                .ok_or(BlockValidationErr::Overflow)?;
+            fees.push(fee);
+
+            self.track_block_spends(transaction, &mut block_spent, &mut block_created);
+        }
+
+        let coinbase_value = coinbase.output_value()
+            .ok_or(BlockValidationErr::Overflow)?;
+
+        let max_coinbase_value = self.block_reward(block.header.index).checked_add(total_fee)
+            .ok_or(BlockValidationErr::Overflow)?;
+
+        log_debug!("block {} collects {} in fees, its coinbase pays {} of at most {}",
+            block.header.index, total_fee, coinbase_value, max_coinbase_value);
+
+        let coinbase_outpoints = coinbase.outpoints_with(self.hasher());
+
+        if coinbase_value < total_fee || coinbase_value > max_coinbase_value {
+            return Err(BlockValidationErr::InvalidCoinbaseTransaction);
+        } else if self.overwrites_unspent(&coinbase_outpoints, &block_spent, &block_created) {
+            return Err(BlockValidationErr::OverwritesUnspentOutput { tx_index: 0 });
+        } else {
+            block_created.extend(coinbase_outpoints.iter().cloned().zip(coinbase.outputs.iter().cloned()));
+        }
+
+        // Fees only move existing coins, so just the rest counts as minted
+        let minted = coinbase_value - total_fee;
+        let total_minted = self.total_minted.checked_add(minted)
+            .ok_or(BlockValidationErr::Overflow)?;
+
+        if total_minted > self.config.max_supply {
+            return Err(BlockValidationErr::SupplyCapExceeded);
+        }
+
+        Ok(BlockEffects {
+            spent: block_spent,
+            created: block_created,
+            coinbase_outpoints,
+            fees,
+            minted,
+        })
+
+    }
+
+    // utxo_commitment() as it would be once `effects` were applied
+    fn commitment_after (&self, effects: &BlockEffects) -> Hash {
+        let mut snapshot = self.unspent_outputs
+            .iter()
+            .filter(|(outpoint, _)| !effects.spent.contains(*outpoint))
+            .chain(&effects.created)
+            .map(|(outpoint, output)| (*outpoint, output.clone()))
+            .collect::<Vec<(OutPoint, Output)>>();
+
+        snapshot.sort_unstable_by_key(|(outpoint, _)| *outpoint);
+        snapshot_commitment(&snapshot, self.hasher())
+    }
+
+    // The utxo_root a block with these transactions has to commit to for it
+    // to extend the tip, i.e. the root of the UTXO set they leave behind.
+    // Fails if they don't validate as the next block's. Set it before mining:
+    //
+    //     block.header.utxo_root = chain.utxo_root_for(&block)?;
+    //     block.mine(difficulty)?;
+    pub fn utxo_root_for (&self, block: &Block) -> Result<Hash, BlockValidationErr> {
+        Ok(self.commitment_after(&self.block_effects(block)?))
+    }
+
+    // Records what a transaction checked with check_transaction() spends and
+    // creates, for checking the block's later transactions
+    fn track_block_spends (&self, transaction: &Transaction, block_spent: &mut HashSet<OutPoint>, block_created: &mut HashMap<OutPoint, Output>) {
+        // An output created earlier in the block never reaches the UTXO set
+        // once spent. It is still remembered as spent, so spending it again
+        // is a double spend rather than a spend of an unknown output.
+        for outpoint in transaction.spent_outpoints() {
+            block_created.remove(outpoint);
+            block_spent.insert(*outpoint);
+        }
+
+        let outpoints = transaction.outpoints_with(self.hasher());
+        block_created.extend(outpoints.into_iter().zip(transaction.outputs.iter().cloned()));
+    }
+
+    // Whether any of `outpoints` is unspent as of the block's earlier
+    // transactions, so creating it again would overwrite it. Only an
+    // identical transaction has the same txid, so in practice this catches
+    // a coinbase repeating an earlier one.
+    fn overwrites_unspent (&self, outpoints: &[OutPoint], block_spent: &HashSet<OutPoint>, block_created: &HashMap<OutPoint, Output>) -> bool {
+        outpoints.iter().any(|outpoint| block_created.contains_key(outpoint)
+            || (self.unspent_outputs.contains_key(outpoint) && !block_spent.contains(outpoint)))
+    }
+
+    // Checks a non-coinbase transaction for a block at `height`, returning its
+    // fee. `block_spent` and `block_created` are the outputs the block's
+    // earlier transactions spent from the UTXO set and created.
+    fn check_transaction (&self, transaction: &Transaction, tx_index: usize, height: u32,
+                          block_spent: &HashSet<OutPoint>, block_created: &HashMap<OutPoint, Output>) -> Result<u64, BlockValidationErr> {
+        // Only the first transaction may mint coins. Without this, an empty
+        // input set would pass the input checks below vacuously.
+        if transaction.is_coinbase() {
+            return Err(BlockValidationErr::InvalidCoinbaseTransaction);
+        } else if transaction.inputs.is_empty() {
+            return Err(BlockValidationErr::NoInputs { tx_index });
+        } else if !transaction.coinbase_data.is_empty() {
+            return Err(BlockValidationErr::InvalidCoinbaseData { tx_index });
+        } else if self.has_too_many_inputs_or_outputs(transaction) {
+            return Err(BlockValidationErr::TooManyInputsOrOutputs { tx_index });
+        } else if transaction.lock_height > height {
+            return Err(BlockValidationErr::TransactionNotYetValid { tx_index });
+        } else if transaction.outputs.iter().any(|output| self.is_dust(output)) {
+            return Err(BlockValidationErr::DustOutput { tx_index });
+        }
+
+        // As Transaction::verify_structure() would have it
+        let mut distinct = HashSet::with_capacity(transaction.inputs.len());
+        if !transaction.spent_outpoints().all(|outpoint| distinct.insert(outpoint)) {
+            return Err(BlockValidationErr::DuplicateInput { tx_index });
+        }
+
+        // Inputs are resolved against the outputs of earlier transactions in
+        // the block, then the UTXO set as it was before this block, so the
+        // value comes from the chain rather than from the transaction itself.
+        // The coinbase's outputs only become spendable (once mature) after
+        // the block.
+        let mut owners = Vec::with_capacity(transaction.inputs.len());
+        let mut input_value: u64 = 0;
+        for &outpoint in transaction.spent_outpoints() {
+            let output = match block_created.get(&outpoint) {
+                Some(output) => output,
+                None if block_spent.contains(&outpoint) => return Err(BlockValidationErr::DoubleSpendWithinBlock { tx_index }),
+                None => self.unspent_outputs.get(&outpoint)
+                    .ok_or(BlockValidationErr::SpendOfUnknownOutput { tx_index, outpoint })?,
+            };
+
+            if !self.is_mature(&outpoint, height) {
+                return Err(BlockValidationErr::ImmatureCoinbaseSpend { tx_index, outpoint });
+            }
+
+            input_value = input_value.checked_add(output.value)
+                .ok_or(BlockValidationErr::Overflow)?;
+            owners.push(output);
+        }
+
+        for (input, owner) in transaction.inputs.iter().zip(owners) {
+            let outpoint = input.previous_output;
+
+            if !transaction.verify_input(input, owner) {
+                return Err(if owner.script.is_empty() {
+                    BlockValidationErr::InvalidSignature { tx_index, outpoint }
+                } else {
+                    BlockValidationErr::ScriptFailure { tx_index, outpoint }
+                });
+            }
+        }
+
+        if self.overwrites_unspent(&transaction.outpoints_with(self.hasher()), block_spent, block_created) {
+            return Err(BlockValidationErr::OverwritesUnspentOutput { tx_index });
+        }
+
+        let output_value = transaction.output_value()
+            .ok_or(BlockValidationErr::Overflow)?;
+
+        if output_value > input_value {
+            return Err(BlockValidationErr::InsufficientInputValue {
+                tx_index,
+                shortfall: output_value - input_value,
+            });
+        }
+
+        Ok(input_value - output_value)
+    }
+
+    // Assembles the next block from the best paying mempool transactions
+    // that are still valid, pays the block reward plus their fees to
+    // `miner`, mines it at the expected difficulty and appends it, then drops
+    // what it mined from `mempool`. Returns the new block's hash. Mines by
+    // proof of work whatever the consensus engine, so it is only useful with
+    // ProofOfWork.
+    pub fn mine_next_block (&mut self, miner: &Address, mempool: &mut Mempool) -> Result<Hash, MineBlockError> {
+        let index = self.blocks.len() as u32;
+        let mut fees: u64 = 0;
+        let mut transactions = vec![];
+        let mut block_spent = HashSet::new();
+        let mut block_created = HashMap::new();
+
+        // The selection puts parents before the children spending their
+        // outputs, so each is checked as part of the block so far
+        for transaction in mempool.select_for_block(self.config.max_block_transactions.saturating_sub(1) as usize) {
+            // The mempool may still hold transactions that have since been
+            // mined or had their inputs spent
+            let tx_index = transactions.len() + 1;
+            if let Ok(fee) = self.check_transaction(&transaction, tx_index, index, &block_spent, &block_created) {
+                fees = fees.checked_add(fee).ok_or(BlockValidationErr::Overflow)?;
+                self.track_block_spends(&transaction, &mut block_spent, &mut block_created);
+                transactions.push(transaction);
+            }
+        }
+
+        let reward = self.block_reward(index).min(self.config.max_supply.saturating_sub(self.total_minted));
+        let coinbase = Transaction {
+            inputs: vec![],
+            outputs: vec![Output {
+                to_addr: *miner,
+                value: reward.checked_add(fees).ok_or(BlockValidationErr::Overflow)?,
+                script: vec![],
+            }],
+            coinbase: true,
+            // The height keeps the coinbase apart from earlier ones paying
+            // the same miner the same amount, which would have the same txid
+            coinbase_data: u32_bytes(&index).to_vec(),
+            lock_height: 0,
+        };
+
+        let mut block_transactions = vec![coinbase];
+        block_transactions.extend(block::canonical_order(transactions, self.hasher()));
+
+        let timestamp = if self.blocks.is_empty() {
+            self.now()
+        } else {
+            self.now().max(self.median_time_past() + 1)
+        };
+        let difficulty = self.expected_difficulty(index);
+        let mut block = Block::new(index, timestamp, self.last_hash(), block_transactions, difficulty);
+        block.header.network = self.config.network;
+        block.header.utxo_root = self.utxo_root_for(&block)?;
+        block.mine_with(difficulty, self.hasher())?;
+
+        let hash = self.hash_of(&block);
+        self.update_with_block(block)?;
+        mempool.remove_confirmed(&self.blocks[index as usize]);
+
+        Ok(hash)
+    }
+
+    // Like update_with_block(), but a block whose parent isn't known yet is
+    // kept until it arrives instead of being rejected, once it passes the
+    // checks that don't need the parent, see check_orphan(). Returns whether the
+    // block was newly connected; once it is, any orphans waiting on it are
+    // connected too, and those that turn out invalid are dropped. Blocks
+    // already known, see has_block(), are ignored without being validated
+    // again, and blocks already found invalid get the same error again.
+    pub fn add_block (&mut self, block: Block) -> Result<bool, BlockValidationErr> {
+        if let Some(err) = self.tree.rejection(&block.hash_with(self.hasher())) {
+            return Err(err.clone());
+        } else if self.has_block(&block.hash_with(self.hasher())) {
+            return Ok(false);
+        }
+
+        if block.header.index != 0 && !self.tree.contains(&block.header.prev_block_hash) {
+            self.check_orphan(&block)?;
+            self.add_orphan(block);
+            return Ok(false);
+        }
+
+        let mut parents = vec![block.hash_with(self.hasher())];
+        self.update_with_block(block)?;
+
+        while let Some(parent) = parents.pop() {
+            for child in self.orphans.remove(&parent).unwrap_or_default() {
+                let hash = child.hash_with(self.hasher());
+                if self.update_with_block(child).is_ok() {
+                    parents.push(hash);
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    // Whether the block is on the active chain, on a side branch or waiting
+    // in the orphan pool. Pruned blocks are still known, blocks found invalid
+    // aren't.
+    pub fn has_block (&self, hash: &Hash) -> bool {
+        (self.tree.contains(hash) && self.tree.rejection(hash).is_none()) || self.orphans
+            .values()
+            .flatten()
+            .any(|orphan| orphan.hash_with(self.hasher()) == *hash)
+    }
+
+    pub fn orphan_count (&self) -> usize {
+        self.orphans.values().map(|children| children.len()).sum()
+    }
+
+    // What a block is checked for before it goes in the orphan pool: all
+    // that can be without its parent, the work its header states included,
+    // so the pool can't be filled for free
+    fn check_orphan (&self, block: &Block) -> Result<(), BlockValidationErr> {
+        if block.transactions.len() > self.config.max_block_transactions as usize {
+            return Err(BlockValidationErr::BlockTooLarge);
+        } else if block.header.merkle_root != block.merkle_root_with(self.hasher()) {
+            return Err(BlockValidationErr::InvalidMerkleRoot);
+        }
+
+        self.check_timestamp(&block.header)?;
+        self.check_version(&block.header)?;
+        self.check_checkpoint(&block.header)?;
+        self.consensus.validate_orphan_header(&block.header, self)
+    }
+
+    // Once the pool is full, the orphan furthest ahead of the tip goes first,
+    // as it is the least likely to connect soon
+    fn add_orphan (&mut self, block: Block) {
+        let hash = block.hash_with(self.hasher());
+        let known = self.orphans
+            .get(&block.header.prev_block_hash)
+            .is_some_and(|children| children.iter().any(|child| child.hash_with(self.hasher()) == hash));
+
+        if known {
+            return;
+        }
+
+        if self.orphan_count() >= MAX_ORPHANS {
+            let furthest = self.orphans
+                .iter()
+                .flat_map(|(parent, children)| children
+                    .iter()
+                    .enumerate()
+                    .map(move |(position, child)| (child.header.index, *parent, position))
+                )
+                .max();
+
+            match furthest {
+                Some((index, parent, position)) if index > block.header.index => {
+                    let children = self.orphans.get_mut(&parent).unwrap();
+                    children.remove(position);
+                    if children.is_empty() {
+                        self.orphans.remove(&parent);
+                    }
+                },
+                _ => return,
+            }
+        }
+
+        self.orphans.entry(block.header.prev_block_hash).or_default().push(block);
+    }
+
+    // The headers of every block from genesis to tip, for bootstrapping a
+    // light client, see verify_header_chain(). Pruned blocks keep theirs.
+    pub fn export_headers (&self) -> Vec<BlockHeader> {
+        self.blocks
+            .iter()
+            .map(|block| block.header)
+            .collect()
+    }
+
+    // Headers known so far: the blocks' plus those accepted ahead of them
+    pub fn header_count (&self) -> usize {
+        self.blocks.len() + self.pending_headers.len()
+    }
+
+    // Extends the header chain without the block itself, for syncing headers
+    // before bodies. The header gets every check a block gets that doesn't
+    // need its transactions, its difficulty included: that is what the
+    // header chain retargets to, across as many retarget boundaries as the
+    // headers run ahead of the blocks. Bodies are then supplied in order with
+    // accept_body(); the UTXO set only changes as each full block validates.
+    pub fn accept_header (&mut self, header: BlockHeader) -> Result<(), BlockValidationErr> {
+        let i = self.header_count();
+        if header.index != i as u32 {
+            return Err(BlockValidationErr::MismatchedIndex);
+        }
+
+        self.check_timestamp(&header)?;
+        self.check_version(&header)?;
+        self.check_checkpoint(&header)?;
+        self.consensus.validate_header(&header, self)?;
+
+        let recent_timestamps = self.blocks
+            .iter()
+            .map(|block| block.header.timestamp)
+            .chain(self.pending_headers.iter().map(|header| header.timestamp))
+            .rev();
+        let prev_hash = match self.pending_headers.back() {
+            Some(prev_header) => Some(prev_header.hash_with(self.hasher())),
+            None => self.tip().map(|block| block.hash_with(self.hasher())),
+        };
+
+        match prev_hash {
+            Some(prev_hash) => {
+                if header.timestamp <= median_time(recent_timestamps) {
+                    return Err(BlockValidationErr::TimestampBeforeMedian);
+                } else if header.prev_block_hash != prev_hash {
+                    return Err(BlockValidationErr::MismatchedPreviousHash);
+                }
+            },
+            None => {
+                if header.prev_block_hash != Hash::zero() {
+                    return Err(BlockValidationErr::InvalidGenesisBlockFormat);
+                }
+            },
+        }
+
+        self.pending_headers.push_back(header);
+
+        Ok(())
+    }
+
+    // Completes the first header accepted by accept_header() with its
+    // transactions, validating the block in full. Transactions that don't
+    // match the header's merkle root leave the header waiting for the right
+    // ones; any other failure means the header chain from there on was
+    // invalid, so it is dropped.
+    pub fn accept_body (&mut self, transactions: Vec<Transaction>) -> Result<(), BlockValidationErr> {
+        let header = match self.pending_headers.front() {
+            Some(header) => *header,
+            None => return Err(BlockValidationErr::MismatchedIndex),
+        };
+
+        let block = Block { header, transactions };
+        if block.header.merkle_root != block.merkle_root_with(self.hasher()) {
+            return Err(BlockValidationErr::InvalidMerkleRoot);
+        }
+
+        self.update_with_block(block).inspect_err(|_| self.pending_headers.clear())
+    }
+
+    // Removes the tip, restoring the UTXO set to what it was before the tip
+    // was accepted. Works on pruned blocks too, though the returned block
+    // then has no transactions, but not on those from_checkpoint() loaded.
+    // Side blocks built on the removed one are forgotten with it.
+    pub fn rollback (&mut self) -> Option<Block> {
+        if self.blocks.len() <= self.fast_forwarded || self.blocks.is_empty() {
+            return None;
+        }
+
+        let block = self.disconnect_tip().block;
+        self.tree.remove_with_descendants(&block.hash_with(self.hasher()));
+
+        Some(block)
+    }
+
+    // Takes the tip off, restoring the chain state to what it was before the
+    // tip was connected. The tip's node stays in the block tree. The caller
+    // makes sure there is a tip with undo data.
+    fn disconnect_tip (&mut self) -> DisconnectedBlock {
+        let block = self.blocks.pop().unwrap();
+        let undo = self.undo.pop().unwrap();
+        let i = self.blocks.len();
+        let unspent_outputs = Arc::make_mut(&mut self.unspent_outputs);
+        let coinbase_heights = &mut self.coinbase_heights;
+
+        let created = undo.created
+            .iter()
+            .filter_map(|outpoint| unspent_outputs
+                .remove(outpoint)
+                .map(|output| (*outpoint, output, coinbase_heights.remove(outpoint)))
+            )
+            .collect();
+        for (outpoint, output, height) in &undo.spent {
+            if let Some(height) = height {
+                coinbase_heights.insert(*outpoint, *height);
+            }
+            unspent_outputs.insert(*outpoint, output.clone());
+        }
+
+        let hash = block.hash_with(self.hasher());
+        Arc::make_mut(&mut self.block_index).remove(&hash);
+        for transaction in &block.transactions {
+            let hash = transaction.hash_with(self.hasher());
+            if matches!(self.transaction_index.get(&hash), Some(&(block, _)) if block == i) {
+                self.transaction_index.remove(&hash);
+            }
+            for outpoint in transaction.spent_outpoints() {
+                self.spender_index.remove(outpoint);
+            }
+        }
+        self.pruned = self.pruned.min(i);
+        self.difficulty = undo.difficulty;
+        self.total_work = self.total_work.saturating_sub(block::work(self.expected_difficulty(i as u32)));
+        self.total_minted -= undo.minted;
+        self.pending_headers.clear();
+
+        DisconnectedBlock { block, undo, created }
+    }
+
+    // Puts a block disconnect_tip() took off back on without validating it
+    // again, e.g. a pruned one, which couldn't be
+    fn reconnect (&mut self, disconnected: DisconnectedBlock) {
+        let DisconnectedBlock { block, undo, created } = disconnected;
+        let i = self.blocks.len();
+        let unspent_outputs = Arc::make_mut(&mut self.unspent_outputs);
+
+        for (outpoint, _, _) in &undo.spent {
+            unspent_outputs.remove(outpoint);
+            self.coinbase_heights.remove(outpoint);
+        }
+        for (outpoint, output, height) in created {
+            if let Some(height) = height {
+                self.coinbase_heights.insert(outpoint, height);
+            }
+            unspent_outputs.insert(outpoint, output);
+        }
+
+        if self.config.indexing {
+            for (position, transaction) in block.transactions.iter().enumerate() {
+                self.transaction_index.insert(transaction.hash_with(self.hasher()), (i, position));
+                for outpoint in transaction.spent_outpoints() {
+                    self.spender_index.insert(*outpoint, (i, position));
+                }
+            }
+        }
+
+        let hash = block.hash_with(self.hasher());
+        Arc::make_mut(&mut self.block_index).insert(hash, i);
+        self.total_work = self.total_work.saturating_add(block::work(self.expected_difficulty(i as u32)));
+        self.blocks.push(block);
+        self.total_minted += undo.minted;
+        self.undo.push(undo);
+        self.retarget();
+    }
+
+    // The bounds on a timestamp that don't depend on the blocks before it.
+    // Applies to the genesis block too: nothing legitimate is ever stamped in
+    // the future, and replaying old blocks is unaffected.
+    fn check_timestamp (&self, header: &BlockHeader) -> Result<(), BlockValidationErr> {
+        if header.timestamp > self.now() + self.config.max_future_drift as u128 {
+            Err(BlockValidationErr::TimestampTooFarInFuture)
+        } else if header.timestamp < self.config.min_timestamp {
+            Err(BlockValidationErr::TimestampTooOld)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn check_version (&self, header: &BlockHeader) -> Result<(), BlockValidationErr> {
+        let min_version = self.config.min_versions
+            .range(..=header.index)
+            .map(|(_, version)| *version)
+            .max()
+            .unwrap_or(0);
+
+        if header.network != self.config.network {
+            Err(BlockValidationErr::WrongNetwork)
+        } else if header.version < min_version {
+            Err(BlockValidationErr::UnsupportedVersion)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn check_checkpoint (&self, header: &BlockHeader) -> Result<(), BlockValidationErr> {
+        if header.index == 0 && self.config.genesis_hash.is_some_and(|hash| hash != header.hash_with(self.hasher())) {
+            return Err(BlockValidationErr::GenesisMismatch);
+        }
+
+        match self.config.checkpoints.get(&header.index) {
+            Some(hash) if *hash != header.hash_with(self.hasher()) => Err(BlockValidationErr::CheckpointMismatch),
+            _ => Ok(()),
+        }
+    }
+
+    // Median timestamp of the last MEDIAN_TIME_SPAN blocks (or all of them, on
+    // a shorter chain). Unlike requiring each block to be later than its
+    // parent, one badly skewed timestamp can't drag this around.
+    fn median_time_past (&self) -> u128 {
+        median_time(self.iter_rev().map(|block| block.header.timestamp))
+    }
+
+    // Called after each accepted block. Once a full window of blocks is in,
+    // the difficulty is scaled by how long the window actually took compared
+    // to how long it should have taken. Larger difficulty means easier blocks.
+    fn retarget (&mut self) {
+        let interval = self.config.retarget_interval as usize;
+        let height = self.blocks.len();
+
+        if interval == 0 || self.config.target_block_time == 0 || !height.is_multiple_of(interval) {
+            return;
+        }
+
+        // Measure from the last block of the previous window, if there is one.
+        // A window reaching below a checkpoint can't be measured.
+        let first = match self.blocks.get((height - interval).saturating_sub(1)) {
+            Some(first) => first.header,
+            None => return,
+        };
+
+        self.difficulty = self.retargeted(self.difficulty, &first, &self.blocks[height - 1].header);
+    }
+
+    // `difficulty` scaled by how long the blocks from `first` to `last`
+    // took compared to how long they should have taken
+    fn retargeted (&self, difficulty: u128, first: &BlockHeader, last: &BlockHeader) -> u128 {
+        let gaps = (last.index - first.index) as u128;
+
+        if gaps == 0 {
+            return difficulty;
+        }
+
+        let expected = self.config.target_block_time as u128 * gaps;
+        let actual = last.timestamp.saturating_sub(first.timestamp)
+            .max(expected / MAX_RETARGET_FACTOR)
+            .min(expected * MAX_RETARGET_FACTOR);
+
+        let difficulty = (difficulty / expected).saturating_mul(actual)
+            .saturating_add(difficulty % expected * actual / expected);
+
+        difficulty.min(self.config.min_difficulty).max(1)
+    }
+}
+
+// Median of the first MEDIAN_TIME_SPAN timestamps, newest first
+fn median_time<I: Iterator<Item = u128>> (newest_first: I) -> u128 {
+    let mut timestamps = newest_first
+        .take(MEDIAN_TIME_SPAN)
+        .collect::<Vec<u128>>();
+
+    timestamps.sort_unstable();
+    timestamps.get(timestamps.len() / 2).cloned().unwrap_or(0)
+}
+
+// Root over a sorted UTXO snapshot, see Blockchain::utxo_commitment()
+pub(crate) fn snapshot_commitment (snapshot: &[(OutPoint, Output)], hasher: &dyn Hasher<Output = Hash>) -> Hash {
+    let leaves = snapshot
+        .iter()
+        .map(|(outpoint, output)| {
+            let mut bytes = outpoint.bytes();
+            bytes.extend(output.bytes());
+            hasher.digest(&bytes)
+        })
+        .collect();
+
+    merkle_root_with(leaves, hasher)
+}
+
+// Only the config and the blocks are stored; the UTXO set and the current
+// difficulty are derived data
+impl Serialize for Blockchain {
+    fn serialize<S: Serializer> (&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.pruned > 0 {
+            return Err(serde::ser::Error::custom("a pruned chain can't be serialized"));
+        }
+
+        let mut state = serializer.serialize_struct("Blockchain", 2)?;
+        state.serialize_field("config", &self.config)?;
+        state.serialize_field("blocks", &self.blocks)?;
+        state.end()
+    }
+}
+
+// Rebuilds the UTXO set by replaying every block, so a chain that doesn't
+// validate (or a tampered UTXO set) can't be deserialized
+impl<'de> Deserialize<'de> for Blockchain {
+    fn deserialize<D: Deserializer<'de>> (deserializer: D) -> Result<Self, D::Error> {
+        let stored = StoredBlockchain::deserialize(deserializer)?;
+
+        Blockchain::with_config(stored.config).replay(stored.blocks)
+            .map_err(|err| D::Error::custom(format!("invalid block: {:?}", err)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+
+    fn miner () -> Address {
+        Address::from_pubkey(&SigningKey::from_bytes(&[1; 32]).verifying_key())
+    }
+
+    fn chain (blocks: usize) -> Blockchain {
+        let mut chain = Blockchain::new(u128::MAX);
+        for _ in 0..blocks {
+            chain.mine_next_block(&miner(), &mut Mempool::new()).unwrap();
+        }
+        chain
+    }
+
+    #[test]
+    fn verify_finds_a_tampered_block () {
+        let mut chain = chain(3);
+        assert!(chain.verify().is_ok());
+
+        chain.blocks.get_mut(1).unwrap().transactions[0].outputs[0].value -= 1;
+
+        assert!(matches!(chain.verify(), Err((1, BlockValidationErr::InvalidMerkleRoot))));
+    }
+
+    #[test]
+    fn verify_finds_a_drifted_utxo_set () {
+        let mut chain = chain(3);
+        let outpoint = *chain.unspent_outputs.keys().next().unwrap();
+
+        Arc::make_mut(&mut chain.unspent_outputs).remove(&outpoint);
+
+        assert!(matches!(chain.verify(), Err((2, BlockValidationErr::UnspentOutputsMismatch))));
+        chain.rebuild_utxo_set().unwrap();
+        assert!(chain.verify().is_ok());
+    }
+
+    #[test]
+    fn rebuilding_undoes_added_and_removed_utxos () {
+        let mut chain = chain(3);
+        let (before, utxo_set_hash) = (chain.utxo_snapshot(), chain.utxo_set_hash());
+        let (removed, output) = before[0].clone();
+        let added = OutPoint { txid: Hash::digest(b"forged"), index: 0 };
+
+        let unspent_outputs = Arc::make_mut(&mut chain.unspent_outputs);
+        unspent_outputs.remove(&removed);
+        unspent_outputs.insert(added, output);
+        assert_ne!(chain.utxo_set_hash(), utxo_set_hash);
+
+        chain.rebuild_utxo_set().unwrap();
+        assert!(chain.utxo_snapshot() == before);
+        assert!(chain.is_unspent(&removed) && !chain.is_unspent(&added));
+
+        chain.rebuild_utxo_set().unwrap();
+        assert_eq!(chain.utxo_set_hash(), utxo_set_hash);
+        assert_eq!(chain.get_balance(&miner()), 150);
+    }
+
+    #[test]
+    fn rebuilding_fails_at_the_first_bad_block_and_changes_nothing () {
+        let mut chain = chain(3);
+        let outpoint = *chain.unspent_outputs.keys().next().unwrap();
+        Arc::make_mut(&mut chain.unspent_outputs).remove(&outpoint);
+        let utxo_set_hash = chain.utxo_set_hash();
+
+        chain.blocks.get_mut(1).unwrap().transactions[0].outputs[0].value -= 1;
+
+        assert!(matches!(chain.rebuild_utxo_set(), Err((1, BlockValidationErr::InvalidMerkleRoot))));
+        assert_eq!(chain.utxo_set_hash(), utxo_set_hash);
+    }
+
+    #[test]
+    fn the_supply_audit_finds_an_inflated_coinbase () {
+        let mut chain = chain(3);
+        assert_eq!(chain.audit_supply().unwrap(), chain.total_minted());
+
+        chain.blocks.get_mut(1).unwrap().transactions[0].outputs[0].value += 1;
+
+        assert!(matches!(chain.audit_supply(), Err(SupplyError::InvalidIssuance { block: 1 })));
+    }
+
+    #[test]
+    fn the_supply_audit_finds_coins_missing_from_the_utxo_set () {
+        let mut chain = chain(3);
+        let minted = chain.total_minted();
+        let outpoint = *chain.unspent_outputs.keys().next().unwrap();
+
+        Arc::make_mut(&mut chain.unspent_outputs).get_mut(&outpoint).unwrap().value -= 1;
+
+        assert!(matches!(
+            chain.audit_supply(),
+            Err(SupplyError::UnspentValueMismatch { minted: m, unspent: u }) if m == minted && u == minted - 1
+        ));
+    }
 }
@@ -0,0 +1,171 @@
+use super::*;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::f64::consts::LN_2;
+use std::fmt::{self, Display, Formatter};
+
+// Caps on what a filter may cost to hold and to test against, since peers
+// hand them over. The same as BIP 37's.
+pub const MAX_BLOOM_FILTER_SIZE: usize = 36_000 * 8;
+pub const MAX_BLOOM_HASH_COUNT: u32 = 50;
+
+// A probabilistic set for light clients: a wallet inserts the addresses and
+// output hashes it cares about and hands the filter to a full node, which can
+// then tell which blocks might be relevant without learning exactly what the
+// wallet is watching. contains() never misses an inserted item but may report
+// items that weren't inserted.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "StoredBloomFilter")]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    size: usize,
+    hash_count: u32,
+}
+
+// A filter as read, before it is checked by BloomFilter::from_parts()
+#[derive(Deserialize)]
+struct StoredBloomFilter {
+    bits: Vec<u8>,
+    size: usize,
+    hash_count: u32,
+}
+
+// Why a filter read from elsewhere was refused
+#[derive(Debug)]
+pub enum BloomFilterError {
+    // No bits at all, or more than MAX_BLOOM_FILTER_SIZE
+    InvalidSize,
+    // The bits don't fill exactly the bytes `size` bits take
+    SizeMismatch,
+    // No hashes at all, or more than MAX_BLOOM_HASH_COUNT
+    InvalidHashCount,
+}
+
+impl Display for BloomFilterError {
+    fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            BloomFilterError::InvalidSize => write!(f, "filter size is zero or too large"),
+            BloomFilterError::SizeMismatch => write!(f, "filter bits don't match its size"),
+            BloomFilterError::InvalidHashCount => write!(f, "filter hash count is zero or too large"),
+        }
+    }
+}
+
+impl std::error::Error for BloomFilterError {}
+
+impl TryFrom<StoredBloomFilter> for BloomFilter {
+    type Error = BloomFilterError;
+
+    fn try_from (stored: StoredBloomFilter) -> Result<Self, Self::Error> {
+        BloomFilter::from_parts(stored.bits, stored.size, stored.hash_count)
+    }
+}
+
+impl BloomFilter {
+    // A filter of `size` bits setting `hash_count` bits per item. Both are
+    // raised to at least 1 and capped at MAX_BLOOM_FILTER_SIZE and
+    // MAX_BLOOM_HASH_COUNT.
+    pub fn new (size: usize, hash_count: u32) -> Self {
+        let size = size.clamp(1, MAX_BLOOM_FILTER_SIZE);
+
+        BloomFilter {
+            bits: vec![0; size.div_ceil(8)],
+            size,
+            hash_count: hash_count.clamp(1, MAX_BLOOM_HASH_COUNT),
+        }
+    }
+
+    // A filter from its bits, e.g. one a peer sent, refused unless it is one
+    // new() could have made. Anything else could index past the bits.
+    pub fn from_parts (bits: Vec<u8>, size: usize, hash_count: u32) -> Result<Self, BloomFilterError> {
+        if size == 0 || size > MAX_BLOOM_FILTER_SIZE {
+            return Err(BloomFilterError::InvalidSize);
+        } else if bits.len() != size.div_ceil(8) {
+            return Err(BloomFilterError::SizeMismatch);
+        } else if hash_count == 0 || hash_count > MAX_BLOOM_HASH_COUNT {
+            return Err(BloomFilterError::InvalidHashCount);
+        }
+
+        Ok(BloomFilter {
+            bits,
+            size,
+            hash_count,
+        })
+    }
+
+    // Sized so that, once `expected_items` have been inserted, an item that
+    // wasn't has about a `false_positive_rate` chance of matching anyway. A
+    // lower rate means a larger filter and reveals more about its contents,
+    // up to the caps new() applies.
+    pub fn with_rate (expected_items: usize, false_positive_rate: f64) -> Self {
+        let items = expected_items.max(1) as f64;
+        let rate = false_positive_rate.clamp(f64::MIN_POSITIVE, 1.0);
+
+        let size = (-items * rate.ln() / (LN_2 * LN_2)).ceil();
+        let hash_count = (size / items * LN_2).round();
+
+        BloomFilter::new(size as usize, hash_count as u32)
+    }
+
+    pub fn insert (&mut self, data: &[u8]) {
+        for bit in self.bit_indexes(data) {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    pub fn contains (&self, data: &[u8]) -> bool {
+        self.bit_indexes(data).all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    // Double hashing: the k indexes are h1 + i * h2 for two halves of one
+    // digest, which is as good as k independent hashes for a Bloom filter
+    fn bit_indexes (&self, data: &[u8]) -> impl Iterator<Item = usize> {
+        let digest = Hash::digest(data);
+        let mut half = [0; 8];
+
+        half.copy_from_slice(&digest.as_bytes()[0..8]);
+        let h1 = u64::from_le_bytes(half);
+        half.copy_from_slice(&digest.as_bytes()[8..16]);
+        let h2 = u64::from_le_bytes(half);
+
+        let size = self.size as u64;
+        (0..self.hash_count as u64)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % size) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deserialize (bits: Vec<u8>, size: usize, hash_count: u32) -> bincode::Result<BloomFilter> {
+        bincode::deserialize(&bincode::serialize(&(bits, size, hash_count)).unwrap())
+    }
+
+    #[test]
+    fn round_trips_through_serde () {
+        let mut filter = BloomFilter::new(100, 3);
+        filter.insert(b"watched");
+
+        let read: BloomFilter = bincode::deserialize(&bincode::serialize(&filter).unwrap()).unwrap();
+        assert!(read.contains(b"watched"));
+    }
+
+    #[test]
+    fn refuses_filters_that_would_panic () {
+        assert!(deserialize(vec![], 0, 1).is_err());
+        assert!(deserialize(vec![0; 1], 100, 1).is_err());
+        assert!(deserialize(vec![0; 14], 100, 1).is_err());
+        assert!(deserialize(vec![0; 13], 100, 0).is_err());
+        assert!(deserialize(vec![0; 13], 100, MAX_BLOOM_HASH_COUNT + 1).is_err());
+        assert!(deserialize(vec![0; 13], 100, 3).is_ok());
+    }
+
+    #[test]
+    fn new_stays_within_the_caps () {
+        let filter = BloomFilter::with_rate(usize::MAX, 0.0);
+
+        assert!(filter.size <= MAX_BLOOM_FILTER_SIZE);
+        assert!(filter.hash_count <= MAX_BLOOM_HASH_COUNT);
+    }
+}
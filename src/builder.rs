@@ -0,0 +1,141 @@
+use super::*;
+use crate::transaction::{Input, OutPoint, Output};
+
+#[derive(Debug)]
+pub enum TransactionBuilderErr {
+    NoInputs,
+    NoOutputs,
+    ZeroValueOutput,
+    DustOutput,
+    InsufficientInputValue,
+    Overflow,
+}
+
+// Builds an unsigned transaction spending the given outputs, e.g.
+//
+//     TransactionBuilder::new()
+//         .input(outpoint, utxo)
+//         .output(bob, 30)
+//         .output(chris, 10)
+//         .change(alice, 2)
+//         .build()?
+//
+// The outputs are only used for their value. The fee is whatever the inputs
+// leave over after the outputs.
+pub struct TransactionBuilder {
+    inputs: Vec<(OutPoint, Output)>,
+    outputs: Vec<Output>,
+    change: Option<(Address, u64)>,
+    dust_threshold: u64,
+    lock_height: u32,
+}
+
+impl TransactionBuilder {
+    pub fn new () -> Self {
+        TransactionBuilder {
+            inputs: vec![],
+            outputs: vec![],
+            change: None,
+            dust_threshold: 0,
+            lock_height: 0,
+        }
+    }
+
+    // Outputs worth less than this are rejected
+    pub fn with_dust_threshold (mut self, dust_threshold: u64) -> Self {
+        self.dust_threshold = dust_threshold;
+        self
+    }
+
+    pub fn input (mut self, outpoint: OutPoint, output: Output) -> Self {
+        self.inputs.push((outpoint, output));
+        self
+    }
+
+    // E.g. what Blockchain::select_coins() picked
+    pub fn inputs<I: IntoIterator<Item = (OutPoint, Output)>> (mut self, inputs: I) -> Self {
+        self.inputs.extend(inputs);
+        self
+    }
+
+    pub fn output (mut self, to_addr: Address, value: u64) -> Self {
+        self.outputs.push(Output { to_addr, value, script: vec![] });
+        self
+    }
+
+    pub fn outputs<I: IntoIterator<Item = (Address, u64)>> (mut self, outputs: I) -> Self {
+        self.outputs.extend(outputs.into_iter().map(|(to_addr, value)| Output { to_addr, value, script: vec![] }));
+        self
+    }
+
+    // Pays whatever is left after the outputs and `fee` back to `to_addr`.
+    // Change that would be dust is left to the fee instead.
+    pub fn change (mut self, to_addr: Address, fee: u64) -> Self {
+        self.change = Some((to_addr, fee));
+        self
+    }
+
+    // See Transaction::lock_height
+    pub fn lock_height (mut self, lock_height: u32) -> Self {
+        self.lock_height = lock_height;
+        self
+    }
+
+    pub fn build (self) -> Result<Transaction, TransactionBuilderErr> {
+        if self.inputs.is_empty() {
+            return Err(TransactionBuilderErr::NoInputs);
+        }
+
+        for output in &self.outputs {
+            if output.value == 0 {
+                return Err(TransactionBuilderErr::ZeroValueOutput);
+            }
+            if output.value < self.dust_threshold {
+                return Err(TransactionBuilderErr::DustOutput);
+            }
+        }
+
+        let input_value = self.inputs
+            .iter()
+            .try_fold(0u64, |sum, (_, output)| sum.checked_add(output.value))
+            .ok_or(TransactionBuilderErr::Overflow)?;
+        let output_value = self.outputs
+            .iter()
+            .try_fold(0u64, |sum, output| sum.checked_add(output.value))
+            .ok_or(TransactionBuilderErr::Overflow)?;
+
+        if output_value > input_value {
+            return Err(TransactionBuilderErr::InsufficientInputValue);
+        }
+
+        let mut outputs = self.outputs;
+
+        if let Some((to_addr, fee)) = self.change {
+            let change = (input_value - output_value)
+                .checked_sub(fee)
+                .ok_or(TransactionBuilderErr::InsufficientInputValue)?;
+
+            if change > 0 && change >= self.dust_threshold {
+                outputs.push(Output { to_addr, value: change, script: vec![] });
+            }
+        }
+
+        if outputs.is_empty() {
+            return Err(TransactionBuilderErr::NoOutputs);
+        }
+
+        Ok(Transaction {
+            inputs: self.inputs.into_iter().map(|(outpoint, _)| Input::new(outpoint)).collect(),
+            outputs,
+            coinbase: false,
+            coinbase_data: vec![],
+            lock_height: self.lock_height,
+        })
+    }
+}
+
+impl Default for TransactionBuilder {
+    fn default () -> Self {
+        TransactionBuilder::new()
+    }
+}
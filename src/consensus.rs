@@ -0,0 +1,152 @@
+use super::*;
+
+// Decides whether a block is acceptable as the next block of `chain`. Only the
+// acceptance rule lives here; index, linkage, timestamps and transactions are
+// always checked by the chain itself.
+pub trait Consensus: Send + Sync {
+    // The part of the rule that only needs the header. Also used on its own
+    // for headers received ahead of their blocks, see Blockchain::accept_header().
+    fn validate_header (&self, header: &BlockHeader, chain: &Blockchain) -> Result<(), BlockValidationErr>;
+
+    // Defaults to validate_header()
+    fn validate (&self, block: &Block, chain: &Blockchain) -> Result<(), BlockValidationErr> {
+        self.validate_header(&block.header, chain)
+    }
+
+    // The part of validate_header() that holds without knowing the header's
+    // branch, for blocks waiting on their parent, see Blockchain::add_block().
+    // Defaults to accepting the header.
+    fn validate_orphan_header (&self, _header: &BlockHeader, _chain: &Blockchain) -> Result<(), BlockValidationErr> {
+        Ok(())
+    }
+}
+
+// The default: the block's hash must meet the difficulty its header states,
+// and that must be the one the chain expects of it on its branch, see
+// Blockchain::expected_difficulty_for(). A miner could otherwise claim an
+// easier difficulty than the chain's.
+pub struct ProofOfWork;
+
+impl Consensus for ProofOfWork {
+    fn validate_header (&self, header: &BlockHeader, chain: &Blockchain) -> Result<(), BlockValidationErr> {
+        if header.difficulty != chain.expected_difficulty_for(header) {
+            Err(BlockValidationErr::UnexpectedDifficulty)
+        } else if check_difficulty(&chain.hash_of(header), header.difficulty) {
+            Ok(())
+        } else {
+            Err(BlockValidationErr::InvalidHash)
+        }
+    }
+
+    // The expected difficulty depends on the branch, but no branch may be
+    // easier than the chain's min_difficulty
+    fn validate_orphan_header (&self, header: &BlockHeader, chain: &Blockchain) -> Result<(), BlockValidationErr> {
+        if header.difficulty > chain.min_difficulty() {
+            Err(BlockValidationErr::UnexpectedDifficulty)
+        } else if check_difficulty(&chain.hash_of(header), header.difficulty) {
+            Ok(())
+        } else {
+            Err(BlockValidationErr::InvalidHash)
+        }
+    }
+}
+
+// ProofOfWork without the work, so tests can use realistic difficulties
+// without mining: the header must still state the chain's expected
+// difficulty, but its hash isn't checked, e.g. an unmined block with nonce 0
+// passes. Mainnet blocks are held to ProofOfWork in full, so a production
+// chain can't be weakened by picking this by mistake.
+#[cfg(feature = "test-pow")]
+pub struct TestProofOfWork;
+
+#[cfg(feature = "test-pow")]
+impl Consensus for TestProofOfWork {
+    fn validate_header (&self, header: &BlockHeader, chain: &Blockchain) -> Result<(), BlockValidationErr> {
+        if header.network == MAINNET {
+            ProofOfWork.validate_header(header, chain)
+        } else if header.difficulty != chain.expected_difficulty_for(header) {
+            Err(BlockValidationErr::UnexpectedDifficulty)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn validate_orphan_header (&self, header: &BlockHeader, chain: &Blockchain) -> Result<(), BlockValidationErr> {
+        if header.network == MAINNET {
+            ProofOfWork.validate_orphan_header(header, chain)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// Accepts every block. Only useful for tests and experiments.
+pub struct AcceptAll;
+
+impl Consensus for AcceptAll {
+    fn validate_header (&self, _header: &BlockHeader, _chain: &Blockchain) -> Result<(), BlockValidationErr> {
+        Ok(())
+    }
+}
+
+// A simplified proof of stake. The block's producer is whoever its coinbase
+// pays first, and its stake is that address's balance at the tip. Each slot
+// of `slot_duration` ms, every address gets a kernel hash derived from the
+// previous block, the address and the slot; an address may produce a block
+// in that slot if its kernel is below `target` times its stake, so the odds
+// grow with stake and an address with none can never produce. Nothing needs
+// grinding, but the model has none of the protections real PoS chains need,
+// e.g. producers aren't made to sign their blocks.
+pub struct ProofOfStake {
+    // Per coin of stake, compared against the kernel's top 128 bits
+    pub target: u128,
+    pub slot_duration: u64,
+}
+
+impl ProofOfStake {
+    pub fn kernel (&self, chain: &Blockchain, prev_block_hash: &Hash, producer: &Address, timestamp: u128) -> Hash {
+        let slot = timestamp / self.slot_duration.max(1) as u128;
+
+        let mut bytes = vec![];
+        bytes.extend(prev_block_hash.as_bytes());
+        bytes.extend(producer.bytes());
+        bytes.extend(&u128_bytes(&slot));
+
+        chain.hasher().digest(&bytes)
+    }
+
+    // Whether `producer` may make the block after `prev_block_hash` at
+    // `timestamp`, staking its balance on `chain`
+    pub fn is_eligible (&self, chain: &Blockchain, prev_block_hash: &Hash, producer: &Address, timestamp: u128) -> bool {
+        let stake = chain.get_balance(producer) as u128;
+        let kernel = self.kernel(chain, prev_block_hash, producer, timestamp);
+
+        stake > 0 && difficulty_bytes_as_u128(&kernel) < self.target.saturating_mul(stake)
+    }
+}
+
+impl Consensus for ProofOfStake {
+    // The producer is only known from the coinbase, so headers alone pass
+    fn validate_header (&self, _header: &BlockHeader, _chain: &Blockchain) -> Result<(), BlockValidationErr> {
+        Ok(())
+    }
+
+    fn validate (&self, block: &Block, chain: &Blockchain) -> Result<(), BlockValidationErr> {
+        // Nobody has stake before genesis
+        if block.header.index == 0 {
+            return Ok(());
+        }
+
+        let producer = block.transactions
+            .first()
+            .and_then(|coinbase| coinbase.outputs.first())
+            .map(|output| &output.to_addr)
+            .ok_or(BlockValidationErr::IneligibleProducer)?;
+
+        if self.is_eligible(chain, &block.header.prev_block_hash, producer, block.header.timestamp) {
+            Ok(())
+        } else {
+            Err(BlockValidationErr::IneligibleProducer)
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use super::*;
+use std::io::{self, Write};
+
+const CSV_HEADER: &str = "block_index,tx_hash,input_value,output_value,fee,is_coinbase";
+
+// Quotes a field if it holds anything CSV treats specially
+fn csv_field (field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl Blockchain {
+    // One row per transaction, in chain order. Fees are the ones validation
+    // worked out, see transaction_fee(), and inputs are valued as the
+    // outputs plus the fee. A coinbase has no inputs and pays no fee.
+    pub fn export_csv<W: Write> (&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "{}", CSV_HEADER)?;
+
+        for block in self.blocks() {
+            for (position, transaction) in block.transactions.iter().enumerate() {
+                self.export_csv_row(writer, block, position, transaction)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn export_csv_row<W: Write> (&self, writer: &mut W, block: &Block, position: usize, transaction: &Transaction) -> io::Result<()> {
+        let output_value = transaction.output_value().unwrap_or(0);
+        let fee = self.transaction_fee(block.header.index, position).unwrap_or(0);
+        let input_value = if transaction.is_coinbase() {
+            0
+        } else {
+            output_value.saturating_add(fee)
+        };
+
+        let row = [
+            block.header.index.to_string(),
+            self.hash_of(transaction).to_string(),
+            input_value.to_string(),
+            output_value.to_string(),
+            fee.to_string(),
+            transaction.is_coinbase().to_string(),
+        ];
+
+        writeln!(writer, "{}", row.iter().map(|field| csv_field(field)).collect::<Vec<String>>().join(","))
+    }
+
+    // The chain as JSON, in the same shape it is serialized in elsewhere.
+    // Fails on a pruned chain, which can't be serialized.
+    #[cfg(feature = "json")]
+    pub fn export_json<W: Write> (&self, writer: &mut W) -> io::Result<()> {
+        serde_json::to_writer_pretty(writer, self).map_err(io::Error::other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_fields_csv_treats_specially () {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("carriage\rreturn"), "\"carriage\rreturn\"");
+    }
+}
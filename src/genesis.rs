@@ -0,0 +1,37 @@
+use super::*;
+use crate::blockchain::snapshot_commitment;
+use crate::transaction::Output;
+
+pub struct GenesisConfig {
+    pub difficulty: u128,
+    // Paid to `recipient` by the genesis coinbase; must not exceed the
+    // chain's block reward at height 0
+    pub initial_reward: u64,
+    pub recipient: Address,
+    pub timestamp: u128,
+}
+
+// A mined genesis block: index 0, zeroed previous hash and a single coinbase,
+// whose output is all the UTXO set the block commits to
+pub fn build_genesis (config: GenesisConfig) -> Result<Block, MineError> {
+    let coinbase = Transaction {
+        inputs: vec![],
+        outputs: vec![
+            Output {
+                to_addr: config.recipient,
+                value: config.initial_reward,
+                script: vec![],
+            },
+        ],
+        coinbase: true,
+        coinbase_data: vec![],
+        lock_height: 0,
+    };
+
+    let utxos = coinbase.outpoints().into_iter().zip(coinbase.outputs.iter().cloned()).collect::<Vec<_>>();
+    let mut genesis = Block::new(0, config.timestamp, Hash::zero(), vec![coinbase], config.difficulty);
+    genesis.header.utxo_root = snapshot_commitment(&utxos, &Sha256);
+    genesis.mine(config.difficulty)?;
+
+    Ok(genesis)
+}
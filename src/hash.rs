@@ -0,0 +1,107 @@
+use std::convert::TryFrom;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::str::FromStr;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error;
+
+// A SHA-256 digest. Stored inline, so copying one doesn't allocate.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Hash(pub [u8; 32]);
+
+#[derive(Debug)]
+pub enum ParseHashError {
+    InvalidHex,
+    InvalidLength,
+}
+
+impl Hash {
+    // Used as the genesis block's previous hash
+    pub fn zero () -> Self {
+        Hash([0; 32])
+    }
+
+    pub fn digest (bytes: &[u8]) -> Self {
+        let digest = crypto_hash::digest(crypto_hash::Algorithm::SHA256, bytes);
+        Hash(<[u8; 32]>::try_from(digest.as_slice()).unwrap())
+    }
+
+    pub fn as_bytes (&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    // Parses the lowercase or uppercase hex Display produces; same as parse()
+    pub fn from_hex (s: &str) -> Result<Self, ParseHashError> {
+        s.parse()
+    }
+}
+
+impl AsRef<[u8]> for Hash {
+    fn as_ref (&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Display for Hash {
+    fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl Debug for Hash {
+    fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Hash({})", self)
+    }
+}
+
+impl FromStr for Hash {
+    type Err = ParseHashError;
+
+    fn from_str (s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s).map_err(|_| ParseHashError::InvalidHex)?;
+        let bytes = <[u8; 32]>::try_from(bytes.as_slice()).map_err(|_| ParseHashError::InvalidLength)?;
+
+        Ok(Hash(bytes))
+    }
+}
+
+// Hashes serialize as hex strings rather than arrays of numbers
+impl Serialize for Hash {
+    fn serialize<S: Serializer> (&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Hash {
+    fn deserialize<D: Deserializer<'de>> (deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|err| D::Error::custom(format!("invalid hash: {:?}", err)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_hex () {
+        let hash = Hash::digest(b"block");
+        let hex = hash.to_string();
+
+        assert_eq!(hex.len(), 64);
+        assert_eq!(hex, hex.to_lowercase());
+        assert_eq!(Hash::from_hex(&hex).unwrap(), hash);
+        assert_eq!(Hash::from_hex(&hex.to_uppercase()).unwrap(), hash);
+        assert_eq!(format!("{:?}", hash), format!("Hash({})", hex));
+    }
+
+    #[test]
+    fn rejects_malformed_hex () {
+        let hex = Hash::digest(b"block").to_string();
+
+        assert!(matches!(Hash::from_hex(&hex.replacen(&hex[..1], "g", 1)), Err(ParseHashError::InvalidHex)));
+        assert!(matches!(Hash::from_hex(&hex[..63]), Err(ParseHashError::InvalidHex)));
+        assert!(matches!(Hash::from_hex(&hex[..62]), Err(ParseHashError::InvalidLength)));
+        assert!(matches!(Hash::from_hex(&format!("{}00", hex)), Err(ParseHashError::InvalidLength)));
+        assert!(matches!(Hash::from_hex(""), Err(ParseHashError::InvalidLength)));
+    }
+}
@@ -4,6 +4,10 @@ pub trait Hashable {
     fn bytes (&self) -> Vec<u8>;
 
     fn hash (&self) -> Hash {
-        crypto_hash::digest(crypto_hash::Algorithm::SHA256, &self.bytes())
+        self.hash_with(&Sha256)
+    }
+
+    fn hash_with (&self, hasher: &dyn Hasher<Output = Hash>) -> Hash {
+        hasher.digest(&self.bytes())
     }
 }
@@ -0,0 +1,49 @@
+use super::*;
+use std::convert::TryFrom;
+
+// A hash function a chain can be built on. Every block, transaction and
+// output hash a chain computes comes from the single Hasher it was created
+// with, see Blockchain::with_hasher().
+pub trait Hasher: Send + Sync {
+    type Output;
+
+    fn digest (&self, bytes: &[u8]) -> Self::Output;
+}
+
+// The default
+pub struct Sha256;
+
+impl Hasher for Sha256 {
+    type Output = Hash;
+
+    fn digest (&self, bytes: &[u8]) -> Hash {
+        Hash::digest(bytes)
+    }
+}
+
+// SHA-512 cut down to its first 32 bytes. Not the standardized SHA-512/256,
+// which starts from different initial values; just a second algorithm to
+// experiment with.
+pub struct Sha512Truncated;
+
+impl Hasher for Sha512Truncated {
+    type Output = Hash;
+
+    fn digest (&self, bytes: &[u8]) -> Hash {
+        let digest = crypto_hash::digest(crypto_hash::Algorithm::SHA512, bytes);
+        Hash(<[u8; 32]>::try_from(&digest[..32]).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_same_bytes_digest_differently_under_each_hasher () {
+        for bytes in [&b""[..], b"block", &[0; 100]] {
+            assert_eq!(Sha256.digest(bytes), Hash::digest(bytes));
+            assert_ne!(Sha256.digest(bytes), Sha512Truncated.digest(bytes));
+        }
+    }
+}
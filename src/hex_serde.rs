@@ -0,0 +1,12 @@
+use serde::{Deserialize, Deserializer, Serializer};
+use serde::de::Error;
+
+// Serializes a byte vector as a hex string rather than a list of integers
+pub fn serialize<S: Serializer> (bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hex::encode(bytes))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>> (deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    hex::decode(&s).map_err(|err| D::Error::custom(format!("invalid hex: {:?}", err)))
+}
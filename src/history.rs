@@ -0,0 +1,111 @@
+use super::*;
+use std::collections::HashMap;
+use crate::transaction::{OutPoint, Output};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressEntryKind {
+    Received,
+    Spent,
+}
+
+// One output an address received or spent. A spend has the same outpoint as
+// the entry for receiving the output it consumed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressEntry {
+    pub kind: AddressEntryKind,
+    pub block_index: u32,
+    pub tx_hash: Hash,
+    pub outpoint: OutPoint,
+    pub amount: u64,
+}
+
+// An address's balance split by whether it is confirmed yet, see
+// Blockchain::get_balance_detailed()
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BalanceBreakdown {
+    // What get_balance() reports, held by the UTXO set
+    pub confirmed: u64,
+    // Paid to the address by mempool transactions and not spent again by
+    // others in the mempool
+    pub pending_incoming: u64,
+    // The part of `confirmed` that mempool transactions spend
+    pub pending_outgoing: u64,
+}
+
+impl BalanceBreakdown {
+    // What the address will hold once the mempool is mined
+    pub fn projected (&self) -> u64 {
+        (self.confirmed - self.pending_outgoing).saturating_add(self.pending_incoming)
+    }
+}
+
+impl Blockchain {
+    // The address's balance on the chain alongside what `mempool` would add
+    // to and take from it. Outputs only the mempool has are never counted as
+    // confirmed.
+    pub fn get_balance_detailed (&self, address: &Address, mempool: &Mempool) -> BalanceBreakdown {
+        let utxos = self.utxos_for(address);
+        let confirmed = utxos.iter().map(|(_, output)| output.value).sum();
+        let pending_outgoing = utxos
+            .iter()
+            .filter(|(outpoint, _)| mempool.is_spent(outpoint))
+            .map(|(_, output)| output.value)
+            .sum();
+
+        let pending_incoming = mempool
+            .inventory()
+            .iter()
+            .filter_map(|tx_hash| mempool.get(tx_hash).map(|transaction| (tx_hash, transaction)))
+            .flat_map(|(txid, transaction)| transaction.outputs
+                .iter()
+                .enumerate()
+                .map(move |(index, output)| (OutPoint { txid: *txid, index: index as u32 }, output)))
+            .filter(|(outpoint, output)| &output.to_addr == address && !mempool.is_spent(outpoint))
+            .map(|(_, output)| output.value)
+            .sum();
+
+        BalanceBreakdown {
+            confirmed,
+            pending_incoming,
+            pending_outgoing,
+        }
+    }
+
+    // Every output paid to the address and every spend of one, in chain
+    // order; within a transaction, its spends come before what it pays out.
+    // Pruned blocks are skipped, though spends in later blocks of outputs
+    // they created are still found.
+    pub fn address_history (&self, address: &Address) -> Vec<AddressEntry> {
+        let mut history = vec![];
+        // The address's outputs created in the blocks walked so far
+        let mut received: HashMap<OutPoint, Output> = HashMap::new();
+
+        for (block, transaction) in self.transactions() {
+            let tx_hash = self.hash_of(transaction);
+            let entry = |kind, outpoint: OutPoint, output: &Output| AddressEntry {
+                kind,
+                block_index: block.header.index,
+                tx_hash,
+                outpoint,
+                amount: output.value,
+            };
+
+            for &outpoint in transaction.spent_outpoints() {
+                let output = received.remove(&outpoint)
+                    .or_else(|| self.spent_output(block.header.index, &outpoint).cloned());
+
+                if let Some(output) = output.filter(|output| &output.to_addr == address) {
+                    history.push(entry(AddressEntryKind::Spent, outpoint, &output));
+                }
+            }
+            for (outpoint, output) in transaction.outpoints_with(self.hasher()).into_iter().zip(&transaction.outputs) {
+                if &output.to_addr == address {
+                    history.push(entry(AddressEntryKind::Received, outpoint, output));
+                    received.insert(outpoint, output.clone());
+                }
+            }
+        }
+
+        history
+    }
+}
@@ -1,6 +1,3 @@
-type Hash = Vec<u8>;
-type Address = String;
-
 // Credit: https://stackoverflow.com/a/44378174/2773837
 use std::time::{ SystemTime, UNIX_EPOCH };
 
@@ -14,76 +11,74 @@ pub fn now () -> u128 {
 }
 
 pub fn u32_bytes (u: &u32) -> [u8; 4] {
-    [
-        (u >> 8 * 0x0) as u8,
-        (u >> 8 * 0x1) as u8,
-        (u >> 8 * 0x2) as u8,
-        (u >> 8 * 0x3) as u8,
-    ]
+    u.to_le_bytes()
 }
 
 pub fn u64_bytes (u: &u64) -> [u8; 8] {
-    [
-        (u >> 8 * 0x0) as u8,
-        (u >> 8 * 0x1) as u8,
-        (u >> 8 * 0x2) as u8,
-        (u >> 8 * 0x3) as u8,
-
-        (u >> 8 * 0x4) as u8,
-        (u >> 8 * 0x5) as u8,
-        (u >> 8 * 0x6) as u8,
-        (u >> 8 * 0x7) as u8,
-    ]
+    u.to_le_bytes()
 }
 
 pub fn u128_bytes (u: &u128) -> [u8; 16] {
-    [
-        (u >> 8 * 0x0) as u8,
-        (u >> 8 * 0x1) as u8,
-        (u >> 8 * 0x2) as u8,
-        (u >> 8 * 0x3) as u8,
-
-        (u >> 8 * 0x4) as u8,
-        (u >> 8 * 0x5) as u8,
-        (u >> 8 * 0x6) as u8,
-        (u >> 8 * 0x7) as u8,
-
-        (u >> 8 * 0x8) as u8,
-        (u >> 8 * 0x9) as u8,
-        (u >> 8 * 0xa) as u8,
-        (u >> 8 * 0xb) as u8,
-
-        (u >> 8 * 0xc) as u8,
-        (u >> 8 * 0xd) as u8,
-        (u >> 8 * 0xe) as u8,
-        (u >> 8 * 0xf) as u8,
-    ]
+    u.to_le_bytes()
 }
 
-pub fn difficulty_bytes_as_u128 (v: &Vec<u8>) -> u128 {
-    ((v[31] as u128) << 0xf * 8) |
-        ((v[30] as u128) << 0xe * 8) |
-        ((v[29] as u128) << 0xd * 8) |
-        ((v[28] as u128) << 0xc * 8) |
-        ((v[27] as u128) << 0xb * 8) |
-        ((v[26] as u128) << 0xa * 8) |
-        ((v[25] as u128) << 0x9 * 8) |
-        ((v[24] as u128) << 0x8 * 8) |
-        ((v[23] as u128) << 0x7 * 8) |
-        ((v[22] as u128) << 0x6 * 8) |
-        ((v[21] as u128) << 0x5 * 8) |
-        ((v[20] as u128) << 0x4 * 8) |
-        ((v[19] as u128) << 0x3 * 8) |
-        ((v[18] as u128) << 0x2 * 8) |
-        ((v[17] as u128) << 0x1 * 8) |
-        ((v[16] as u128) << 0x0 * 8)
+pub fn difficulty_bytes_as_u128 (v: &Hash) -> u128 {
+    let mut bytes = [0; 16];
+    bytes.copy_from_slice(&v.as_bytes()[16..32]);
+    u128::from_le_bytes(bytes)
 }
 
+#[macro_use]
+mod logging;
+mod hash;
+pub use crate::hash::{Hash, ParseHashError};
+mod address;
+pub use crate::address::{Address, ParseAddressError};
+mod hex_serde;
+mod target;
+pub use crate::target::{compact_to_target, difficulty_to_target, meets_target, target_to_compact};
 mod block;
-pub use crate::block::Block;
+pub use crate::block::{Block, BlockHeader, MineError, BLOCK_VERSION, MAINNET, TESTNET, canonical_order, check_difficulty, verify_header_chain, work};
+mod bloom;
+pub use crate::bloom::{BloomFilter, BloomFilterError, MAX_BLOOM_FILTER_SIZE, MAX_BLOOM_HASH_COUNT};
+mod consensus;
+pub use crate::consensus::{AcceptAll, Consensus, ProofOfStake, ProofOfWork};
+#[cfg(feature = "test-pow")]
+pub use crate::consensus::TestProofOfWork;
+mod genesis;
+pub use crate::genesis::{GenesisConfig, build_genesis};
 mod hashable;
 pub use crate::hashable::Hashable;
+mod hasher;
+pub use crate::hasher::{Hasher, Sha256, Sha512Truncated};
+mod merkle;
+pub use crate::merkle::{merkle_proof, merkle_proof_with, merkle_root, merkle_root_with, verify_merkle_proof, verify_merkle_proof_with};
+mod builder;
+pub use crate::builder::{TransactionBuilder, TransactionBuilderErr};
+mod store;
+mod tree;
+pub use crate::tree::BlockTree;
 mod blockchain;
-pub use crate::blockchain::Blockchain;
+pub use crate::blockchain::{Blockchain, BlockUpdate, BlockValidationErr, CheckpointError, DifficultyError, LoadError, MineBlockError, ReorgResult, COINBASE_MATURITY};
+mod snapshot;
+pub use crate::snapshot::ChainSnapshot;
+mod shared;
+pub use crate::shared::SharedBlockchain;
+mod mempool;
+pub use crate::mempool::{Mempool, MempoolErr};
+mod export;
+mod audit;
+pub use crate::audit::SupplyError;
+mod history;
+pub use crate::history::{AddressEntry, AddressEntryKind, BalanceBreakdown};
+mod stats;
+pub use crate::stats::{BlockTimeStats, ChainSummary};
+#[cfg(feature = "net")]
+pub mod net;
+#[cfg(feature = "rpc")]
+mod rpc;
+#[cfg(feature = "rpc")]
+pub use crate::rpc::RpcServer;
+pub mod script;
 pub mod transaction;
-pub use crate::transaction::Transaction;
+pub use crate::transaction::{OutPoint, Transaction};
@@ -1,62 +1,96 @@
 use blockchainlib::*;
+use ed25519_dalek::SigningKey;
 
 fn main () {
+    let alice = SigningKey::from_bytes(&[1; 32]);
+    let bob = SigningKey::from_bytes(&[2; 32]);
+    let chris = SigningKey::from_bytes(&[3; 32]);
+
     let difficulty = 0x000fffffffffffffffffffffffffffff;
-    let mut genesis_block = Block::new(0, now(), vec![0; 32], vec![
+    let mut genesis_block = Block::new(0, now(), Hash::zero(), vec![
         Transaction {
             inputs: vec![ ],
             outputs: vec![
                 transaction::Output {
-                    to_addr: "Alice".to_owned(),
-                    value: 50,
+                    to_addr: transaction::address(&alice.verifying_key()),
+                    value: 43,
+                    script: vec![],
                 },
                 transaction::Output {
-                    to_addr: "Bob".to_owned(),
+                    to_addr: transaction::address(&bob.verifying_key()),
                     value: 7,
+                    script: vec![],
                 },
             ],
+            coinbase: true,
+            coinbase_data: vec![],
+            lock_height: 0,
         },
     ], difficulty);
-    genesis_block.mine();
 
-    println!("Mined genesis block {:?}", &genesis_block);
+    // The demo spends the genesis coinbase in the very next block
+    let mut blockchain = Blockchain::new(difficulty).with_coinbase_maturity(0);
 
-    let mut last_hash = genesis_block.hash.clone();
-    let mut blockchain = Blockchain::new(difficulty);
+    genesis_block.header.utxo_root = blockchain.utxo_root_for(&genesis_block).expect("Invalid genesis block");
+    genesis_block.mine(difficulty).expect("Failed to mine genesis block");
+
+    println!("Mined genesis block {:?}", &genesis_block);
 
     blockchain.update_with_block(genesis_block).expect("Failed to add genesis block");
 
-    let mut block = Block::new(1, now(), last_hash, vec![
+    let mut transaction = Transaction {
+        inputs: vec![
+            transaction::Input::new(OutPoint {
+                txid: blockchain.block(0).unwrap().transactions[0].hash(),
+                index: 0,
+            }),
+        ],
+        outputs: vec![
+            transaction::Output {
+                to_addr: transaction::address(&alice.verifying_key()),
+                value: 36,
+                script: vec![],
+            },
+            transaction::Output {
+                to_addr: transaction::address(&bob.verifying_key()),
+                value: 5,
+                script: vec![],
+            },
+        ],
+        coinbase: false,
+        coinbase_data: vec![],
+        lock_height: 0,
+    };
+    transaction.sign(&alice);
+
+    let mut block = Block::new(1, now(), blockchain.last_hash(), vec![
         Transaction {
             inputs: vec![ ],
             outputs: vec![
                 transaction::Output {
-                    to_addr: "Chris".to_owned(),
-                    value: 536,
-                },
-            ],
-        },
-        Transaction {
-            inputs: vec![
-                blockchain.blocks[0].transactions[0].outputs[0].clone(),
-            ],
-            outputs: vec![
-                transaction::Output {
-                    to_addr: "Alice".to_owned(),
-                    value: 360,
-                },
-                transaction::Output {
-                    to_addr: "Bob".to_owned(),
-                    value: 12,
+                    to_addr: transaction::address(&chris.verifying_key()),
+                    // The block reward plus the 2 left over from alice's 43
+                    value: 52,
+                    script: vec![],
                 },
             ],
+            coinbase: true,
+            coinbase_data: vec![],
+            lock_height: 0,
         },
+        transaction,
     ], difficulty);
 
-    block.mine();
+    block.header.utxo_root = blockchain.utxo_root_for(&block).expect("Invalid block");
+    block.mine(difficulty).expect("Failed to mine block");
 
     println!("Mined block {:?}", &block);
 
-    last_hash = block.hash.clone();
     blockchain.update_with_block(block).expect("Failed to add block");
+
+    println!("Balances: alice {}, bob {}, chris {}",
+        blockchain.get_balance(&transaction::address(&alice.verifying_key())),
+        blockchain.get_balance(&transaction::address(&bob.verifying_key())),
+        blockchain.get_balance(&transaction::address(&chris.verifying_key())),
+    );
 }
@@ -0,0 +1,517 @@
+use super::*;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use crate::transaction::{fee_rate, OutPoint, Output, TxStructureErr};
+
+#[derive(Debug)]
+pub enum MempoolErr {
+    InvalidStructure(TxStructureErr),
+    CoinbaseTransaction,
+    // Spends nothing, which only a coinbase may
+    NoInputs,
+    // Only a coinbase may carry coinbase data
+    InvalidCoinbaseData,
+    TooManyInputsOrOutputs,
+    // Locked until a height above the next block's
+    NotYetValid,
+    DustOutput,
+    InvalidInput,
+    DoubleSpend,
+    InsufficientInputValue,
+    // Pays less per byte than the mempool's min_relay_fee_rate()
+    FeeTooLow,
+    InvalidSignature,
+    ImmatureCoinbaseSpend,
+    // The mempool is full of transactions paying at least as well per byte
+    MempoolFull,
+    Overflow,
+}
+
+struct MempoolEntry {
+    transaction: Transaction,
+    fee: u64,
+    // Serialized size in bytes
+    size: usize,
+}
+
+impl MempoolEntry {
+    // Whether this entry pays less per byte than `fee` over `size` bytes
+    fn pays_less_than (&self, fee: u64, size: usize) -> bool {
+        (self.fee as u128) * (size as u128) < (fee as u128) * (self.size as u128)
+    }
+
+    fn cmp_fee_rate (&self, other: &MempoolEntry) -> Ordering {
+        cmp_fee_rate(self.fee, self.size, other.fee, other.size)
+    }
+}
+
+// Transactions that have been validated against the chain but not yet mined.
+// Held transactions are keyed by their hash under the chain's hasher, so the
+// outputs of one are found at outpoints with that txid.
+pub struct Mempool {
+    entries: HashMap<Hash, MempoolEntry>,
+    // Outpoint -> hash of the mempool transaction spending it
+    spent_outputs: HashMap<OutPoint, Hash>,
+    // Sum of the entries' sizes
+    size: usize,
+    max_size: usize,
+    // Fee per 1000 bytes
+    min_relay_fee_rate: u64,
+}
+
+impl Mempool {
+    // Unbounded, see with_max_size()
+    pub fn new () -> Self {
+        Mempool {
+            entries: HashMap::new(),
+            spent_outputs: HashMap::new(),
+            size: 0,
+            max_size: usize::MAX,
+            min_relay_fee_rate: 0,
+        }
+    }
+
+    // Caps the total serialized size of the transactions held. Once full,
+    // the transactions paying the least per byte make way for better paying
+    // ones.
+    pub fn with_max_size (mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    // The least a transaction must pay per 1000 bytes to be accepted, as a
+    // guard against spam; blocks may still include transactions paying less.
+    // Defaults to 0.
+    pub fn with_min_relay_fee_rate (mut self, min_relay_fee_rate: u64) -> Self {
+        self.min_relay_fee_rate = min_relay_fee_rate;
+        self
+    }
+
+    pub fn min_relay_fee_rate (&self) -> u64 {
+        self.min_relay_fee_rate
+    }
+
+    // Only applies to transactions added from now on
+    pub fn set_min_relay_fee_rate (&mut self, min_relay_fee_rate: u64) {
+        self.min_relay_fee_rate = min_relay_fee_rate;
+    }
+
+    pub fn len (&self) -> usize {
+        self.entries.len()
+    }
+
+    // Total serialized size of the transactions held, in bytes
+    pub fn size (&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty (&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains (&self, tx_hash: &Hash) -> bool {
+        self.entries.contains_key(tx_hash)
+    }
+
+    pub fn get (&self, tx_hash: &Hash) -> Option<&Transaction> {
+        self.entries.get(tx_hash).map(|entry| &entry.transaction)
+    }
+
+    // Whether a held transaction spends the output
+    pub fn is_spent (&self, outpoint: &OutPoint) -> bool {
+        self.spent_outputs.contains_key(outpoint)
+    }
+
+    // Hashes of the transactions held, sorted, for advertising to peers
+    pub fn inventory (&self) -> Vec<Hash> {
+        let mut hashes = self.entries.keys().cloned().collect::<Vec<Hash>>();
+        hashes.sort_unstable();
+        hashes
+    }
+
+    // The transactions in a peer's inventory that this mempool doesn't hold,
+    // in the peer's order and without repeats, i.e. the ones to ask it for
+    pub fn missing (&self, their_inv: &[Hash]) -> Vec<Hash> {
+        let mut seen = HashSet::new();
+
+        their_inv
+            .iter()
+            .filter(|hash| !self.contains(hash) && seen.insert(**hash))
+            .cloned()
+            .collect()
+    }
+
+    // A transaction spending an output some mempool transaction already spends
+    // replaces it (and whatever depends on it) if it pays a higher fee than
+    // everything it would displace put together: the transactions it
+    // conflicts with and all their descendants. Otherwise it is rejected as a
+    // DoubleSpend.
+    pub fn add_transaction (&mut self, transaction: Transaction, chain: &Blockchain) -> Result<(), MempoolErr> {
+        let hash = chain.hash_of(&transaction);
+
+        // Rejections are routine for a mempool, so not worth a warning
+        self.insert(hash, transaction, chain)
+            .inspect_err(|err| log_debug!("rejected transaction {}: {:?}", hash, err))
+    }
+
+    fn insert (&mut self, hash: Hash, transaction: Transaction, chain: &Blockchain) -> Result<(), MempoolErr> {
+        transaction.verify_structure().map_err(MempoolErr::InvalidStructure)?;
+
+        if transaction.is_coinbase() {
+            return Err(MempoolErr::CoinbaseTransaction);
+        } else if transaction.inputs.is_empty() {
+            return Err(MempoolErr::NoInputs);
+        } else if !transaction.coinbase_data.is_empty() {
+            return Err(MempoolErr::InvalidCoinbaseData);
+        } else if chain.has_too_many_inputs_or_outputs(&transaction) {
+            return Err(MempoolErr::TooManyInputsOrOutputs);
+        } else if transaction.lock_height > chain.block_count() as u32 {
+            return Err(MempoolErr::NotYetValid);
+        } else if transaction.outputs.iter().any(|output| chain.is_dust(output)) {
+            return Err(MempoolErr::DustOutput);
+        }
+
+        let mut input_value: u64 = 0;
+        let mut conflicts = HashSet::new();
+
+        for outpoint in transaction.spent_outpoints() {
+            if let Some(spender) = self.spent_outputs.get(outpoint) {
+                conflicts.insert(*spender);
+            }
+
+            // Judged against the next block, the earliest this could be mined in
+            if !chain.is_mature(outpoint, chain.block_count() as u32) {
+                return Err(MempoolErr::ImmatureCoinbaseSpend);
+            }
+
+            match self.resolve_output(outpoint, chain) {
+                Some(output) => {
+                    input_value = input_value.checked_add(output.value)
+                        .ok_or(MempoolErr::Overflow)?;
+                },
+                None => return Err(MempoolErr::InvalidInput),
+            }
+        }
+
+        for input in &transaction.inputs {
+            let owner = self.resolve_output(&input.previous_output, chain)
+                .ok_or(MempoolErr::InvalidInput)?;
+
+            if !transaction.verify_input(input, owner) {
+                return Err(MempoolErr::InvalidSignature);
+            }
+        }
+
+        let output_value = transaction.output_value()
+            .ok_or(MempoolErr::Overflow)?;
+
+        if output_value > input_value {
+            return Err(MempoolErr::InsufficientInputValue);
+        }
+
+        let fee = input_value - output_value;
+        let size = transaction.size();
+
+        if (fee as u128) * 1000 < (self.min_relay_fee_rate as u128) * (size as u128) {
+            return Err(MempoolErr::FeeTooLow);
+        }
+
+        let replaced_fee = self.with_descendants(conflicts.iter())
+            .iter()
+            .map(|hash| self.entries[hash].fee)
+            .fold(0u64, |sum, fee| sum.saturating_add(fee));
+
+        if !conflicts.is_empty() && fee <= replaced_fee {
+            return Err(MempoolErr::DoubleSpend);
+        }
+
+        let evicted = self.make_room(size, fee, &conflicts)?;
+
+        // Pushing out a transaction this one spends an output of would leave
+        // it spending nothing
+        let removed = self.with_descendants(conflicts.iter().chain(&evicted));
+        if transaction.spent_outpoints().any(|outpoint| removed.contains(&outpoint.txid)) {
+            return Err(MempoolErr::InvalidInput);
+        }
+
+        for conflict in &conflicts {
+            log_debug!("transaction {} replaces {}", hash, conflict);
+            self.remove_with_descendants(conflict);
+        }
+        for evictee in &evicted {
+            log_debug!("transaction {} evicts {}", hash, evictee);
+            self.remove_with_descendants(evictee);
+        }
+
+        log_debug!("added transaction {} paying {} for {} bytes", hash, fee, size);
+        self.spent_outputs.extend(transaction.spent_outpoints().map(|outpoint| (*outpoint, hash)));
+        self.size += size;
+        self.entries.insert(hash, MempoolEntry {
+            transaction,
+            fee,
+            size,
+        });
+
+        Ok(())
+    }
+
+    // The output an input names: unspent on the chain, or created by a held
+    // transaction, which is never a coinbase and so always mature
+    fn resolve_output<'a> (&'a self, outpoint: &OutPoint, chain: &'a Blockchain) -> Option<&'a Output> {
+        if let Some(output) = chain.unspent_output(outpoint) {
+            return Some(output);
+        }
+
+        self.entries.get(&outpoint.txid)?.transaction.outputs.get(outpoint.index as usize)
+    }
+
+    // Hashes of the held transactions spending the outputs of `hash`
+    fn children (&self, hash: &Hash) -> Vec<Hash> {
+        let outputs = self.entries.get(hash).map_or(0, |entry| entry.transaction.outputs.len());
+
+        (0..outputs as u32)
+            .filter_map(|index| self.spent_outputs.get(&OutPoint { txid: *hash, index }).cloned())
+            .collect()
+    }
+
+    // The entries to evict, lowest fee per byte first, for a transaction of
+    // `size` bytes paying `fee` to fit once `replaced` are gone. Fails if that
+    // would mean evicting anything paying as well as the new transaction.
+    fn make_room (&self, size: usize, fee: u64, replaced: &HashSet<Hash>) -> Result<Vec<Hash>, MempoolErr> {
+        if size > self.max_size {
+            return Err(MempoolErr::MempoolFull);
+        }
+
+        let mut remaining = self.size - replaced
+            .iter()
+            .map(|hash| self.entries[hash].size)
+            .sum::<usize>();
+
+        let mut candidates = self.entries
+            .iter()
+            .filter(|(hash, _)| !replaced.contains(*hash))
+            .collect::<Vec<_>>();
+        candidates.sort_by(|(a_hash, a), (b_hash, b)| a.cmp_fee_rate(b).then(a_hash.cmp(b_hash)));
+
+        let mut evicted = vec![];
+        for (hash, entry) in candidates {
+            if remaining + size <= self.max_size {
+                break;
+            } else if !entry.pays_less_than(fee, size) {
+                return Err(MempoolErr::MempoolFull);
+            }
+
+            remaining -= entry.size;
+            evicted.push(*hash);
+        }
+
+        Ok(evicted)
+    }
+
+    // Drops the transactions `block` confirmed, e.g. once it has been added
+    // to the chain, along with any that conflict with them and whatever
+    // depends on those. Transactions spending the outputs of a confirmed one
+    // stay, as those outputs are now on the chain.
+    pub fn remove_confirmed (&mut self, block: &Block) {
+        for transaction in &block.transactions {
+            let bytes = transaction.bytes();
+
+            for outpoint in transaction.spent_outpoints() {
+                let spender = match self.spent_outputs.get(outpoint) {
+                    Some(spender) => *spender,
+                    None => continue,
+                };
+
+                // Compared without witnesses, like the hash: a held copy
+                // signed differently is still the transaction confirmed
+                if self.entries[&spender].transaction.bytes() == bytes {
+                    self.remove_entry(&spender);
+                } else {
+                    log_debug!("transaction {} conflicts with block {}", spender, block.header.index);
+                    self.remove_with_descendants(&spender);
+                }
+            }
+        }
+    }
+
+    // Removes the transaction alone, returning whether it was held
+    fn remove_entry (&mut self, hash: &Hash) -> bool {
+        let entry = match self.entries.remove(hash) {
+            Some(entry) => entry,
+            None => return false,
+        };
+
+        for outpoint in entry.transaction.spent_outpoints() {
+            self.spent_outputs.remove(outpoint);
+        }
+        self.size -= entry.size;
+
+        true
+    }
+
+    // Removes the transaction and any that spend its outputs
+    fn remove_with_descendants (&mut self, hash: &Hash) {
+        let descendants = self.children(hash);
+
+        if self.remove_entry(hash) {
+            for descendant in descendants {
+                self.remove_with_descendants(&descendant);
+            }
+        }
+    }
+
+    // The transactions remove_with_descendants() would remove for `hashes`
+    fn with_descendants<'a, I: Iterator<Item = &'a Hash>> (&self, hashes: I) -> HashSet<Hash> {
+        let mut found = HashSet::new();
+        let mut pending = hashes.cloned().collect::<Vec<Hash>>();
+
+        while let Some(hash) = pending.pop() {
+            if self.entries.contains_key(&hash) && found.insert(hash) {
+                pending.extend(self.children(&hash));
+            }
+        }
+
+        found
+    }
+
+    // Fee per byte of a held transaction, see transaction::fee_rate()
+    pub fn fee_rate (&self, tx_hash: &Hash) -> Option<f64> {
+        self.entries.get(tx_hash).map(|entry| fee_rate(entry.fee, entry.size))
+    }
+
+    // Packages of a transaction and the held ancestors it needs mined with
+    // it, highest fee per byte across the whole package first, so a child
+    // paying well pulls in a parent paying too little on its own (child pays
+    // for parent). A package goes in whole, ancestors first, or not at all if
+    // it doesn't fit in `max_count`. Ties are broken by hash so every node
+    // picks the same set. Put the selection through canonical_order() before
+    // mining it.
+    pub fn select_for_block (&self, max_count: usize) -> Vec<Transaction> {
+        let mut selected = HashSet::new();
+        let mut too_large = HashSet::new();
+        let mut transactions = vec![];
+        // Each package's current fee and size. Scores are pushed again as
+        // packages shrink rather than updated in the heap, so popped ones
+        // that no longer match are stale and skipped.
+        let mut scores = HashMap::new();
+        let mut heap = BinaryHeap::new();
+
+        for hash in self.entries.keys() {
+            let score = self.package_score(hash, &selected);
+            scores.insert(*hash, (score.fee, score.size));
+            heap.push(score);
+        }
+
+        while transactions.len() < max_count {
+            let best = match heap.pop() {
+                Some(best) if scores[&best.hash] != (best.fee, best.size) => continue,
+                Some(best) => best.hash,
+                None => break,
+            };
+
+            if selected.contains(&best) || too_large.contains(&best) {
+                continue;
+            }
+
+            let package = self.package(&best, &selected);
+            if transactions.len() + package.len() > max_count {
+                too_large.insert(best);
+                continue;
+            }
+
+            for hash in &package {
+                transactions.push(self.entries[hash].transaction.clone());
+                selected.insert(*hash);
+            }
+
+            // Only the packages of what descends from the selection shrank
+            for hash in self.with_descendants(package.iter()) {
+                if !selected.contains(&hash) {
+                    let score = self.package_score(&hash, &selected);
+                    scores.insert(hash, (score.fee, score.size));
+                    heap.push(score);
+                }
+            }
+        }
+
+        transactions
+    }
+
+    fn package_score (&self, hash: &Hash, selected: &HashSet<Hash>) -> PackageScore {
+        let (fee, size) = self.package(hash, selected)
+            .iter()
+            .map(|hash| &self.entries[hash])
+            .fold((0u64, 0usize), |(fee, size), entry| (fee.saturating_add(entry.fee), size + entry.size));
+
+        PackageScore { fee, size, hash: *hash }
+    }
+
+    // `hash` and its held ancestors not already `selected`, parents before
+    // children
+    fn package (&self, hash: &Hash, selected: &HashSet<Hash>) -> Vec<Hash> {
+        let mut package = vec![];
+        self.add_to_package(hash, selected, &mut HashSet::new(), &mut package);
+        package
+    }
+
+    fn add_to_package (&self, hash: &Hash, selected: &HashSet<Hash>, visited: &mut HashSet<Hash>, package: &mut Vec<Hash>) {
+        if selected.contains(hash) || !visited.insert(*hash) {
+            return;
+        }
+
+        let mut parents = self.entries[hash].transaction
+            .spent_outpoints()
+            .map(|outpoint| outpoint.txid)
+            .filter(|txid| self.entries.contains_key(txid))
+            .collect::<Vec<Hash>>();
+        parents.sort_unstable();
+        parents.dedup();
+
+        for parent in &parents {
+            self.add_to_package(parent, selected, visited, package);
+        }
+
+        package.push(*hash);
+    }
+}
+
+// A package in select_for_block()'s heap, highest fee per byte first and the
+// lowest hash on a tie
+struct PackageScore {
+    fee: u64,
+    size: usize,
+    hash: Hash,
+}
+
+impl Ord for PackageScore {
+    fn cmp (&self, other: &Self) -> Ordering {
+        cmp_fee_rate(self.fee, self.size, other.fee, other.size)
+            .then(other.hash.cmp(&self.hash))
+    }
+}
+
+impl PartialOrd for PackageScore {
+    fn partial_cmp (&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for PackageScore {
+    fn eq (&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for PackageScore {}
+
+// Orders `fee` over `size` bytes against `other_fee` over `other_size` by fee
+// per byte, compared exactly rather than as floats
+fn cmp_fee_rate (fee: u64, size: usize, other_fee: u64, other_size: usize) -> Ordering {
+    (fee as u128 * other_size as u128).cmp(&(other_fee as u128 * size as u128))
+}
+
+impl Default for Mempool {
+    fn default () -> Self {
+        Mempool::new()
+    }
+}
@@ -0,0 +1,147 @@
+use super::*;
+
+fn hash_pair (left: &Hash, right: &Hash, hasher: &dyn Hasher<Output = Hash>) -> Hash {
+    let mut bytes = vec![];
+    bytes.extend(left.as_bytes());
+    bytes.extend(right.as_bytes());
+    hasher.digest(&bytes)
+}
+
+// Binary Merkle tree over the given leaves. Like Bitcoin, a level with an odd
+// number of nodes has its last node paired with itself. A single leaf is its
+// own root, and no leaves at all give the zero hash.
+pub fn merkle_root (leaves: Vec<Hash>) -> Hash {
+    merkle_root_with(leaves, &Sha256)
+}
+
+// merkle_root() with the nodes hashed by `hasher`
+pub fn merkle_root_with (leaves: Vec<Hash>, hasher: &dyn Hasher<Output = Hash>) -> Hash {
+    if leaves.is_empty() {
+        return Hash::zero();
+    }
+
+    let mut level = leaves;
+
+    while level.len() > 1 {
+        level = next_level(&level, hasher);
+    }
+
+    level[0]
+}
+
+fn next_level (level: &[Hash], hasher: &dyn Hasher<Output = Hash>) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| hash_pair(&pair[0], pair.last().unwrap(), hasher))
+        .collect()
+}
+
+// The sibling hashes on the path from leaf `index` up to the root, each with
+// a flag that is true when the sibling sits on the left. None if there is no
+// leaf at `index`.
+pub fn merkle_proof (leaves: Vec<Hash>, index: usize) -> Option<Vec<(Hash, bool)>> {
+    merkle_proof_with(leaves, index, &Sha256)
+}
+
+// merkle_proof() in a tree whose nodes are hashed by `hasher`, as
+// merkle_root_with() builds it
+pub fn merkle_proof_with (leaves: Vec<Hash>, mut index: usize, hasher: &dyn Hasher<Output = Hash>) -> Option<Vec<(Hash, bool)>> {
+    if index >= leaves.len() {
+        return None;
+    }
+
+    let mut proof = vec![];
+    let mut level = leaves;
+
+    while level.len() > 1 {
+        let sibling = if index.is_multiple_of(2) {
+            // An odd node out is paired with itself
+            (*level.get(index + 1).unwrap_or(&level[index]), false)
+        } else {
+            (level[index - 1], true)
+        };
+
+        proof.push(sibling);
+        level = next_level(&level, hasher);
+        index /= 2;
+    }
+
+    Some(proof)
+}
+
+pub fn verify_merkle_proof (leaf: &Hash, proof: &[(Hash, bool)], root: &Hash) -> bool {
+    verify_merkle_proof_with(leaf, proof, root, &Sha256)
+}
+
+pub fn verify_merkle_proof_with (leaf: &Hash, proof: &[(Hash, bool)], root: &Hash, hasher: &dyn Hasher<Output = Hash>) -> bool {
+    let computed = proof
+        .iter()
+        .fold(*leaf, |hash, (sibling, is_left)| if *is_left {
+            hash_pair(sibling, &hash, hasher)
+        } else {
+            hash_pair(&hash, sibling, hasher)
+        });
+
+    &computed == root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves (count: u8) -> Vec<Hash> {
+        (0..count).map(|i| Hash::digest(&[i])).collect()
+    }
+
+    #[test]
+    fn pairs_up_one_to_four_leaves () {
+        let [a, b, c, d] = [0, 1, 2, 3].map(|i| Hash::digest(&[i]));
+        let pair = |left: &Hash, right: &Hash| hash_pair(left, right, &Sha256);
+
+        assert_eq!(merkle_root(vec![a]), a);
+        assert_eq!(merkle_root(vec![a, b]), pair(&a, &b));
+        assert_eq!(merkle_root(vec![a, b, c]), pair(&pair(&a, &b), &pair(&c, &c)));
+        assert_eq!(merkle_root(vec![a, b, c, d]), pair(&pair(&a, &b), &pair(&c, &d)));
+    }
+
+    #[test]
+    fn changing_any_leaf_changes_the_root () {
+        for count in 1..=4 {
+            let root = merkle_root(leaves(count));
+
+            for index in 0..count as usize {
+                let mut changed = leaves(count);
+                changed[index] = Hash::digest(b"changed");
+                assert_ne!(merkle_root(changed), root, "leaf {} of {}", index, count);
+            }
+        }
+    }
+
+    #[test]
+    fn proofs_verify_for_every_leaf () {
+        for count in 1..=9 {
+            let leaves = leaves(count);
+            let root = merkle_root(leaves.clone());
+
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = merkle_proof(leaves.clone(), index).unwrap();
+                assert!(verify_merkle_proof(leaf, &proof, &root), "leaf {} of {}", index, count);
+            }
+        }
+    }
+
+    #[test]
+    fn proof_fails_for_another_leaf () {
+        let leaves = leaves(5);
+        let root = merkle_root(leaves.clone());
+        let proof = merkle_proof(leaves.clone(), 1).unwrap();
+
+        assert!(!verify_merkle_proof(&leaves[2], &proof, &root));
+    }
+
+    #[test]
+    fn no_proof_past_the_last_leaf () {
+        assert!(merkle_proof(vec![], 0).is_none());
+        assert!(merkle_proof(leaves(3), 3).is_none());
+    }
+}
@@ -0,0 +1,191 @@
+// Block and transaction gossip between nodes over TCP. Every message is sent
+// as a little-endian u32 length followed by that many bytes of bincode.
+
+use super::*;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use serde::{Deserialize, Serialize};
+
+// Larger frames are refused rather than allocated
+pub const MAX_MESSAGE_SIZE: u32 = 32 * 1024 * 1024;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Message {
+    // Sent on connecting: how many blocks the sender has, and its tip
+    Version { height: u32, tip: Hash },
+    // Asks for every block after the last one the sender shares with the
+    // receiver, given the sender's Blockchain::block_locator()
+    GetBlocks { locator: Vec<Hash> },
+    Block(Block),
+    Tx(Transaction),
+    // Announces blocks by hash
+    Inv(Vec<Hash>),
+    // Asks for the receiver's mempool inventory, answered with TxInv
+    GetMempool,
+    // Announces mempool transactions by hash
+    TxInv(Vec<Hash>),
+    // Asks for mempool transactions by hash, answered with a Tx for each
+    // one the receiver holds
+    GetTxs(Vec<Hash>),
+}
+
+#[derive(Debug)]
+pub enum NetError {
+    Io(io::Error),
+    Decode(bincode::Error),
+    MessageTooLarge,
+    // The peer sent something that doesn't validate
+    InvalidBlock(BlockValidationErr),
+    InvalidTransaction(MempoolErr),
+}
+
+impl From<io::Error> for NetError {
+    fn from(err: io::Error) -> Self {
+        NetError::Io(err)
+    }
+}
+
+impl From<bincode::Error> for NetError {
+    fn from(err: bincode::Error) -> Self {
+        NetError::Decode(err)
+    }
+}
+
+impl Message {
+    // The framed message, length prefix included
+    pub fn encode (&self) -> Result<Vec<u8>, NetError> {
+        let payload = bincode::serialize(self)?;
+        if payload.len() > MAX_MESSAGE_SIZE as usize {
+            return Err(NetError::MessageTooLarge);
+        }
+
+        let mut bytes = u32_bytes(&(payload.len() as u32)).to_vec();
+        bytes.extend(payload);
+
+        Ok(bytes)
+    }
+
+    pub fn write_to<W: Write> (&self, writer: &mut W) -> Result<(), NetError> {
+        writer.write_all(&self.encode()?)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    // Reads one framed message
+    pub fn read_from<R: Read> (reader: &mut R) -> Result<Message, NetError> {
+        let mut length = [0u8; 4];
+        reader.read_exact(&mut length)?;
+
+        let length = u32::from_le_bytes(length);
+        if length > MAX_MESSAGE_SIZE {
+            return Err(NetError::MessageTooLarge);
+        }
+
+        let mut payload = vec![0u8; length as usize];
+        reader.read_exact(&mut payload)?;
+
+        Ok(bincode::deserialize(&payload)?)
+    }
+}
+
+pub struct Peer {
+    stream: TcpStream,
+}
+
+impl Peer {
+    pub fn connect<A: ToSocketAddrs> (addr: A) -> Result<Self, NetError> {
+        Ok(Peer::new(TcpStream::connect(addr)?))
+    }
+
+    // Wraps an incoming connection, e.g. from TcpListener::accept()
+    pub fn new (stream: TcpStream) -> Self {
+        Peer {
+            stream,
+        }
+    }
+
+    pub fn send (&mut self, message: &Message) -> Result<(), NetError> {
+        message.write_to(&mut self.stream)
+    }
+
+    // Blocks until the peer sends a message
+    pub fn receive (&mut self) -> Result<Message, NetError> {
+        Message::read_from(&mut self.stream)
+    }
+
+    pub fn send_version (&mut self, chain: &Blockchain) -> Result<(), NetError> {
+        self.send(&Message::Version {
+            height: chain.block_count() as u32,
+            tip: chain.last_hash(),
+        })
+    }
+
+    // Asks for the blocks this chain is missing
+    pub fn request_blocks (&mut self, chain: &Blockchain) -> Result<(), NetError> {
+        self.send(&Message::GetBlocks {
+            locator: chain.block_locator(),
+        })
+    }
+
+    // Reacts to a message from the peer: catches up with peers that are
+    // ahead, serves blocks and mempool transactions they ask for, asks for
+    // announced transactions this node lacks, and feeds received blocks
+    // through Blockchain::add_block() and transactions into the mempool,
+    // dropping from the mempool what the blocks confirm.
+    pub fn handle (&mut self, message: Message, chain: &mut Blockchain, mempool: &mut Mempool) -> Result<(), NetError> {
+        match message {
+            Message::Version { height, .. } => {
+                if height as usize > chain.block_count() {
+                    self.request_blocks(chain)?;
+                }
+            },
+            Message::GetBlocks { locator } => {
+                // With nothing in common, start from genesis
+                let from = chain.find_fork_point(&locator).map_or(0, |index| index + 1);
+
+                for block in chain.blocks_from(from) {
+                    self.send(&Message::Block(block.clone()))?;
+                }
+            },
+            Message::Block(block) => {
+                // Where the chain was, so the blocks it connects can be told
+                // apart even if it switches branches
+                let locator = chain.block_locator();
+
+                if chain.add_block(block).map_err(NetError::InvalidBlock)? {
+                    let from = chain.find_fork_point(&locator).map_or(0, |index| index + 1);
+
+                    for block in chain.blocks_from(from) {
+                        mempool.remove_confirmed(block);
+                    }
+                }
+            },
+            Message::Tx(transaction) => {
+                mempool.add_transaction(transaction, chain).map_err(NetError::InvalidTransaction)?;
+            },
+            Message::Inv(hashes) => {
+                if hashes.iter().any(|hash| chain.get_block_by_hash(hash).is_none()) {
+                    self.request_blocks(chain)?;
+                }
+            },
+            Message::GetMempool => {
+                self.send(&Message::TxInv(mempool.inventory()))?;
+            },
+            Message::TxInv(hashes) => {
+                let missing = mempool.missing(&hashes);
+
+                if !missing.is_empty() {
+                    self.send(&Message::GetTxs(missing))?;
+                }
+            },
+            Message::GetTxs(hashes) => {
+                for transaction in hashes.iter().filter_map(|hash| mempool.get(hash)) {
+                    self.send(&Message::Tx(transaction.clone()))?;
+                }
+            },
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,283 @@
+// A minimal JSON-RPC 2.0 server over HTTP, for querying a running chain.
+// Requests are POSTed as e.g. {"jsonrpc": "2.0", "method": "getblockcount",
+// "params": [], "id": 1}. Supported methods:
+//
+//     getblockcount                   number of blocks
+//     getblock <hash>                 the block with that hash
+//     getbalance <address>            unspent value paid to the address
+//     sendrawtransaction <hex>        adds a hex encoded (bincode) transaction
+//                                     to the mempool, returning its hash
+//
+// Connections are served one at a time on the calling thread, so each one
+// is held to a read timeout and to limits on its headers.
+
+use super::*;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+// Standard JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+// Application defined: the request was well formed but couldn't be carried out
+const REJECTED: i64 = -1;
+
+// Largest request body accepted, in bytes; larger ones get a 413
+const MAX_BODY_SIZE: usize = 1 << 20;
+// Most bytes and lines the request line and headers may take together;
+// more get a 431
+const MAX_HEADER_SIZE: u64 = 8 * 1024;
+const MAX_HEADER_LINES: usize = 100;
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Results are JSON text rather than a Value, since a Value can't hold the
+// u128 fields blocks have
+type RpcResult = Result<String, (i64, String)>;
+
+pub struct RpcServer {
+    chain: SharedBlockchain,
+    mempool: Arc<Mutex<Mempool>>,
+    // How long a read from a client may stall before its connection is
+    // dropped
+    read_timeout: Duration,
+}
+
+impl RpcServer {
+    pub fn new (chain: SharedBlockchain, mempool: Arc<Mutex<Mempool>>) -> Self {
+        RpcServer {
+            chain,
+            mempool,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+        }
+    }
+
+    pub fn with_read_timeout (mut self, read_timeout: Duration) -> Self {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    // Serves requests until accepting a connection fails. Errors on a single
+    // connection only drop that connection.
+    pub fn serve<A: ToSocketAddrs> (&self, addr: A) -> io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+
+        for stream in listener.incoming() {
+            let _ = self.handle_connection(stream?);
+        }
+
+        Ok(())
+    }
+
+    fn handle_connection (&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(self.read_timeout))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut headers = reader.by_ref().take(MAX_HEADER_SIZE);
+        let mut content_length = Some(0);
+
+        for lines in 1.. {
+            let mut line = String::new();
+            let read = headers.read_line(&mut line)?;
+
+            // A line cut off by the limit would go on past it
+            if headers.limit() == 0 && !line.ends_with('\n') {
+                return write_response(stream, "431 Request Header Fields Too Large", "");
+            } else if read == 0 || line.trim().is_empty() {
+                break;
+            } else if lines > MAX_HEADER_LINES {
+                return write_response(stream, "431 Request Header Fields Too Large", "");
+            }
+
+            if let Some((name, value)) = line.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().ok();
+                }
+            }
+        }
+
+        // The length is the client's word, so it's checked before anything
+        // is allocated for the body
+        let content_length = match content_length {
+            Some(content_length) if content_length <= MAX_BODY_SIZE => content_length,
+            Some(_) => return write_response(stream, "413 Payload Too Large", ""),
+            None => return write_response(stream, "400 Bad Request", ""),
+        };
+
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        let response = self.handle(&String::from_utf8_lossy(&body));
+        write_response(stream, "200 OK", &response)
+    }
+
+    // Handles a single JSON-RPC request, returning the JSON response
+    pub fn handle (&self, request: &str) -> String {
+        let request: Value = match serde_json::from_str(request) {
+            Ok(request) => request,
+            Err(err) => return response(Value::Null, Err((PARSE_ERROR, err.to_string()))),
+        };
+
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let method = match request.get("method").and_then(Value::as_str) {
+            Some(method) => method,
+            None => return response(id, Err((INVALID_REQUEST, "missing method".to_string()))),
+        };
+        let params = request.get("params")
+            .and_then(Value::as_array)
+            .cloned()
+            .unwrap_or_default();
+
+        let result = match method {
+            "getblockcount" => self.get_block_count(),
+            "getblock" => self.get_block(&params),
+            "getbalance" => self.get_balance(&params),
+            "sendrawtransaction" => self.send_raw_transaction(&params),
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method {}", method))),
+        };
+
+        response(id, result)
+    }
+
+    fn get_block_count (&self) -> RpcResult {
+        to_json(&self.chain.block_count())
+    }
+
+    fn get_block (&self, params: &[Value]) -> RpcResult {
+        let hash = string_param(params, 0)?
+            .parse::<Hash>()
+            .map_err(|err| (INVALID_PARAMS, format!("invalid hash: {:?}", err)))?;
+
+        let chain = self.chain.read();
+        let block = chain.get_block_by_hash(&hash)
+            .ok_or((REJECTED, "block not found".to_string()))?;
+
+        to_json(block)
+    }
+
+    fn get_balance (&self, params: &[Value]) -> RpcResult {
+        let address = string_param(params, 0)?
+            .parse::<Address>()
+            .map_err(|err| (INVALID_PARAMS, format!("invalid address: {:?}", err)))?;
+
+        to_json(&self.chain.get_balance(&address))
+    }
+
+    fn send_raw_transaction (&self, params: &[Value]) -> RpcResult {
+        let bytes = hex::decode(string_param(params, 0)?)
+            .map_err(|err| (INVALID_PARAMS, format!("invalid hex: {:?}", err)))?;
+        let transaction: Transaction = bincode::deserialize(&bytes)
+            .map_err(|err| (INVALID_PARAMS, format!("invalid transaction: {}", err)))?;
+        let chain = self.chain.read();
+        let hash = chain.hash_of(&transaction);
+        self.mempool.lock().unwrap()
+            .add_transaction(transaction, &chain)
+            .map_err(|err| (REJECTED, format!("{:?}", err)))?;
+
+        to_json(&hash)
+    }
+}
+
+fn write_response (mut stream: TcpStream, status: &str, body: &str) -> io::Result<()> {
+    write!(stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body,
+    )?;
+    stream.flush()
+}
+
+fn string_param (params: &[Value], position: usize) -> Result<&str, (i64, String)> {
+    params.get(position)
+        .and_then(Value::as_str)
+        .ok_or((INVALID_PARAMS, format!("expected a string parameter at position {}", position)))
+}
+
+fn to_json<T: Serialize + ?Sized> (value: &T) -> RpcResult {
+    serde_json::to_string(value).map_err(|err| (REJECTED, err.to_string()))
+}
+
+fn response (id: Value, result: RpcResult) -> String {
+    match result {
+        Ok(result) => format!(r#"{{"jsonrpc":"2.0","result":{},"id":{}}}"#, result, id),
+        Err((code, message)) => json!({
+            "jsonrpc": "2.0",
+            "error": { "code": code, "message": message },
+            "id": id,
+        }).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    // Sends `request` to a connection the server handles, returning the
+    // status line of the response
+    fn status_for (request: &str) -> String {
+        let server = RpcServer::new(SharedBlockchain::new(Blockchain::new(u128::MAX)), Arc::new(Mutex::new(Mempool::new())));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let (stream, _) = listener.accept().unwrap();
+                let _ = server.handle_connection(stream);
+            });
+
+            let mut client = TcpStream::connect(addr).unwrap();
+            client.write_all(request.as_bytes()).unwrap();
+
+            let mut status = String::new();
+            BufReader::new(client).read_line(&mut status).unwrap();
+            status.trim().to_string()
+        })
+    }
+
+    #[test]
+    fn refuses_oversized_bodies_before_reading_them () {
+        let request = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_SIZE + 1);
+        assert_eq!(status_for(&request), "HTTP/1.1 413 Payload Too Large");
+    }
+
+    #[test]
+    fn refuses_malformed_lengths () {
+        assert_eq!(status_for("POST / HTTP/1.1\r\nContent-Length: lots\r\n\r\n"), "HTTP/1.1 400 Bad Request");
+    }
+
+    #[test]
+    fn refuses_endless_header_lines () {
+        let request = "X".repeat(MAX_HEADER_SIZE as usize);
+        assert_eq!(status_for(&request), "HTTP/1.1 431 Request Header Fields Too Large");
+    }
+
+    #[test]
+    fn refuses_too_many_headers () {
+        let request = format!("POST / HTTP/1.1\r\n{}", "X: y\r\n".repeat(MAX_HEADER_LINES));
+        assert_eq!(status_for(&request), "HTTP/1.1 431 Request Header Fields Too Large");
+    }
+
+    #[test]
+    fn drops_clients_that_stall () {
+        let server = RpcServer::new(SharedBlockchain::new(Blockchain::new(u128::MAX)), Arc::new(Mutex::new(Mempool::new())))
+            .with_read_timeout(Duration::from_millis(100));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(b"POST / HTTP/1.1\r\n").unwrap();
+
+        let (stream, _) = listener.accept().unwrap();
+        assert!(server.handle_connection(stream).is_err());
+    }
+
+    #[test]
+    fn answers_requests () {
+        let body = r#"{"jsonrpc":"2.0","method":"getblockcount","params":[],"id":1}"#;
+        let request = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+        assert_eq!(status_for(&request), "HTTP/1.1 200 OK");
+    }
+}
@@ -0,0 +1,155 @@
+use super::*;
+use ed25519_dalek::VerifyingKey;
+
+// A tiny stack language for locking outputs, modelled on Bitcoin Script but
+// with a handful of opcodes and no branches or loops, so a script runs in
+// time linear in its length. An output's script is run after its spender's
+// witness, which may only push data, and the spend is valid if the top of
+// the stack is then true: non-empty with a non-zero byte.
+
+// Opcodes 0x01 to 0x4b push that many following bytes
+pub const OP_0: u8 = 0x00;
+pub const OP_PUSH_MAX: u8 = 0x4b;
+pub const OP_1: u8 = 0x51;
+pub const OP_VERIFY: u8 = 0x69;
+pub const OP_DUP: u8 = 0x76;
+pub const OP_EQUAL: u8 = 0x87;
+pub const OP_EQUALVERIFY: u8 = 0x88;
+// Replaces the top item with its SHA-256 digest, whatever Hasher the chain
+// uses
+pub const OP_SHA256: u8 = 0xa8;
+// Pops a public key and then a signature and pushes whether the signature
+// is the key's over the spending transaction's signing_hash()
+pub const OP_CHECKSIG: u8 = 0xac;
+
+// Longest script or witness, in bytes
+pub const MAX_SCRIPT_SIZE: usize = 1_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptError {
+    TooLarge,
+    // A push runs past the end of the script
+    Truncated,
+    UnknownOpcode(u8),
+    // The witness contains something other than pushes
+    NonPushWitness,
+    StackUnderflow,
+    VerifyFailed,
+    // The script ran to the end without leaving true on the stack
+    Failed,
+}
+
+// Pays to the holder of `public_key`: the witness must push a signature and
+// then the key itself, see p2pkh_witness()
+pub fn p2pkh (public_key: &VerifyingKey) -> Vec<u8> {
+    pay_to_address(&Address::from_pubkey(public_key))
+}
+
+// p2pkh() for the key `address` is the hash of. Outputs without a script of
+// their own are spent as if they had this one.
+pub fn pay_to_address (address: &Address) -> Vec<u8> {
+    let mut script = vec![OP_DUP, OP_SHA256];
+    script.extend(push(address.as_bytes()));
+    script.extend(&[OP_EQUALVERIFY, OP_CHECKSIG]);
+    script
+}
+
+pub fn p2pkh_witness (signature: &[u8], public_key: &VerifyingKey) -> Vec<u8> {
+    let mut witness = push(signature);
+    witness.extend(push(public_key.as_bytes()));
+    witness
+}
+
+// The opcodes pushing `data`. Panics if it is longer than OP_PUSH_MAX bytes.
+pub fn push (data: &[u8]) -> Vec<u8> {
+    assert!(data.len() <= OP_PUSH_MAX as usize, "push data too long");
+
+    if data.is_empty() {
+        return vec![OP_0];
+    }
+
+    let mut script = vec![data.len() as u8];
+    script.extend(data);
+    script
+}
+
+// Runs `witness` and then `script` against the transaction's signing_hash()
+pub fn execute (witness: &[u8], script: &[u8], signing_hash: &Hash) -> Result<(), ScriptError> {
+    let mut stack = vec![];
+
+    run(witness, signing_hash, &mut stack, true)?;
+    run(script, signing_hash, &mut stack, false)?;
+
+    match stack.last() {
+        Some(top) if is_true(top) => Ok(()),
+        _ => Err(ScriptError::Failed),
+    }
+}
+
+fn run (script: &[u8], signing_hash: &Hash, stack: &mut Vec<Vec<u8>>, push_only: bool) -> Result<(), ScriptError> {
+    if script.len() > MAX_SCRIPT_SIZE {
+        return Err(ScriptError::TooLarge);
+    }
+
+    let mut position = 0;
+
+    while let Some(&opcode) = script.get(position) {
+        position += 1;
+
+        if opcode <= OP_PUSH_MAX {
+            let end = position + opcode as usize;
+            let data = script.get(position..end).ok_or(ScriptError::Truncated)?;
+            stack.push(data.to_vec());
+            position = end;
+            continue;
+        } else if push_only && opcode != OP_1 {
+            return Err(ScriptError::NonPushWitness);
+        }
+
+        match opcode {
+            OP_1 => stack.push(vec![1]),
+            OP_VERIFY => {
+                if !is_true(&pop(stack)?) {
+                    return Err(ScriptError::VerifyFailed);
+                }
+            },
+            OP_DUP => {
+                let top = stack.last().ok_or(ScriptError::StackUnderflow)?.clone();
+                stack.push(top);
+            },
+            OP_EQUAL | OP_EQUALVERIFY => {
+                let equal = pop(stack)? == pop(stack)?;
+
+                if opcode == OP_EQUAL {
+                    stack.push(bool_item(equal));
+                } else if !equal {
+                    return Err(ScriptError::VerifyFailed);
+                }
+            },
+            OP_SHA256 => {
+                let top = pop(stack)?;
+                stack.push(Hash::digest(&top).as_bytes().to_vec());
+            },
+            OP_CHECKSIG => {
+                let public_key = pop(stack)?;
+                let signature = pop(stack)?;
+                stack.push(bool_item(transaction::verify_signature(&public_key, &signature, signing_hash)));
+            },
+            _ => return Err(ScriptError::UnknownOpcode(opcode)),
+        }
+    }
+
+    Ok(())
+}
+
+fn pop (stack: &mut Vec<Vec<u8>>) -> Result<Vec<u8>, ScriptError> {
+    stack.pop().ok_or(ScriptError::StackUnderflow)
+}
+
+fn is_true (item: &[u8]) -> bool {
+    item.iter().any(|byte| *byte != 0)
+}
+
+fn bool_item (value: bool) -> Vec<u8> {
+    if value { vec![1] } else { vec![] }
+}
@@ -0,0 +1,66 @@
+use super::*;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+// A chain shared between threads, e.g. an RPC server and a miner. Clones are
+// handles to the same chain. Readers only wait on writers, not on each other.
+#[derive(Clone)]
+pub struct SharedBlockchain {
+    inner: Arc<RwLock<Blockchain>>,
+}
+
+impl SharedBlockchain {
+    pub fn new (blockchain: Blockchain) -> Self {
+        SharedBlockchain {
+            inner: Arc::new(RwLock::new(blockchain)),
+        }
+    }
+
+    pub fn read (&self) -> RwLockReadGuard<'_, Blockchain> {
+        self.inner.read().unwrap()
+    }
+
+    pub fn write (&self) -> RwLockWriteGuard<'_, Blockchain> {
+        self.inner.write().unwrap()
+    }
+
+    // Holds the read lock only long enough to take the snapshot
+    pub fn snapshot (&self) -> ChainSnapshot {
+        self.read().snapshot()
+    }
+
+    // Number of blocks on the chain, one more than Blockchain::height()
+    pub fn block_count (&self) -> usize {
+        self.read().block_count()
+    }
+
+    // The tip's index, None for an empty chain, see Blockchain::height()
+    pub fn height (&self) -> Option<u32> {
+        self.read().height()
+    }
+
+    pub fn tip_hash (&self) -> Option<Hash> {
+        let chain = self.read();
+        chain.tip().map(|block| chain.hash_of(block))
+    }
+
+    pub fn get_balance (&self, address: &Address) -> u64 {
+        self.read().get_balance(address)
+    }
+
+    pub fn update_with_block (&self, block: Block) -> Result<(), BlockValidationErr> {
+        self.write().update_with_block(block)
+    }
+}
+
+// Fails to compile if any of these stops being safe to share between
+// threads, e.g. through a callback or consensus field that isn't Send + Sync
+const _: fn () = || {
+    fn assert_send_sync<T: Send + Sync> () {}
+
+    assert_send_sync::<Blockchain>();
+    assert_send_sync::<Block>();
+    assert_send_sync::<Transaction>();
+    assert_send_sync::<Mempool>();
+    assert_send_sync::<ChainSnapshot>();
+    assert_send_sync::<SharedBlockchain>();
+};
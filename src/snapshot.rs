@@ -0,0 +1,53 @@
+use super::*;
+use crate::store::BlockStore;
+use crate::transaction::{OutPoint, Output};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// An immutable view of a chain as it was when Blockchain::snapshot() was
+// called. It shares the chain's blocks and UTXO set rather than copying them,
+// so taking one is cheap and readers holding one never block, or see, later
+// writes to the chain. Clones share the same view.
+#[derive(Clone)]
+pub struct ChainSnapshot {
+    pub(crate) blocks: BlockStore,
+    pub(crate) unspent_outputs: Arc<HashMap<OutPoint, Output>>,
+    pub(crate) block_index: Arc<HashMap<Hash, usize>>,
+}
+
+impl ChainSnapshot {
+    pub fn blocks (&self) -> impl DoubleEndedIterator<Item = &Block> {
+        self.blocks.iter()
+    }
+
+    pub fn block_count (&self) -> usize {
+        self.blocks.len()
+    }
+
+    // The tip's index, one less than the number of blocks
+    pub fn height (&self) -> Option<u32> {
+        self.blocks.last().map(|block| block.header.index)
+    }
+
+    pub fn tip (&self) -> Option<&Block> {
+        self.blocks.last()
+    }
+
+    pub fn get_block_by_hash (&self, hash: &Hash) -> Option<&Block> {
+        self.block_index
+            .get(hash)
+            .map(|&index| &self.blocks[index])
+    }
+
+    pub fn get_balance (&self, address: &Address) -> u64 {
+        self.unspent_outputs
+            .values()
+            .filter(|output| &output.to_addr == address)
+            .map(|output| output.value)
+            .sum()
+    }
+
+    pub fn is_unspent (&self, outpoint: &OutPoint) -> bool {
+        self.unspent_outputs.contains_key(outpoint)
+    }
+}
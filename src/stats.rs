@@ -0,0 +1,102 @@
+use super::*;
+
+// What estimate_fee() suggests when recent blocks have no fees to go by
+const MIN_FEE_ESTIMATE: u64 = 1;
+
+// Times between consecutive blocks, in milliseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockTimeStats {
+    pub min: u128,
+    pub max: u128,
+    pub mean: u128,
+    pub median: u128,
+}
+
+// Chain vitals for dashboards, see Blockchain::summary()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainSummary {
+    pub height: Option<u32>,
+    // The zero hash on an empty chain
+    pub tip_hash: Hash,
+    pub difficulty: u128,
+    pub total_work: u128,
+    pub utxo_count: usize,
+    // Coins minted so far
+    pub total_supply: u64,
+    // None unless filled in by with_mempool(), as the chain has no mempool
+    // of its own
+    pub mempool_size: Option<usize>,
+}
+
+impl ChainSummary {
+    pub fn with_mempool (mut self, mempool: &Mempool) -> Self {
+        self.mempool_size = Some(mempool.len());
+        self
+    }
+}
+
+impl Blockchain {
+    // Everything here is kept up to date as blocks come in, so this is cheap
+    pub fn summary (&self) -> ChainSummary {
+        ChainSummary {
+            height: self.height(),
+            tip_hash: self.last_hash(),
+            difficulty: self.difficulty(),
+            total_work: self.total_work(),
+            utxo_count: self.utxo_count(),
+            total_supply: self.total_minted(),
+            mempool_size: None,
+        }
+    }
+
+    // None until there are at least two blocks to measure between
+    pub fn block_time_stats (&self) -> Option<BlockTimeStats> {
+        let mut deltas = self.blocks()
+            .zip(self.blocks().skip(1))
+            // Timestamps only have to beat the median of recent blocks, so a
+            // block can be stamped earlier than its parent
+            .map(|(parent, block)| block.header.timestamp.saturating_sub(parent.header.timestamp))
+            .collect::<Vec<u128>>();
+
+        if deltas.is_empty() {
+            return None;
+        }
+
+        deltas.sort_unstable();
+
+        let count = deltas.len();
+        let median = if count % 2 == 0 {
+            (deltas[count / 2 - 1] + deltas[count / 2]) / 2
+        } else {
+            deltas[count / 2]
+        };
+
+        Some(BlockTimeStats {
+            min: deltas[0],
+            max: deltas[count - 1],
+            mean: deltas.iter().sum::<u128>() / count as u128,
+            median,
+        })
+    }
+
+    // Suggests a fee for a transaction to be mined within about
+    // `target_blocks` blocks: the median fee paid by the transactions in that
+    // many recent blocks. Blocks are limited by transaction count rather than
+    // size, so the fee per transaction is the fee rate. Never less than
+    // MIN_FEE_ESTIMATE.
+    pub fn estimate_fee (&self, target_blocks: usize) -> u64 {
+        let mut fees = self
+            .iter_rev()
+            .take(target_blocks)
+            .flat_map(|block| (1..block.transactions.len())
+                .filter_map(move |position| self.transaction_fee(block.header.index, position)))
+            .collect::<Vec<u64>>();
+
+        if fees.is_empty() {
+            return MIN_FEE_ESTIMATE;
+        }
+
+        fees.sort_unstable();
+        fees[fees.len() / 2].max(MIN_FEE_ESTIMATE)
+    }
+}
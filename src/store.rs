@@ -0,0 +1,196 @@
+use super::*;
+use serde::{Serialize, Serializer};
+use serde::ser::SerializeSeq;
+use std::ops::Index;
+use std::sync::Arc;
+
+// Blocks per chunk. A write after a snapshot copies the chunk it lands in
+// plus one pointer per chunk, rather than every block.
+const CHUNK_SIZE: usize = 1024;
+
+// The active chain's blocks in order, in fixed-size chunks shared between
+// clones. Cloning only bumps a reference count, and a clone never sees, or
+// is slowed down by, writes to the store it was cloned from. Blocks are
+// looked up by index, which need not start at 0, see starting_at().
+#[derive(Clone, Default)]
+pub(crate) struct BlockStore {
+    chunks: Arc<Vec<Arc<Vec<Block>>>>,
+    // Index of the first block held
+    base: usize,
+    // Number of blocks held
+    held: usize,
+}
+
+impl BlockStore {
+    // An empty store whose first block will be the one at `base`, for a
+    // chain that doesn't have the blocks below it
+    pub(crate) fn starting_at (base: usize) -> Self {
+        BlockStore {
+            base,
+            ..BlockStore::default()
+        }
+    }
+
+    pub(crate) fn base (&self) -> usize {
+        self.base
+    }
+
+    // The index the next block pushed gets, so the number of blocks from
+    // genesis on whether or not they are all held
+    pub(crate) fn len (&self) -> usize {
+        self.base + self.held
+    }
+
+    pub(crate) fn is_empty (&self) -> bool {
+        self.held == 0
+    }
+
+    // The position of the block at `index` among those held
+    fn position (&self, index: usize) -> Option<usize> {
+        index.checked_sub(self.base).filter(|&position| position < self.held)
+    }
+
+    pub(crate) fn get (&self, index: usize) -> Option<&Block> {
+        let position = self.position(index)?;
+
+        Some(&self.chunks[position / CHUNK_SIZE][position % CHUNK_SIZE])
+    }
+
+    pub(crate) fn get_mut (&mut self, index: usize) -> Option<&mut Block> {
+        let position = self.position(index)?;
+
+        let chunk = &mut Arc::make_mut(&mut self.chunks)[position / CHUNK_SIZE];
+        Some(&mut Arc::make_mut(chunk)[position % CHUNK_SIZE])
+    }
+
+    pub(crate) fn last (&self) -> Option<&Block> {
+        self.len().checked_sub(1).and_then(|index| self.get(index))
+    }
+
+    pub(crate) fn iter (&self) -> impl DoubleEndedIterator<Item = &Block> {
+        self.chunks.iter().flat_map(|chunk| chunk.iter())
+    }
+
+    // The blocks held from `index` on
+    pub(crate) fn iter_from (&self, index: usize) -> impl Iterator<Item = &Block> {
+        self.iter().skip(index.saturating_sub(self.base))
+    }
+
+    pub(crate) fn push (&mut self, block: Block) {
+        let chunks = Arc::make_mut(&mut self.chunks);
+
+        if self.held.is_multiple_of(CHUNK_SIZE) {
+            chunks.push(Arc::new(Vec::with_capacity(CHUNK_SIZE)));
+        }
+
+        Arc::make_mut(chunks.last_mut().unwrap()).push(block);
+        self.held += 1;
+    }
+
+    pub(crate) fn pop (&mut self) -> Option<Block> {
+        if self.held == 0 {
+            return None;
+        }
+
+        let chunks = Arc::make_mut(&mut self.chunks);
+        let block = Arc::make_mut(chunks.last_mut().unwrap()).pop();
+
+        if chunks.last().is_some_and(|chunk| chunk.is_empty()) {
+            chunks.pop();
+        }
+
+        self.held -= 1;
+        block
+    }
+}
+
+impl Index<usize> for BlockStore {
+    type Output = Block;
+
+    fn index (&self, index: usize) -> &Block {
+        self.get(index).expect("block index out of range")
+    }
+}
+
+// As the Vec<Block> it stands in for, holding only the blocks held. The
+// length goes first, as formats like bincode need it up front.
+impl Serialize for BlockStore {
+    fn serialize<S: Serializer> (&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.held))?;
+        for block in self.iter() {
+            seq.serialize_element(block)?;
+        }
+        seq.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store (count: usize) -> BlockStore {
+        let mut store = BlockStore::default();
+        for index in 0..count {
+            store.push(Block::new(index as u32, 0, Hash::zero(), vec![], 0));
+        }
+        store
+    }
+
+    #[test]
+    fn keeps_order_across_chunks () {
+        let store = store(CHUNK_SIZE * 2 + 3);
+
+        assert_eq!(store.len(), CHUNK_SIZE * 2 + 3);
+        assert_eq!(store.iter().count(), store.len());
+        assert!(store.iter().enumerate().all(|(index, block)| block.header.index == index as u32));
+        assert_eq!(store.last().unwrap().header.index, CHUNK_SIZE as u32 * 2 + 2);
+        assert!(store.get(store.len()).is_none());
+    }
+
+    #[test]
+    fn pops_back_over_a_chunk_boundary () {
+        let mut store = store(CHUNK_SIZE + 1);
+
+        assert_eq!(store.pop().unwrap().header.index, CHUNK_SIZE as u32);
+        assert_eq!(store.pop().unwrap().header.index, CHUNK_SIZE as u32 - 1);
+        assert_eq!(store.len(), CHUNK_SIZE - 1);
+        assert_eq!(store.iter().count(), CHUNK_SIZE - 1);
+    }
+
+    #[test]
+    fn looks_up_by_index_from_the_base () {
+        let mut store = BlockStore::starting_at(5);
+        store.push(Block::new(5, 0, Hash::zero(), vec![], 0));
+        store.push(Block::new(6, 0, Hash::zero(), vec![], 0));
+
+        assert_eq!(store.len(), 7);
+        assert!(store.get(4).is_none());
+        assert_eq!(store.iter().next().unwrap().header.index, 5);
+        assert_eq!(store[6].header.index, 6);
+        assert_eq!(store.iter_from(6).count(), 1);
+        assert_eq!(store.iter_from(0).count(), 2);
+    }
+
+    #[test]
+    fn serializes_as_a_vec () {
+        let store = store(CHUNK_SIZE + 1);
+        let blocks = store.iter().cloned().collect::<Vec<Block>>();
+
+        assert_eq!(bincode::serialize(&store).unwrap(), bincode::serialize(&blocks).unwrap());
+    }
+
+    #[test]
+    fn clones_dont_see_later_writes () {
+        let mut store = store(CHUNK_SIZE + 1);
+        let clone = store.clone();
+
+        store.pop();
+        store.push(Block::new(7, 1, Hash::zero(), vec![], 0));
+        store.get_mut(0).unwrap().header.timestamp = 1;
+
+        assert_eq!(clone.len(), CHUNK_SIZE + 1);
+        assert_eq!(clone.last().unwrap().header.index, CHUNK_SIZE as u32);
+        assert_eq!(clone[0].header.timestamp, 0);
+        assert_eq!(store[0].header.timestamp, 1);
+    }
+}
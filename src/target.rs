@@ -0,0 +1,142 @@
+use super::*;
+
+// Targets are 256-bit numbers stored little-endian, the same way a hash is
+// read when checking it against one: a hash meets a target when it is
+// numerically below it.
+//
+// The compact "bits" form is Bitcoin's nBits: the top byte is the length of
+// the target in bytes and the low three bytes are its most significant
+// digits, so target = mantissa * 256^(exponent - 3). Bit 0x00800000 is a sign
+// bit and is never set in a valid target.
+
+// None if the bits are negative or expand to more than 256 bits
+pub fn compact_to_target (bits: u32) -> Option<[u8; 32]> {
+    let exponent = (bits >> 24) as usize;
+    let mantissa = bits & 0x007f_ffff;
+    let mut target = [0u8; 32];
+
+    if mantissa == 0 {
+        return Some(target);
+    } else if bits & 0x0080_0000 != 0 {
+        return None;
+    }
+
+    let mantissa_bytes = mantissa.to_le_bytes();
+    for (position, byte) in mantissa_bytes[..3].iter().enumerate() {
+        if *byte == 0 {
+            continue;
+        }
+
+        // Digits shifted below the units place are dropped, as in Bitcoin
+        match (position + exponent).checked_sub(3) {
+            Some(index) if index < 32 => target[index] = *byte,
+            Some(_) => return None,
+            None => (),
+        }
+    }
+
+    Some(target)
+}
+
+// Lossy: only the three most significant bytes of the target are kept
+pub fn target_to_compact (target: &[u8; 32]) -> u32 {
+    let mut size = match target.iter().rposition(|byte| *byte != 0) {
+        Some(position) => position + 1,
+        None => return 0,
+    };
+
+    let mut mantissa = (0..3)
+        .filter_map(|offset| (size + offset).checked_sub(3).map(|index| (index, offset)))
+        .fold(0u32, |mantissa, (index, offset)| mantissa | (target[index] as u32) << (8 * offset));
+
+    // Keep the sign bit clear by moving a digit into the exponent
+    if mantissa & 0x0080_0000 != 0 {
+        mantissa >>= 8;
+        size += 1;
+    }
+
+    (size as u32) << 24 | mantissa
+}
+
+// The target a u128 difficulty stands for. A hash meets `difficulty` when its
+// top 128 bits are below it, which is the same as the whole hash being below
+// difficulty * 2^128.
+//
+// Difficulty stays a u128 threshold rather than a count of leading zero bits
+// over the whole hash: a number of leading zero bits is just the threshold
+// 2^(128 - bits), so it adds no precision up to 128 bits, only steps of a
+// doubling retargeting couldn't move between, and past 128 bits it asks for
+// more work than anyone can do.
+pub fn difficulty_to_target (difficulty: u128) -> [u8; 32] {
+    let mut target = [0u8; 32];
+    target[16..].copy_from_slice(&difficulty.to_le_bytes());
+    target
+}
+
+pub fn meets_target (hash: &Hash, target: &[u8; 32]) -> bool {
+    hash.as_bytes().iter().rev().lt(target.iter().rev())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A hash whose leading zero bits number exactly `bits`
+    fn hash_with_zero_bits (bits: u32) -> Hash {
+        let mut bytes = [0xff; 32];
+        for bit in 0..bits.min(256) {
+            bytes[31 - bit as usize / 8] &= !(0x80 >> (bit % 8));
+        }
+        if bits < 256 {
+            bytes[31 - bits as usize / 8] |= 0x80 >> (bits % 8);
+        }
+        Hash(bytes)
+    }
+
+    #[test]
+    fn compact_bits_round_trip () {
+        for bits in [0x1d00_ffff, 0x1b04_04cb, 0x0312_3456, 0x0400_8000, 0x2000_ffff, 0x0112_0000] {
+            let target = compact_to_target(bits).unwrap();
+
+            assert_eq!(target_to_compact(&target), bits, "{:08x}", bits);
+        }
+
+        // Bitcoin's genesis target, 0xffff * 256^26
+        let target = compact_to_target(0x1d00_ffff).unwrap();
+        assert_eq!(&target[26..28], &[0xff, 0xff]);
+        assert!(target[..26].iter().chain(&target[28..]).all(|byte| *byte == 0));
+
+        // Digits below the units place are dropped
+        assert_eq!(target_to_compact(&compact_to_target(0x0212_3456).unwrap()), 0x0212_3400);
+        assert_eq!(compact_to_target(0x1d00_0000), Some([0; 32]));
+        assert_eq!(target_to_compact(&[0; 32]), 0);
+    }
+
+    #[test]
+    fn compact_bits_guard_against_overflow () {
+        // Too long for 256 bits, though only by the digits actually set
+        assert_eq!(compact_to_target(0x2101_0000), None);
+        assert_eq!(compact_to_target(0xff12_3456), None);
+        assert!(compact_to_target(0x2200_0001).is_some());
+        assert_eq!(compact_to_target(0x2200_0100), None);
+
+        // Negative
+        assert_eq!(compact_to_target(0x1d80_ffff), None);
+
+        // The top digit of the largest target moves into the exponent
+        assert_eq!(target_to_compact(&[0xff; 32]), 0x2100_ffff);
+    }
+
+    #[test]
+    fn targets_agree_with_difficulty () {
+        for bits in [1, 20, 100] {
+            let difficulty = 1 << (128 - bits);
+            let target = difficulty_to_target(difficulty);
+
+            assert!(meets_target(&hash_with_zero_bits(bits), &target));
+            assert!(!meets_target(&hash_with_zero_bits(bits - 1), &target));
+            assert!(check_difficulty(&hash_with_zero_bits(bits), difficulty));
+            assert!(!check_difficulty(&hash_with_zero_bits(bits - 1), difficulty));
+        }
+    }
+}
@@ -1,80 +1,352 @@
 use super::*;
 use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt::{self, Display, Formatter};
+use serde::{Deserialize, Serialize};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
 
-#[derive(Clone)]
+// Outputs are paid to the hash of an ed25519 public key, see Address
+pub fn address (public_key: &VerifyingKey) -> Address {
+    Address::from_pubkey(public_key)
+}
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Output {
     pub to_addr: Address,
     pub value: u64,
+    // Locking script, see the script module. When empty, the output is
+    // spent as if its script were script::pay_to_address(to_addr);
+    // otherwise `to_addr` is only informational and the script decides.
+    #[serde(default, with = "hex_serde")]
+    pub script: Vec<u8>,
 }
 
 impl Hashable for Output {
     fn bytes (&self) -> Vec<u8> {
         let mut bytes = vec![];
 
-        bytes.extend(self.to_addr.as_bytes());
+        bytes.extend(self.to_addr.bytes());
         bytes.extend(&u64_bytes(&self.value));
+        bytes.extend(&self.script);
 
         bytes
     }
 }
 
+// Names an output by the transaction that created it and its position among
+// that transaction's outputs, so two outputs paying the same amount to the
+// same address are still told apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct OutPoint {
+    pub txid: Hash,
+    pub index: u32,
+}
+
+impl Hashable for OutPoint {
+    fn bytes (&self) -> Vec<u8> {
+        let mut bytes = self.txid.as_bytes().to_vec();
+        bytes.extend(&u32_bytes(&self.index));
+        bytes
+    }
+}
+
+impl Display for OutPoint {
+    fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.txid, self.index)
+    }
+}
+
+// Spends the output at `previous_output`. The witness must satisfy the
+// output's script, which for a plain payment to an address means pushing a
+// signature over the transaction's signing_hash() and the public key the
+// address is the hash of, see script::p2pkh_witness().
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Input {
+    pub previous_output: OutPoint,
+    #[serde(default, with = "hex_serde")]
+    pub witness: Vec<u8>,
+}
+
+impl Input {
+    pub fn new (previous_output: OutPoint) -> Self {
+        Input {
+            previous_output,
+            witness: vec![],
+        }
+    }
+}
+
+// Most bytes of coinbase_data a coinbase may carry
+pub const MAX_COINBASE_DATA: usize = 100;
+
+// Largest serialized transaction verify_structure() lets through
+pub const MAX_TRANSACTION_SIZE: usize = 100_000;
+
+// Why verify_structure() rejected a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxStructureErr {
+    NoOutputs,
+    // Two inputs spend the same output
+    DuplicateInput,
+    // The output values sum to more than fits in a u64
+    ValueOverflow,
+    TooLarge,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Transaction {
-    pub inputs: Vec<Output>,
+    pub inputs: Vec<Input>,
     pub outputs: Vec<Output>,
+    // Set on the coinbase and nothing else. Blocks check it agrees with the
+    // transaction's shape, so an input-less transaction can't pass for a
+    // second coinbase or a coinbase for an ordinary transaction. Part of the
+    // hash, so flipping it makes a different transaction.
+    #[serde(default)]
+    pub coinbase: bool,
+    // Arbitrary bytes a miner may put in the coinbase, e.g. a tag or an extra
+    // nonce for when every block nonce has been tried. Part of the hash, so of
+    // the merkle root too. Must be empty on every other transaction.
+    #[serde(default, with = "hex_serde")]
+    pub coinbase_data: Vec<u8>,
+    // The transaction can't be mined in a block below this height. 0 means
+    // no lock. Ignored on the coinbase.
+    #[serde(default)]
+    pub lock_height: u32,
 }
 
 impl Transaction {
-    pub fn input_value (&self) -> u64 {
-        self.inputs
-            .iter()
-            .map(|input| input.value)
-            .sum()
+    // The hash of everything in the transaction, witnesses included. Blocks commit to these rather than to txids, see
+    // Block::merkle_root(), so a block's hash still covers its signatures.
+    pub fn wtxid (&self) -> Hash {
+        self.wtxid_with(&Sha256)
     }
 
-    pub fn output_value (&self) -> u64 {
-        self.outputs
-            .iter()
-            .map(|output| output.value)
-            .sum()
+    pub fn wtxid_with (&self, hasher: &dyn Hasher<Output = Hash>) -> Hash {
+        hasher.digest(&self.bytes_with_witnesses(true))
     }
 
-    pub fn input_hashes (&self) -> HashSet<Hash> {
-        self.inputs
-            .iter()
-            .map(|input| input.hash())
-            .collect::<HashSet<Hash>>()
+    // Everything in the transaction, or with `witnesses` false everything
+    // but the witnesses. Outputs are named by their position, so that is
+    // hashed too: reordering them makes a different transaction.
+    fn bytes_with_witnesses (&self, witnesses: bool) -> Vec<u8> {
+        let mut bytes = vec![];
+
+        for input in &self.inputs {
+            bytes.extend(input.previous_output.bytes());
+            if witnesses {
+                bytes.extend(&u32_bytes(&(input.witness.len() as u32)));
+                bytes.extend(&input.witness);
+            }
+        }
+        for output in &self.outputs {
+            bytes.extend(output.bytes());
+        }
+
+        bytes.push(self.coinbase as u8);
+        bytes.extend(&self.coinbase_data);
+        bytes.extend(&u32_bytes(&self.lock_height));
+
+        bytes
     }
 
-    pub fn output_hashes (&self) -> HashSet<Hash> {
+    // None if the values sum to more than fits in a u64, as a wrapped sum
+    // could make a transaction look like it pays less than it does. What the
+    // inputs are worth is up to the chain, see Blockchain::fee().
+    pub fn output_value (&self) -> Option<u64> {
         self.outputs
             .iter()
-            .map(|output| output.hash())
-            .collect::<HashSet<Hash>>()
+            .try_fold(0u64, |sum, output| sum.checked_add(output.value))
+    }
+
+    // The outputs the transaction spends
+    pub fn spent_outpoints (&self) -> impl Iterator<Item = &OutPoint> {
+        self.inputs.iter().map(|input| &input.previous_output)
+    }
+
+    // Where the transaction's own outputs will be, in order
+    pub fn outpoints (&self) -> Vec<OutPoint> {
+        self.outpoints_with(&Sha256)
+    }
+
+    // outpoints() on a chain using `hasher`
+    pub fn outpoints_with (&self, hasher: &dyn Hasher<Output = Hash>) -> Vec<OutPoint> {
+        let txid = self.hash_with(hasher);
+
+        (0..self.outputs.len() as u32)
+            .map(|index| OutPoint { txid, index })
+            .collect()
+    }
+
+    // Serialized length in bytes, what fee rates are measured against
+    pub fn size (&self) -> usize {
+        bincode::serialized_size(self).expect("transactions always serialize") as usize
     }
 
+    // Sanity checks that need no chain state, cheap enough to run on anything
+    // received from a peer before looking it up against the chain. Passing
+    // says nothing about whether the inputs exist or are signed for.
+    pub fn verify_structure (&self) -> Result<(), TxStructureErr> {
+        let mut inputs = HashSet::new();
+
+        if self.outputs.is_empty() {
+            Err(TxStructureErr::NoOutputs)
+        } else if !self.spent_outpoints().all(|outpoint| inputs.insert(outpoint)) {
+            Err(TxStructureErr::DuplicateInput)
+        } else if self.output_value().is_none() {
+            Err(TxStructureErr::ValueOverflow)
+        } else if self.size() > MAX_TRANSACTION_SIZE {
+            Err(TxStructureErr::TooLarge)
+        } else {
+            Ok(())
+        }
+    }
+
+    // Whether the transaction is marked as a coinbase. Only to be trusted for
+    // transactions already in a block, where the mark has been checked
+    // against the transaction's shape.
     pub fn is_coinbase (&self) -> bool {
-        self.inputs.len() == 0
+        self.coinbase
+    }
+
+    // What input signatures sign: everything except the witnesses carrying
+    // them.
+    // Always SHA-256, whatever Hasher the chain uses.
+    pub fn signing_hash (&self) -> Hash {
+        let mut bytes = vec![];
+
+        for input in &self.inputs {
+            bytes.extend(input.previous_output.bytes());
+        }
+        for output in &self.outputs {
+            bytes.extend(output.bytes());
+        }
+        bytes.extend(&u32_bytes(&self.lock_height));
+
+        Hash::digest(&bytes)
+    }
+
+    // Signs every input with this key, for spending outputs paid to its
+    // address or locked with its script::p2pkh() script. Inputs owned by
+    // different keys are signed one at a time with sign_input().
+    pub fn sign (&mut self, key: &SigningKey) {
+        for position in 0..self.inputs.len() {
+            self.sign_input(position, key);
+        }
+    }
+
+    // Signs the input at `position`, if there is one, see sign()
+    pub fn sign_input (&mut self, position: usize, key: &SigningKey) {
+        let public_key = key.verifying_key();
+        let signature = key.sign(self.signing_hash().as_bytes()).to_bytes().to_vec();
+
+        if let Some(input) = self.inputs.get_mut(position) {
+            input.witness = script::p2pkh_witness(&signature, &public_key);
+        }
+    }
+
+    // Checks the input's witness against `owner`'s script, or against
+    // script::pay_to_address() if it has none. `owner` should be the output
+    // the chain has at the input's previous_output.
+    pub fn verify_input (&self, input: &Input, owner: &Output) -> bool {
+        let script = if owner.script.is_empty() {
+            script::pay_to_address(&owner.to_addr)
+        } else {
+            owner.script.clone()
+        };
+
+        script::execute(&input.witness, &script, &self.signing_hash()).is_ok()
+    }
+}
+
+// Whether `signature` is the ed25519 signature of `public_key` over `message`
+pub(crate) fn verify_signature (public_key: &[u8], signature: &[u8], message: &Hash) -> bool {
+    let public_key = <[u8; 32]>::try_from(public_key)
+        .ok()
+        .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok());
+    let signature = Signature::from_slice(signature).ok();
+
+    match (public_key, signature) {
+        (Some(public_key), Some(signature)) => public_key.verify(message.as_bytes(), &signature).is_ok(),
+        _ => false,
     }
 }
 
+// A transaction's hash, its txid, leaves out the witnesses so that nobody
+// relaying it can change its id by re-encoding them, e.g. by pushing extra
+// items onto a witness. wtxid() covers them too.
 impl Hashable for Transaction {
     fn bytes (&self) -> Vec<u8> {
-        let mut bytes = vec![];
+        self.bytes_with_witnesses(false)
+    }
+}
 
-        bytes.extend(
-            self.inputs
-                .iter()
-                .flat_map(|input| input.bytes())
-                .collect::<Vec<u8>>()
-        );
+// Fee per serialized byte. A zero size counts as one byte rather than dividing
+// by zero.
+pub fn fee_rate (fee: u64, size: usize) -> f64 {
+    fee as f64 / size.max(1) as f64
+}
 
-        bytes.extend(
-            self.outputs
-                .iter()
-                .flat_map(|output| output.bytes())
-                .collect::<Vec<u8>>()
-        );
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        bytes
+    fn key (seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn outpoint (index: u32) -> OutPoint {
+        OutPoint { txid: Hash::digest(b"funding"), index }
+    }
+
+    fn transaction (inputs: &[u32], values: &[u64]) -> Transaction {
+        Transaction {
+            inputs: inputs.iter().map(|&index| Input::new(outpoint(index))).collect(),
+            outputs: values.iter().map(|&value| Output { to_addr: address(&key(2).verifying_key()), value, script: vec![] }).collect(),
+            coinbase: false,
+            coinbase_data: vec![],
+            lock_height: 0,
+        }
+    }
+
+    #[test]
+    fn the_same_transaction_always_hashes_the_same () {
+        let built = transaction(&[0, 1], &[10, 20]);
+        let decoded: Transaction = bincode::deserialize(&bincode::serialize(&built).unwrap()).unwrap();
+
+        assert_eq!(built.hash(), transaction(&[0, 1], &[10, 20]).hash());
+        assert_eq!(built.hash(), decoded.hash());
+        assert_eq!(built.signing_hash(), decoded.signing_hash());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn signing_changes_the_wtxid_but_not_the_txid () {
+        let unsigned = transaction(&[0, 1], &[10, 20]);
+        let mut signed = unsigned.clone();
+        signed.sign(&key(1));
+
+        assert_eq!(signed.hash(), unsigned.hash());
+        assert_ne!(signed.wtxid(), unsigned.wtxid());
+    }
+
+    // Outputs are spent by position and inputs are signed by position, so
+    // their order is part of the transaction
+    #[test]
+    fn reordering_makes_a_different_transaction () {
+        let ordered = transaction(&[0, 1], &[10, 20]);
+
+        assert_ne!(ordered.hash(), transaction(&[1, 0], &[10, 20]).hash());
+        assert_ne!(ordered.hash(), transaction(&[0, 1], &[20, 10]).hash());
+        assert_ne!(ordered.signing_hash(), transaction(&[1, 0], &[10, 20]).signing_hash());
+    }
+
+    #[test]
+    fn verify_structure_catches_duplicate_inputs_and_missing_outputs () {
+        assert_eq!(transaction(&[0, 1], &[10]).verify_structure(), Ok(()));
+        assert_eq!(transaction(&[0, 1, 0], &[10]).verify_structure(), Err(TxStructureErr::DuplicateInput));
+        assert_eq!(transaction(&[0], &[]).verify_structure(), Err(TxStructureErr::NoOutputs));
+        assert_eq!(transaction(&[0], &[u64::MAX, 1]).verify_structure(), Err(TxStructureErr::ValueOverflow));
+
+        let inputs = (0..MAX_TRANSACTION_SIZE as u32 / 36 + 1).collect::<Vec<u32>>();
+        assert_eq!(transaction(&inputs, &[10]).verify_structure(), Err(TxStructureErr::TooLarge));
+    }
+}
@@ -0,0 +1,158 @@
+use super::*;
+use std::collections::{HashMap, HashSet};
+
+// Every block a chain knows of, on its active chain or on a side branch,
+// keyed by hash with a link to its parent and the cumulative work up to it.
+// The chain keeps the active chain's blocks itself, so only side-branch
+// blocks are stored here in full. Derived data: side branches aren't
+// persisted, so a loaded chain only knows its active chain.
+#[derive(Default)]
+pub struct BlockTree {
+    nodes: HashMap<Hash, TreeNode>,
+}
+
+struct TreeNode {
+    header: BlockHeader,
+    // Sum of work() from genesis up to and including this block
+    work: u128,
+    // None while the block is on the active chain
+    block: Option<Block>,
+    // Whether the block was connected in full at some point, rather than it
+    // only having passed the header checks as a side block
+    validated: bool,
+    // Why the block, or one it builds on, failed to connect
+    rejection: Option<BlockValidationErr>,
+}
+
+impl BlockTree {
+    pub fn len (&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty (&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn contains (&self, hash: &Hash) -> bool {
+        self.nodes.contains_key(hash)
+    }
+
+    pub fn header (&self, hash: &Hash) -> Option<&BlockHeader> {
+        self.nodes.get(hash).map(|node| &node.header)
+    }
+
+    pub fn cumulative_work (&self, hash: &Hash) -> Option<u128> {
+        self.nodes.get(hash).map(|node| node.work)
+    }
+
+    // Whether the block is on a side branch rather than the active chain
+    pub fn is_side (&self, hash: &Hash) -> bool {
+        self.nodes.get(hash).is_some_and(|node| node.block.is_some())
+    }
+
+    // Whether the block, and so every block it builds on, passed full
+    // validation. Side blocks are only checked in full once their branch
+    // has the most work.
+    pub fn is_validated (&self, hash: &Hash) -> bool {
+        self.nodes.get(hash).is_some_and(|node| node.validated)
+    }
+
+    // Why the block was found invalid, None if it wasn't. A block building
+    // on an invalid one is invalid for the same reason.
+    pub fn rejection (&self, hash: &Hash) -> Option<&BlockValidationErr> {
+        self.nodes.get(hash).and_then(|node| node.rejection.as_ref())
+    }
+
+    // Hashes of the blocks no other known valid block builds on, one per
+    // branch. Blocks found invalid are left out.
+    pub fn tips (&self) -> Vec<Hash> {
+        let valid = || self.nodes.iter().filter(|(_, node)| node.rejection.is_none());
+        let parents = valid()
+            .map(|(_, node)| node.header.prev_block_hash)
+            .collect::<HashSet<Hash>>();
+
+        let mut tips = valid()
+            .map(|(hash, _)| *hash)
+            .filter(|hash| !parents.contains(hash))
+            .collect::<Vec<Hash>>();
+
+        tips.sort_unstable();
+        tips
+    }
+
+    // The tip with the most cumulative work, the lowest hash on a tie. The
+    // chain only switches branches for strictly more work, so on a tie this
+    // may not be the active tip.
+    pub fn best_tip (&self) -> Option<Hash> {
+        self.tips()
+            .into_iter()
+            .max_by_key(|hash| (self.nodes[hash].work, std::cmp::Reverse(*hash)))
+    }
+
+    // A block inserted without its body is on the active chain, so it was
+    // validated as it connected
+    pub(crate) fn insert (&mut self, hash: Hash, header: BlockHeader, work: u128, block: Option<Block>) {
+        let validated = block.is_none();
+        self.nodes.insert(hash, TreeNode { header, work, block, validated, rejection: None });
+    }
+
+    pub(crate) fn remove (&mut self, hash: &Hash) {
+        self.nodes.remove(hash);
+    }
+
+    // Removes a block along with every block built on it, so no side branch
+    // is left hanging off a parent the tree no longer knows
+    pub(crate) fn remove_with_descendants (&mut self, hash: &Hash) {
+        let mut removed = vec![*hash];
+        while let Some(hash) = removed.pop() {
+            if self.nodes.remove(&hash).is_some() {
+                removed.extend(self.children(&hash));
+            }
+        }
+    }
+
+    // Marks a block invalid, and every block built on it
+    pub(crate) fn invalidate (&mut self, hash: &Hash, err: BlockValidationErr) {
+        let mut invalid = vec![*hash];
+        while let Some(hash) = invalid.pop() {
+            if let Some(node) = self.nodes.get_mut(&hash) {
+                node.rejection = Some(err.clone());
+                invalid.extend(self.children(&hash));
+            }
+        }
+    }
+
+    fn children (&self, hash: &Hash) -> Vec<Hash> {
+        self.nodes
+            .iter()
+            .filter(|(_, node)| node.header.prev_block_hash == *hash)
+            .map(|(child, _)| *child)
+            .collect()
+    }
+
+    // Moves a block the chain disconnected onto a side branch
+    pub(crate) fn detach (&mut self, hash: &Hash, block: Block) {
+        if let Some(node) = self.nodes.get_mut(hash) {
+            node.block = Some(block);
+        }
+    }
+
+    // The side-branch blocks leading to `hash`, from the first one off the
+    // active chain. None if `hash` is unknown or on the active chain.
+    pub(crate) fn side_branch (&self, hash: &Hash) -> Option<Vec<Block>> {
+        let mut side = vec![];
+        let mut hash = *hash;
+
+        while let Some(block) = self.nodes.get(&hash)?.block.as_ref() {
+            side.push(block.clone());
+            hash = block.header.prev_block_hash;
+        }
+
+        if side.is_empty() {
+            return None;
+        }
+
+        side.reverse();
+        Some(side)
+    }
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use blockchainlib::*;
+use common::*;
+
+// A chain with one mined block, and its coinbase output worth 50
+fn funded () -> (Blockchain, (OutPoint, transaction::Output)) {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+    let coin = coinbase_output(&chain, 0);
+    assert_eq!(coin.1.value, 50);
+    (chain, coin)
+}
+
+#[test]
+fn spends_exact_change () {
+    let (mut chain, (outpoint, output)) = funded();
+
+    let mut transaction = TransactionBuilder::new()
+        .input(outpoint, output)
+        .outputs(vec![(addr(2), 30), (addr(3), 20)])
+        .change(addr(1), 0)
+        .build()
+        .unwrap();
+    transaction.sign(&key(1));
+
+    assert_eq!(transaction.outputs.len(), 2);
+    assert_eq!(transaction.output_value(), Some(50));
+
+    let mut mempool = Mempool::new();
+    mempool.add_transaction(transaction, &chain).unwrap();
+    mine_pool(&mut chain, &mut mempool, 1);
+    assert_eq!(chain.get_balance(&addr(2)), 30);
+    assert_eq!(chain.get_balance(&addr(3)), 20);
+}
+
+#[test]
+fn returns_change_less_the_fee () {
+    let (mut chain, (outpoint, output)) = funded();
+
+    let mut transaction = TransactionBuilder::new()
+        .input(outpoint, output)
+        .output(addr(2), 30)
+        .output(addr(3), 10)
+        .change(addr(1), 2)
+        .build()
+        .unwrap();
+    transaction.sign(&key(1));
+
+    assert_eq!(transaction.outputs.len(), 3);
+    assert_eq!(transaction.outputs[2].value, 8);
+
+    let mut mempool = Mempool::new();
+    mempool.add_transaction(transaction, &chain).unwrap();
+    mine_pool(&mut chain, &mut mempool, 4);
+    assert_eq!(chain.get_balance(&addr(1)), 8);
+    assert_eq!(chain.get_balance(&addr(4)), chain.block_reward(1) + 2);
+}
+
+#[test]
+fn leaves_dust_change_to_the_fee () {
+    let (_, (outpoint, output)) = funded();
+
+    let transaction = TransactionBuilder::new()
+        .with_dust_threshold(5)
+        .input(outpoint, output)
+        .output(addr(2), 47)
+        .change(addr(1), 0)
+        .build()
+        .unwrap();
+
+    assert_eq!(transaction.outputs.len(), 1);
+    assert_eq!(transaction.output_value(), Some(47));
+}
+
+#[test]
+fn rejects_overspending_and_bad_outputs () {
+    let (_, (outpoint, output)) = funded();
+    let builder = || TransactionBuilder::new().with_dust_threshold(5).input(outpoint, output.clone());
+
+    assert!(matches!(builder().output(addr(2), 51).build(), Err(TransactionBuilderErr::InsufficientInputValue)));
+    assert!(matches!(builder().output(addr(2), 50).change(addr(1), 1).build(), Err(TransactionBuilderErr::InsufficientInputValue)));
+    assert!(matches!(builder().output(addr(2), 0).build(), Err(TransactionBuilderErr::ZeroValueOutput)));
+    assert!(matches!(builder().output(addr(2), 4).build(), Err(TransactionBuilderErr::DustOutput)));
+    assert!(matches!(builder().build(), Err(TransactionBuilderErr::NoOutputs)));
+    assert!(matches!(TransactionBuilder::new().output(addr(2), 10).build(), Err(TransactionBuilderErr::NoInputs)));
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use blockchainlib::*;
+use common::*;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+fn counter () -> (Arc<AtomicUsize>, impl Fn() -> usize) {
+    let count = Arc::new(AtomicUsize::new(0));
+    let read = {
+        let count = count.clone();
+        move || count.load(Ordering::SeqCst)
+    };
+    (count, read)
+}
+
+#[test]
+fn block_added_fires_once_per_accepted_block () {
+    let mut chain = chain();
+    let (count, added) = counter();
+    chain.on_block_added(Box::new(move |_| {
+        count.fetch_add(1, Ordering::SeqCst);
+    }));
+
+    mine_blocks(&mut chain, 3, 1);
+    assert_eq!(added(), 3);
+
+    let mut block = mine_block(&chain, vec![], 0, 1);
+    block.header.prev_block_hash = Hash::zero();
+    assert!(chain.update_with_block(block).is_err());
+    assert_eq!(added(), 3);
+}
+
+#[test]
+fn a_panicking_callback_leaves_the_chain_and_other_callbacks_alone () {
+    let mut chain = chain();
+    chain.on_block_added(Box::new(|_| panic!("callback failed")));
+    let (count, added) = counter();
+    // Panics here would be swallowed too, so only count blocks in order
+    chain.on_block_added(Box::new(move |block| {
+        let _ = count.compare_exchange(block.header.index as usize, block.header.index as usize + 1, Ordering::SeqCst, Ordering::SeqCst);
+    }));
+
+    mine_blocks(&mut chain, 2, 1);
+    assert_eq!(added(), 2);
+    assert_eq!(chain.block_count(), 2);
+    assert_eq!(chain.get_balance(&addr(1)), 100);
+}
+
+#[test]
+fn reorg_fires_with_the_blocks_switched () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 3, 1);
+    let mut other = fork(common::chain(), &chain, 1);
+    mine_blocks(&mut other, 3, 2);
+
+    let (count, reorgs) = counter();
+    chain.on_reorg(Box::new(move |disconnected, connected| {
+        if (disconnected.len(), connected.len()) == (2, 3) {
+            count.fetch_add(1, Ordering::SeqCst);
+        }
+    }));
+    let (count, added) = counter();
+    chain.on_block_added(Box::new(move |_| {
+        count.fetch_add(1, Ordering::SeqCst);
+    }));
+
+    assert!(chain.try_replace(other.blocks().cloned().collect()).unwrap());
+    assert_eq!(reorgs(), 1);
+    assert_eq!(added(), 0);
+
+    // A candidate that doesn't win fires nothing
+    assert!(!chain.try_replace(other.blocks().take(3).cloned().collect()).unwrap());
+    assert_eq!(reorgs(), 1);
+}
@@ -0,0 +1,118 @@
+// Helpers shared by the integration tests
+#![allow(dead_code)]
+
+use blockchainlib::*;
+use blockchainlib::transaction::{address, Input, Output};
+use ed25519_dalek::SigningKey;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static CLOCK: AtomicU64 = AtomicU64::new(1_600_000_000_000);
+
+// A clock a second further on every time it is read, shared by every chain,
+// so blocks mined back to back, on any chain, have rising timestamps
+pub fn tick () -> u128 {
+    CLOCK.fetch_add(1_000, Ordering::SeqCst) as u128
+}
+
+pub fn key (seed: u64) -> SigningKey {
+    let mut bytes = [0; 32];
+    bytes[..8].copy_from_slice(&seed.to_le_bytes());
+    SigningKey::from_bytes(&bytes)
+}
+
+pub fn addr (seed: u64) -> Address {
+    address(&key(seed).verifying_key())
+}
+
+// Nearly every hash meets u128::MAX, so blocks need no real mining. Coinbases
+// mature at once so tests can spend them straight away.
+pub fn chain () -> Blockchain {
+    Blockchain::new(u128::MAX)
+        .with_clock(tick)
+        .with_coinbase_maturity(0)
+}
+
+// Mines `count` blocks paying `miner` with whatever `chain` accepts
+pub fn mine_blocks (chain: &mut Blockchain, count: usize, miner: u64) {
+    for _ in 0..count {
+        chain.mine_next_block(&addr(miner), &mut Mempool::new()).unwrap();
+    }
+}
+
+// Mines a block holding the transactions in `mempool`
+pub fn mine_pool (chain: &mut Blockchain, mempool: &mut Mempool, miner: u64) -> Hash {
+    chain.mine_next_block(&addr(miner), mempool).unwrap()
+}
+
+// A copy of `chain` cut back to its first `count` blocks, with `with`'s rules,
+// for mining a competing branch on
+pub fn fork (with: Blockchain, chain: &Blockchain, count: usize) -> Blockchain {
+    with.with_blocks(chain.blocks().take(count).cloned().collect()).unwrap()
+}
+
+// The coinbase output of the block at `height`
+pub fn coinbase_output (chain: &Blockchain, height: u32) -> (OutPoint, Output) {
+    let coinbase = &chain.block(height).unwrap().transactions[0];
+    (coinbase.outpoints()[0], coinbase.outputs[0].clone())
+}
+
+// A transaction `from` signs spending `outpoint`, paying `value` to `to`
+pub fn pay (outpoint: OutPoint, from: u64, to: u64, value: u64) -> Transaction {
+    let mut transaction = Transaction {
+        inputs: vec![Input::new(outpoint)],
+        outputs: vec![Output { to_addr: addr(to), value, script: vec![] }],
+        coinbase: false,
+        coinbase_data: vec![],
+        lock_height: 0,
+    };
+    transaction.sign(&key(from));
+    transaction
+}
+
+// The coinbase `mine_block()` gives a block at `height`
+pub fn coinbase (miner: u64, value: u64, height: u32) -> Transaction {
+    Transaction {
+        inputs: vec![],
+        outputs: vec![Output { to_addr: addr(miner), value, script: vec![] }],
+        coinbase: true,
+        coinbase_data: height.to_le_bytes().to_vec(),
+        lock_height: 0,
+    }
+}
+
+// The next block on `chain` with exactly `transactions` after a coinbase
+// paying `miner` the block reward plus `fees`, not yet added to it
+pub fn mine_block (chain: &Blockchain, transactions: Vec<Transaction>, fees: u64, miner: u64) -> Block {
+    mine_block_at(chain, transactions, fees, miner, tick())
+}
+
+// mine_block() stamped at `timestamp`
+pub fn mine_block_at (chain: &Blockchain, transactions: Vec<Transaction>, fees: u64, miner: u64, timestamp: u128) -> Block {
+    let index = chain.block_count() as u32;
+    let mut block_transactions = vec![coinbase(miner, chain.block_reward(index) + fees, index)];
+    block_transactions.extend(canonical_order(transactions, chain.hasher()));
+
+    mine_raw(chain, block_transactions, timestamp)
+}
+
+// Mines `count` blocks paying `miner`, each stamped `spacing` ms after the
+// tip, the first one on an empty chain now
+pub fn mine_spaced (chain: &mut Blockchain, count: usize, spacing: u128, miner: u64) {
+    for _ in 0..count {
+        let timestamp = chain.tip().map_or_else(tick, |tip| tip.header.timestamp + spacing);
+        let block = mine_block_at(chain, vec![], 0, miner, timestamp);
+        chain.update_with_block(block).unwrap();
+    }
+}
+
+// The next block on `chain` holding exactly `transactions`, coinbase and
+// all, in that order. Blocks the chain would reject get a zero utxo_root, so
+// they fail on what is actually wrong with them.
+pub fn mine_raw (chain: &Blockchain, transactions: Vec<Transaction>, timestamp: u128) -> Block {
+    let index = chain.block_count() as u32;
+    let difficulty = chain.expected_difficulty(index);
+    let mut block = Block::new(index, timestamp, chain.last_hash(), transactions, difficulty);
+    block.header.utxo_root = chain.utxo_root_for(&block).unwrap_or(Hash::zero());
+    block.mine_with(difficulty, chain.hasher()).unwrap();
+    block
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use blockchainlib::*;
+use common::*;
+use std::thread;
+
+#[test]
+fn readers_run_while_a_writer_appends () {
+    const BLOCKS: usize = 20;
+    let shared = SharedBlockchain::new(chain());
+    assert_eq!(shared.height(), None);
+
+    thread::scope(|scope| {
+        for _ in 0..4 {
+            let shared = shared.clone();
+            scope.spawn(move || {
+                let mut seen = 0;
+                while seen < BLOCKS {
+                    // A single guard sees one consistent chain
+                    let chain = shared.read();
+                    let count = chain.block_count();
+                    assert!(count >= seen);
+                    if count > 0 {
+                        assert_eq!(chain.tip().map(|block| block.header.index as usize), Some(count - 1));
+                        assert_eq!(chain.get_balance(&addr(1)), count as u64 * 50);
+                    }
+                    seen = count;
+                }
+            });
+        }
+
+        for _ in 0..BLOCKS {
+            let block = mine_block(&shared.read(), vec![], 0, 1);
+            shared.update_with_block(block).unwrap();
+        }
+    });
+
+    assert_eq!(shared.block_count(), BLOCKS);
+    assert_eq!(shared.height(), Some(BLOCKS as u32 - 1));
+    assert_eq!(shared.tip_hash(), Some(shared.read().last_hash()));
+    assert_eq!(shared.get_balance(&addr(1)), BLOCKS as u64 * 50);
+}
+
+// Many readers against one writer for long enough to shake out races, each
+// reader checking invariants that hold between whole blocks
+#[test]
+fn readers_racing_a_writer_always_see_whole_blocks () {
+    const BLOCKS: u32 = 50;
+    let lock = std::sync::RwLock::new(chain());
+
+    thread::scope(|scope| {
+        for _ in 0..8 {
+            scope.spawn(|| loop {
+                let chain = lock.read().unwrap();
+                let count = chain.block_count() as u64;
+                assert_eq!(chain.get_balance(&addr(1)) + chain.get_balance(&addr(2)), count * 50);
+                if let Some(tip) = chain.tip() {
+                    assert_eq!(tip.header.index as u64, count - 1);
+                    assert_eq!(chain.utxo_count() as u64, count);
+                }
+                if count == BLOCKS as u64 {
+                    break;
+                }
+                drop(chain);
+                thread::yield_now();
+            });
+        }
+
+        for height in 0..BLOCKS {
+            // Mined alongside the readers, then appended under the write lock.
+            // Each block after the first moves the coinbase before it to 2.
+            let block = {
+                let chain = lock.read().unwrap();
+                let transactions = match height.checked_sub(1) {
+                    Some(previous) => vec![pay(coinbase_output(&chain, previous).0, 1, 2, 50)],
+                    None => vec![],
+                };
+                mine_block(&chain, transactions, 0, 1)
+            };
+            lock.write().unwrap().update_with_block(block).unwrap();
+        }
+    });
+
+    let chain = lock.into_inner().unwrap();
+    assert_eq!(chain.get_balance(&addr(2)), (BLOCKS as u64 - 1) * 50);
+}
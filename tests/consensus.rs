@@ -0,0 +1,112 @@
+mod common;
+
+use blockchainlib::*;
+use common::*;
+
+// A block on `chain` whose hash misses the difficulty
+fn unmined_block (chain: &Blockchain) -> Block {
+    let mut block = mine_block(chain, vec![], 0, 1);
+    while check_difficulty(&block.hash(), block.header.difficulty) {
+        block.header.nonce += 1;
+    }
+    block
+}
+
+#[test]
+fn the_consensus_rule_is_pluggable () {
+    let hard = || Blockchain::new(u128::MAX / 16).with_clock(tick);
+
+    let mut chain = hard();
+    let block = unmined_block(&chain);
+    assert!(matches!(chain.update_with_block(block.clone()), Err(BlockValidationErr::InvalidHash)));
+
+    let mut chain = hard().with_consensus(AcceptAll);
+    chain.update_with_block(block).unwrap();
+    assert_eq!(chain.block_count(), 1);
+}
+
+#[test]
+fn a_chain_hashes_everything_with_its_hasher () {
+    let mut sha256 = chain();
+    let mut sha512 = chain().with_hasher(Sha512Truncated);
+    mine_blocks(&mut sha256, 1, 1);
+    mine_blocks(&mut sha512, 1, 1);
+
+    let block = sha512.block(0).unwrap();
+    assert_eq!(sha512.last_hash(), sha512.hash_of(block));
+    assert_ne!(sha512.hash_of(block), block.hash());
+    assert_ne!(sha512.hash_of(&block.transactions[0]), block.transactions[0].hash());
+
+    // The next block links to the parent by the chain's own hash
+    let next = mine_block(&sha512, vec![], 0, 1);
+    assert_eq!(next.header.prev_block_hash, sha512.hash_of(block));
+    assert!(matches!(sha256.update_with_block(next.clone()), Err(BlockValidationErr::MismatchedPreviousHash)));
+    sha512.update_with_block(next).unwrap();
+}
+
+#[test]
+#[should_panic]
+fn the_hasher_can_t_change_once_there_are_blocks () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+
+    let _ = chain.with_hasher(Sha512Truncated);
+}
+
+#[test]
+fn proof_of_stake_accepts_only_producers_with_stake () {
+    let stake = || ProofOfStake { target: u128::MAX / 100, slot_duration: 1_000 };
+    let mut chain = chain().with_consensus(stake());
+    mine_blocks(&mut chain, 1, 1);
+
+    // 1 holds the genesis coinbase's 50; 2 holds nothing
+    let staker = block_in_first_eligible_slot(&chain, &stake(), 1);
+    let nobody = mine_block(&chain, vec![], 0, 2);
+    assert!(!stake().is_eligible(&chain, &chain.last_hash(), &addr(2), nobody.header.timestamp));
+    assert!(matches!(chain.update_with_block(nobody), Err(BlockValidationErr::IneligibleProducer)));
+
+    chain.update_with_block(staker).unwrap();
+    assert_eq!(chain.block_count(), 2);
+}
+
+// A block by `producer` in the first slot from now it is eligible for
+fn block_in_first_eligible_slot (chain: &Blockchain, engine: &ProofOfStake, producer: u64) -> Block {
+    loop {
+        let block = mine_block(chain, vec![], 0, producer);
+        if engine.is_eligible(chain, &chain.last_hash(), &addr(producer), block.header.timestamp) {
+            return block;
+        }
+    }
+}
+
+#[cfg(feature = "test-pow")]
+#[test]
+fn test_proof_of_work_skips_mining_off_mainnet () {
+    const DIFFICULTY: u128 = u128::MAX >> 40;
+    let testnet = || Blockchain::new(DIFFICULTY).with_clock(tick).with_network(TESTNET);
+    let unmined = |chain: &Blockchain, network: u32| {
+        let mut block = Block::new(0, tick(), Hash::zero(), vec![coinbase(1, 50, 0)], DIFFICULTY);
+        block.header.network = network;
+        block.header.utxo_root = chain.utxo_root_for(&block).unwrap();
+        assert_eq!(block.header.nonce, 0);
+        block
+    };
+
+    let mut chain = testnet();
+    let block = unmined(&chain, TESTNET);
+    assert!(matches!(chain.update_with_block(block.clone()), Err(BlockValidationErr::InvalidHash)));
+
+    let mut chain = testnet().with_consensus(TestProofOfWork);
+    chain.update_with_block(block).unwrap();
+
+    // The stated difficulty is still checked
+    let mut block = Block::new(1, tick(), chain.last_hash(), vec![coinbase(1, 50, 1)], DIFFICULTY * 2);
+    block.header.network = TESTNET;
+    block.header.utxo_root = chain.utxo_root_for(&block).unwrap();
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::UnexpectedDifficulty)));
+
+    // Mainnet blocks still need the work
+    let mut mainnet = Blockchain::new(DIFFICULTY).with_clock(tick).with_consensus(TestProofOfWork);
+    let block = unmined(&mainnet, MAINNET);
+    assert!(matches!(mainnet.update_with_block(block), Err(BlockValidationErr::InvalidHash)));
+}
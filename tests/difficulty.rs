@@ -0,0 +1,112 @@
+mod common;
+
+use blockchainlib::*;
+use common::*;
+
+#[test]
+fn fast_blocks_raise_the_difficulty () {
+    let mut chain = chain().with_retargeting(60_000, 4);
+    mine_spaced(&mut chain, 3, 1_000, 1);
+    assert_eq!(chain.difficulty(), u128::MAX);
+
+    mine_spaced(&mut chain, 1, 1_000, 1);
+    // Harder means a lower threshold, by at most the clamp's factor of four
+    assert_eq!(chain.difficulty(), u128::MAX / 4);
+}
+
+#[test]
+fn slow_blocks_lower_the_difficulty () {
+    let mut chain = Blockchain::new(u128::MAX / 64)
+        .with_clock(tick)
+        .with_min_difficulty(u128::MAX)
+        .with_retargeting(1_000, 4);
+    mine_spaced(&mut chain, 4, 2_000, 1);
+
+    assert_eq!(chain.difficulty(), u128::MAX / 64 * 2);
+}
+
+// The next block on `chain`, mined to meet `difficulty` and stating it
+fn mine_stating (chain: &Blockchain, difficulty: u128) -> Block {
+    let index = chain.block_count() as u32;
+    let mut block = Block::new(index, tick(), chain.last_hash(), vec![coinbase(1, 50, index)], difficulty);
+    block.header.utxo_root = chain.utxo_root_for(&block).unwrap();
+    block.mine_with(difficulty, chain.hasher()).unwrap();
+    block
+}
+
+#[test]
+fn blocks_must_state_the_expected_difficulty () {
+    let mut chain = chain().with_retargeting(60_000, 4);
+    mine_spaced(&mut chain, 4, 1_000, 1);
+    assert_eq!(chain.expected_difficulty(4), u128::MAX / 4);
+
+    // Easier than it should be, though the hash meets what it states
+    let spoofed = mine_stating(&chain, u128::MAX);
+    assert!(check_difficulty(&spoofed.hash(), u128::MAX));
+    assert!(matches!(chain.update_with_block(spoofed), Err(BlockValidationErr::UnexpectedDifficulty)));
+
+    let harder = mine_stating(&chain, u128::MAX / 8);
+    assert!(matches!(chain.update_with_block(harder), Err(BlockValidationErr::UnexpectedDifficulty)));
+
+    let block = mine_stating(&chain, u128::MAX / 4);
+    chain.update_with_block(block).unwrap();
+}
+
+#[test]
+fn genesis_may_be_mined_easier_than_the_running_difficulty () {
+    let running = u128::MAX / 16;
+    let easy = || Blockchain::new(running).with_clock(tick).with_genesis_difficulty(u128::MAX);
+
+    let mut chain = easy();
+    assert_eq!(chain.expected_difficulty(0), u128::MAX);
+    assert_eq!(chain.expected_difficulty(1), running);
+
+    // A genesis mined at the easy difficulty that misses the running one
+    let genesis = loop {
+        let genesis = mine_block(&chain, vec![], 0, 1);
+        if !check_difficulty(&genesis.hash(), running) {
+            break genesis;
+        }
+    };
+    assert!(matches!(Blockchain::new(running).with_clock(tick).update_with_block(genesis.clone()), Err(BlockValidationErr::UnexpectedDifficulty)));
+    chain.update_with_block(genesis).unwrap();
+
+    let block = mine_block(&chain, vec![], 0, 1);
+    assert_eq!(block.header.difficulty, running);
+    chain.update_with_block(block).unwrap();
+
+    // Replaying applies each height's difficulty too
+    let replayed = easy().with_blocks(chain.blocks().cloned().collect()).unwrap();
+    assert_eq!(replayed.last_hash(), chain.last_hash());
+    assert!(replayed.verify().is_ok());
+}
+
+#[test]
+fn set_difficulty_refuses_zero_and_easier_than_the_minimum () {
+    let mut chain = Blockchain::new(u128::MAX / 4);
+
+    assert!(matches!(chain.set_difficulty(0), Err(DifficultyError::Zero)));
+    assert!(matches!(chain.set_difficulty(u128::MAX / 2), Err(DifficultyError::EasierThanMinimum)));
+    assert_eq!(chain.difficulty(), u128::MAX / 4);
+
+    chain.set_difficulty(u128::MAX / 8).unwrap();
+    assert_eq!(chain.difficulty(), u128::MAX / 8);
+    chain.set_difficulty(u128::MAX / 4).unwrap();
+    assert_eq!(chain.difficulty(), chain.min_difficulty());
+}
+
+#[test]
+fn a_long_run_of_slow_blocks_stops_at_the_floor () {
+    let mut chain = Blockchain::new(u128::MAX / 1024)
+        .with_clock(tick)
+        .with_min_difficulty(u128::MAX / 64)
+        .with_retargeting(1_000, 2);
+
+    // Each retarget eases by the clamp's factor of four, so this would go
+    // well past the floor without it
+    mine_spaced(&mut chain, 20, 60_000, 1);
+
+    assert_eq!(chain.difficulty(), u128::MAX / 64);
+    assert_eq!(chain.min_difficulty(), u128::MAX / 64);
+    assert!(chain.difficulty() < u128::MAX);
+}
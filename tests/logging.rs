@@ -0,0 +1,72 @@
+#![cfg(feature = "logging")]
+
+mod common;
+
+use blockchainlib::*;
+use common::*;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::sync::Mutex;
+
+// Keeps every record logged, for the tests to look through
+struct Capture;
+
+static RECORDS: Mutex<Vec<(Level, String)>> = Mutex::new(vec![]);
+static CAPTURE: Capture = Capture;
+
+impl Log for Capture {
+    fn enabled (&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log (&self, record: &Record) {
+        RECORDS.lock().unwrap().push((record.level(), record.args().to_string()));
+    }
+
+    fn flush (&self) {}
+}
+
+// Starts capturing, if another test hasn't already
+fn capture () {
+    let _ = log::set_logger(&CAPTURE);
+    log::set_max_level(LevelFilter::Debug);
+}
+
+// The records so far mentioning `hash`. Tests run in parallel, so they only
+// look at records about their own blocks and transactions.
+fn logged (hash: &Hash) -> Vec<(Level, String)> {
+    let hash = hash.to_string();
+    RECORDS.lock().unwrap().iter().filter(|(_, message)| message.contains(&hash)).cloned().collect()
+}
+
+#[test]
+fn a_rejected_block_logs_a_warning_with_the_error () {
+    let mut chain = chain();
+    capture();
+    mine_blocks(&mut chain, 1, 1);
+
+    let mut block = mine_block(&chain, vec![], 0, 1);
+    block.header.index = 5;
+    block.mine_with(block.header.difficulty, chain.hasher()).unwrap();
+    let hash = block.hash();
+    assert!(chain.update_with_block(block).is_err());
+
+    let records = logged(&hash);
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].0, Level::Warn);
+    assert_eq!(records[0].1, format!("rejected block {} at height 5: MismatchedIndex", hash));
+}
+
+#[test]
+fn an_accepted_block_logs_its_height_and_transactions () {
+    let mut chain = chain();
+    capture();
+    mine_blocks(&mut chain, 1, 1);
+
+    let transaction = pay(coinbase_output(&chain, 0).0, 1, 2, 46);
+    let mut mempool = Mempool::new();
+    mempool.add_transaction(transaction.clone(), &chain).unwrap();
+    let hash = chain.mine_next_block(&addr(3), &mut mempool).unwrap();
+
+    assert!(logged(&hash).contains(&(Level::Info, format!("accepted block {} at height 1 with 2 transaction(s)", hash))));
+    assert!(logged(&transaction.hash()).contains(&(Level::Debug, format!("added transaction {} paying 4 for {} bytes", transaction.hash(), transaction.size()))));
+}
@@ -0,0 +1,195 @@
+mod common;
+
+use blockchainlib::*;
+use blockchainlib::transaction::TxStructureErr;
+use common::*;
+
+// Spends `outpoint`, worth `value` and owned by 1, paying `fee`
+fn spend (outpoint: OutPoint, value: u64, fee: u64) -> Transaction {
+    pay(outpoint, 1, 1, value - fee)
+}
+
+#[test]
+fn a_better_paying_conflict_replaces_a_transaction_and_its_children () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+    let mut mempool = Mempool::new();
+    let outpoint = coinbase_output(&chain, 0).0;
+
+    let low = spend(outpoint, 50, 1);
+    let child = spend(low.outpoints()[0], 49, 1);
+    mempool.add_transaction(low.clone(), &chain).unwrap();
+    mempool.add_transaction(child.clone(), &chain).unwrap();
+
+    // Has to beat the fees of both
+    let same = spend(outpoint, 50, 2);
+    assert!(matches!(mempool.add_transaction(same, &chain), Err(MempoolErr::DoubleSpend)));
+    assert_eq!(mempool.len(), 2);
+
+    let high = spend(outpoint, 50, 3);
+    mempool.add_transaction(high.clone(), &chain).unwrap();
+    assert_eq!(mempool.len(), 1);
+    assert!(mempool.contains(&high.hash()));
+    assert!(!mempool.contains(&low.hash()));
+    assert!(!mempool.contains(&child.hash()));
+    assert!(mempool.is_spent(&outpoint));
+    assert!(!mempool.is_spent(&low.outpoints()[0]));
+}
+
+#[test]
+fn the_lowest_fee_rates_are_evicted_under_the_size_cap () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 5, 1);
+    let spends = [1, 2, 3, 5, 1]
+        .iter()
+        .enumerate()
+        .map(|(height, &fee)| spend(coinbase_output(&chain, height as u32).0, 50, fee))
+        .collect::<Vec<Transaction>>();
+
+    // Same-shaped transactions, so room for three
+    let size = spends[0].size();
+    assert!(spends.iter().all(|transaction| transaction.size() == size));
+    let mut mempool = Mempool::new().with_max_size(size * 3);
+
+    for transaction in &spends[..3] {
+        mempool.add_transaction(transaction.clone(), &chain).unwrap();
+    }
+    assert_eq!(mempool.size(), size * 3);
+
+    mempool.add_transaction(spends[3].clone(), &chain).unwrap();
+    assert_eq!(mempool.len(), 3);
+    assert!(mempool.size() <= size * 3);
+    assert!(!mempool.contains(&spends[0].hash()));
+    assert!(spends[1..4].iter().all(|transaction| mempool.contains(&transaction.hash())));
+
+    // Pays no more than anything held
+    assert!(matches!(mempool.add_transaction(spends[4].clone(), &chain), Err(MempoolErr::MempoolFull)));
+    assert_eq!(mempool.len(), 3);
+}
+
+#[test]
+fn blocks_are_filled_by_fee_rate_rather_than_fee () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 2, 1);
+    let mut mempool = Mempool::new();
+
+    let small = spend(coinbase_output(&chain, 0).0, 50, 3);
+    let mut large = pay(coinbase_output(&chain, 1).0, 1, 2, 5);
+    for recipient in 3..13 {
+        large.outputs.push(transaction::Output { to_addr: addr(recipient), value: 4, script: vec![] });
+    }
+    large.sign(&key(1));
+    mempool.add_transaction(small.clone(), &chain).unwrap();
+    mempool.add_transaction(large.clone(), &chain).unwrap();
+
+    // The large one pays more in all, but less per byte
+    assert!(chain.fee(&large).unwrap() > chain.fee(&small).unwrap());
+    assert!(mempool.fee_rate(&large.hash()).unwrap() < mempool.fee_rate(&small.hash()).unwrap());
+    assert_eq!(mempool.fee_rate(&small.hash()), Some(transaction::fee_rate(3, small.size())));
+
+    let selected = mempool.select_for_block(1);
+    assert_eq!(selected.len(), 1);
+    assert_eq!(selected[0].hash(), small.hash());
+    assert_eq!(mempool.select_for_block(2)[1].hash(), large.hash());
+}
+
+#[test]
+fn a_well_paying_child_pulls_in_its_parent () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 2, 1);
+    let mut mempool = Mempool::new();
+
+    let parent = spend(coinbase_output(&chain, 0).0, 50, 1);
+    let child = spend(parent.outpoints()[0], 49, 11);
+    let other = spend(coinbase_output(&chain, 1).0, 50, 3);
+    for transaction in [&parent, &child, &other] {
+        mempool.add_transaction(transaction.clone(), &chain).unwrap();
+    }
+
+    // On its own the parent pays least, but with its child it pays most
+    let hashes = |selected: Vec<Transaction>| selected.iter().map(|transaction| transaction.hash()).collect::<Vec<Hash>>();
+    assert_eq!(hashes(mempool.select_for_block(3)), [parent.hash(), child.hash(), other.hash()]);
+    assert_eq!(hashes(mempool.select_for_block(2)), [parent.hash(), child.hash()]);
+
+    // The package doesn't fit, and the child can't go in without its parent
+    assert_eq!(hashes(mempool.select_for_block(1)), [other.hash()]);
+
+    let block = mine_block(&chain, mempool.select_for_block(2), 12, 1);
+    chain.update_with_block(block).unwrap();
+    assert_eq!(chain.confirmations(&child.hash()), Some(1));
+}
+
+#[test]
+fn fee_rates_of_empty_sizes_don_t_divide_by_zero () {
+    assert_eq!(transaction::fee_rate(5, 0), 5.0);
+    assert_eq!(transaction::fee_rate(0, 0), 0.0);
+}
+
+#[test]
+fn missing_lists_exactly_what_a_peer_has_that_we_lack () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 4, 1);
+    let spends = (0..4)
+        .map(|height| spend(coinbase_output(&chain, height).0, 50, 1))
+        .collect::<Vec<Transaction>>();
+
+    // Ours holds 0, 1 and 2, theirs 1, 2 and 3
+    let (mut ours, mut theirs) = (Mempool::new(), Mempool::new());
+    for transaction in &spends[..3] {
+        ours.add_transaction(transaction.clone(), &chain).unwrap();
+    }
+    for transaction in &spends[1..] {
+        theirs.add_transaction(transaction.clone(), &chain).unwrap();
+    }
+
+    let mut expected = spends[..3].iter().map(Transaction::hash).collect::<Vec<Hash>>();
+    expected.sort_unstable();
+    assert_eq!(ours.inventory(), expected);
+
+    assert_eq!(ours.missing(&theirs.inventory()), vec![spends[3].hash()]);
+    assert_eq!(theirs.missing(&ours.inventory()), vec![spends[0].hash()]);
+
+    // Repeats are asked for once
+    let repeated = [spends[3].hash(), spends[3].hash()];
+    assert_eq!(ours.missing(&repeated), vec![spends[3].hash()]);
+    assert!(ours.missing(&ours.inventory()).is_empty());
+}
+
+#[test]
+fn malformed_transactions_are_refused_before_the_chain_is_consulted () {
+    let chain = chain();
+    let mut mempool = Mempool::new();
+    // Nothing on the chain, so only the structure can be what's wrong
+    let unknown = OutPoint { txid: Hash::digest(b"unknown"), index: 0 };
+
+    let mut duplicate = pay(unknown, 1, 2, 10);
+    duplicate.inputs.push(duplicate.inputs[0].clone());
+    assert!(matches!(mempool.add_transaction(duplicate, &chain), Err(MempoolErr::InvalidStructure(TxStructureErr::DuplicateInput))));
+
+    let mut empty = pay(unknown, 1, 2, 10);
+    empty.outputs.clear();
+    assert!(matches!(mempool.add_transaction(empty, &chain), Err(MempoolErr::InvalidStructure(TxStructureErr::NoOutputs))));
+    assert!(mempool.is_empty());
+}
+
+#[test]
+fn transactions_must_pay_the_minimum_relay_fee_rate () {
+    let mut chain = chain().with_block_reward(10_000, 100);
+    mine_blocks(&mut chain, 1, 1);
+    let outpoint = coinbase_output(&chain, 0).0;
+    let mut mempool = Mempool::new();
+    assert_eq!(mempool.min_relay_fee_rate(), 0);
+
+    // One per byte, so a transaction's fee has to be at least its size
+    mempool.set_min_relay_fee_rate(1_000);
+    let size = spend(outpoint, 10_000, 1).size() as u64;
+
+    let below = spend(outpoint, 10_000, size - 1);
+    assert!(matches!(mempool.add_transaction(below.clone(), &chain), Err(MempoolErr::FeeTooLow)));
+    assert!(mempool.is_empty());
+    mempool.add_transaction(spend(outpoint, 10_000, size), &chain).unwrap();
+
+    // Blocks aren't held to it
+    let block = mine_block(&chain, vec![below], size - 1, 1);
+    chain.update_with_block(block).unwrap();
+}
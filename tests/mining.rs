@@ -0,0 +1,138 @@
+mod common;
+
+use blockchainlib::*;
+use common::*;
+
+const DIFFICULTY: u128 = u128::MAX / 16;
+
+fn hard_chain () -> Blockchain {
+    Blockchain::new(DIFFICULTY).with_clock(tick)
+}
+
+#[test]
+fn mined_blocks_are_accepted () {
+    let mut chain = hard_chain();
+
+    for _ in 0..3 {
+        let block = mine_block(&chain, vec![], 0, 1);
+        assert!(check_difficulty(&block.hash(), DIFFICULTY));
+        chain.update_with_block(block).unwrap();
+    }
+
+    assert_eq!(chain.block_count(), 3);
+}
+
+#[test]
+fn blocks_missing_the_difficulty_are_rejected () {
+    let mut chain = hard_chain();
+    let mut block = mine_block(&chain, vec![], 0, 1);
+
+    while check_difficulty(&block.hash(), DIFFICULTY) {
+        block.header.nonce += 1;
+    }
+
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::InvalidHash)));
+}
+
+#[test]
+fn a_broken_parent_link_is_not_reported_as_a_bad_hash () {
+    let mut chain = hard_chain();
+    mine_blocks(&mut chain, 1, 1);
+
+    // Meets the difficulty, but names the wrong parent
+    let mut unlinked = mine_block(&chain, vec![], 0, 1);
+    unlinked.header.prev_block_hash = Hash::digest(b"elsewhere");
+    unlinked.mine_with(DIFFICULTY, chain.hasher()).unwrap();
+    assert!(matches!(chain.update_with_block(unlinked), Err(BlockValidationErr::MismatchedPreviousHash)));
+
+    // Names the right parent, but misses the difficulty
+    let mut unmined = mine_block(&chain, vec![], 0, 1);
+    while check_difficulty(&unmined.hash(), DIFFICULTY) {
+        unmined.header.nonce += 1;
+    }
+    assert!(matches!(chain.update_with_block(unmined.clone()), Err(BlockValidationErr::InvalidHash)));
+
+    unmined.mine_with(DIFFICULTY, chain.hasher()).unwrap();
+    chain.update_with_block(unmined).unwrap();
+}
+
+#[test]
+fn parallel_mining_finds_a_valid_nonce () {
+    // About 65,000 hashes on average, enough for every worker to search
+    let difficulty = u128::MAX >> 16;
+    let mut chain = Blockchain::new(difficulty).with_clock(tick);
+    let mut block = mine_block(&chain, vec![], 0, 1);
+
+    for threads in [1, 4] {
+        block.mine_parallel(difficulty, threads).unwrap();
+
+        assert!(check_difficulty(&block.hash(), difficulty));
+        assert_eq!(block.header.difficulty, difficulty);
+    }
+
+    chain.update_with_block(block).unwrap();
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn async_mining_stops_once_cancelled () {
+    use std::time::{Duration, Instant};
+    use tokio_util::sync::CancellationToken;
+
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let chain = hard_chain();
+    let mut block = mine_block(&chain, vec![], 0, 1);
+    let (nonce, difficulty) = (block.header.nonce, block.header.difficulty);
+
+    let cancel = CancellationToken::new();
+    let started = Instant::now();
+    let (block, result) = runtime.block_on(async {
+        // A difficulty a hash would all but never meet, so only cancelling ends it
+        let mining = tokio::spawn({
+            let cancel = cancel.clone();
+            async move {
+                let result = block.mine_async(1, cancel).await;
+                (block, result)
+            }
+        });
+
+        tokio::task::yield_now().await;
+        cancel.cancel();
+        mining.await.unwrap()
+    });
+
+    assert!(matches!(result, Err(MineError::Cancelled)));
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!((block.header.nonce, block.header.difficulty), (nonce, difficulty));
+}
+
+#[test]
+fn mine_next_block_confirms_the_mempool_and_pays_the_miner () {
+    let genesis = build_genesis(GenesisConfig {
+        difficulty: DIFFICULTY,
+        initial_reward: 50,
+        recipient: addr(1),
+        timestamp: tick(),
+    }).unwrap();
+    let mut chain = Blockchain::with_genesis(DIFFICULTY, genesis).unwrap()
+        .with_clock(tick)
+        .with_coinbase_maturity(0);
+
+    let transaction = pay(coinbase_output(&chain, 0).0, 1, 2, 46);
+    let mut mempool = Mempool::new();
+    mempool.add_transaction(transaction.clone(), &chain).unwrap();
+
+    let hash = chain.mine_next_block(&addr(3), &mut mempool).unwrap();
+    assert_eq!(chain.last_hash(), hash);
+    assert!(mempool.is_empty());
+    assert_eq!(chain.confirmations(&transaction.hash()), Some(1));
+    assert_eq!(chain.get_balance(&addr(2)), 46);
+    assert_eq!(chain.get_balance(&addr(3)), chain.block_reward(1) + 4);
+
+    // What it mined replays on a fresh chain
+    let replayed = Blockchain::new(DIFFICULTY)
+        .with_coinbase_maturity(0)
+        .with_blocks(chain.blocks().cloned().collect())
+        .unwrap();
+    assert_eq!(replayed.last_hash(), hash);
+}
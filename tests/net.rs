@@ -0,0 +1,76 @@
+#![cfg(feature = "net")]
+
+mod common;
+
+use blockchainlib::*;
+use blockchainlib::net::{Message, NetError, Peer, MAX_MESSAGE_SIZE};
+use common::*;
+use std::convert::TryInto;
+use std::net::TcpListener;
+
+#[test]
+fn every_message_round_trips_through_its_frame () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 2, 1);
+    let block = chain.blocks().last().unwrap().clone();
+    let transaction = pay(coinbase_output(&chain, 0).0, 1, 2, 50);
+
+    let messages = vec![
+        Message::Version { height: 2, tip: chain.last_hash() },
+        Message::GetBlocks { locator: chain.block_locator() },
+        Message::Block(block),
+        Message::Tx(transaction),
+        Message::Inv(vec![chain.last_hash()]),
+        Message::GetMempool,
+        Message::TxInv(vec![Hash::zero()]),
+        Message::GetTxs(vec![Hash::zero(), chain.last_hash()]),
+    ];
+
+    for message in messages {
+        let bytes = message.encode().unwrap();
+        assert_eq!(u32::from_le_bytes(bytes[..4].try_into().unwrap()) as usize, bytes.len() - 4);
+
+        let decoded = Message::read_from(&mut &bytes[..]).unwrap();
+        assert_eq!(decoded.encode().unwrap(), bytes);
+    }
+}
+
+#[test]
+fn bad_frames_are_refused () {
+    let too_large = (MAX_MESSAGE_SIZE + 1).to_le_bytes();
+    assert!(matches!(Message::read_from(&mut &too_large[..]), Err(NetError::MessageTooLarge)));
+
+    let bytes = Message::GetMempool.encode().unwrap();
+    assert!(matches!(Message::read_from(&mut &bytes[..bytes.len() - 1]), Err(NetError::Io(_))));
+
+    let garbage = [1, 0, 0, 0, 0xff];
+    assert!(matches!(Message::read_from(&mut &garbage[..]), Err(NetError::Decode(_))));
+}
+
+#[test]
+fn a_peer_catches_up_over_tcp () {
+    let mut ahead = chain();
+    mine_blocks(&mut ahead, 4, 1);
+    let mut behind = fork(chain(), &ahead, 1);
+    let mut mempool = Mempool::new();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = Peer::connect(listener.local_addr().unwrap()).unwrap();
+    let mut server = Peer::new(listener.accept().unwrap().0);
+
+    // The server announces it is ahead, and the client asks for what it lacks
+    server.send_version(&ahead).unwrap();
+    let version = client.receive().unwrap();
+    client.handle(version, &mut behind, &mut mempool).unwrap();
+
+    let get_blocks = server.receive().unwrap();
+    server.handle(get_blocks, &mut ahead, &mut Mempool::new()).unwrap();
+
+    for _ in 1..4 {
+        let block = client.receive().unwrap();
+        client.handle(block, &mut behind, &mut mempool).unwrap();
+    }
+
+    assert_eq!(behind.block_count(), 4);
+    assert_eq!(behind.last_hash(), ahead.last_hash());
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use blockchainlib::*;
+use common::*;
+
+// Blocks 1 to `count` of a chain built on `chain`'s genesis elsewhere
+fn ahead (chain: &Blockchain, count: usize) -> Vec<Block> {
+    let mut source = fork(common::chain(), chain, chain.block_count());
+    mine_blocks(&mut source, count, 2);
+    source.blocks().skip(1).cloned().collect()
+}
+
+#[test]
+fn blocks_delivered_out_of_order_connect_once_the_parent_arrives () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+    let blocks = ahead(&chain, 3);
+
+    assert!(!chain.add_block(blocks[2].clone()).unwrap());
+    assert!(!chain.add_block(blocks[1].clone()).unwrap());
+    assert_eq!(chain.orphan_count(), 2);
+    assert_eq!(chain.block_count(), 1);
+    assert!(chain.has_block(&blocks[2].hash()));
+
+    assert!(chain.add_block(blocks[0].clone()).unwrap());
+    assert_eq!(chain.orphan_count(), 0);
+    assert_eq!(chain.block_count(), 4);
+    assert_eq!(chain.last_hash(), blocks[2].hash());
+
+    // Already known, so ignored
+    assert!(!chain.add_block(blocks[1].clone()).unwrap());
+}
+
+#[test]
+fn orphans_are_checked_before_they_are_kept () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+    let mut blocks = ahead(&chain, 2);
+
+    blocks[1].transactions[0].outputs[0].value += 1;
+    assert!(matches!(chain.add_block(blocks[1].clone()), Err(BlockValidationErr::InvalidMerkleRoot)));
+    assert_eq!(chain.orphan_count(), 0);
+}
+
+#[test]
+fn a_block_submitted_twice_is_a_no_op_the_second_time () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+    let blocks = ahead(&chain, 1);
+
+    assert!(chain.add_block(blocks[0].clone()).unwrap());
+    let (tip, utxo_set_hash, work) = (chain.last_hash(), chain.utxo_set_hash(), chain.total_work());
+    assert!(!chain.add_block(blocks[0].clone()).unwrap());
+    assert_eq!((chain.last_hash(), chain.utxo_set_hash(), chain.total_work()), (tip, utxo_set_hash, work));
+
+    // Pruned blocks, and blocks a reorg moved to a side branch, stay known
+    mine_blocks(&mut chain, 2, 1);
+    let mut heavier = fork(common::chain(), &chain, 1);
+    mine_blocks(&mut heavier, 4, 3);
+    chain.prune(1);
+    assert!(!chain.add_block(blocks[0].clone()).unwrap());
+
+    for block in heavier.blocks().skip(1) {
+        assert!(chain.add_block(block.clone()).unwrap());
+    }
+    assert_eq!(chain.last_hash(), heavier.last_hash());
+    assert!(chain.block_tree().is_side(&blocks[0].hash()));
+    assert!(!chain.add_block(blocks[0].clone()).unwrap());
+    assert_eq!(chain.orphan_count(), 0);
+
+    // A block that failed as its branch connected isn't known, and gets its
+    // error again each time
+    let mut branch = fork(common::chain(), &heavier, heavier.block_count() - 1);
+    mine_blocks(&mut branch, 1, 4);
+    let overpaying = mine_block(&branch, vec![], 1, 4);
+    assert!(chain.add_block(branch.tip().unwrap().clone()).unwrap());
+    let err = format!("{:?}", chain.add_block(overpaying.clone()).unwrap_err());
+    assert!(!chain.has_block(&overpaying.hash()));
+    assert_eq!(format!("{:?}", chain.add_block(overpaying.clone()).unwrap_err()), err);
+    assert_eq!(format!("{:?}", chain.update_with_block(overpaying).unwrap_err()), err);
+    assert_eq!(chain.last_hash(), heavier.last_hash());
+}
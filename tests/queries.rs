@@ -0,0 +1,373 @@
+mod common;
+
+use blockchainlib::*;
+use common::*;
+
+#[test]
+fn finds_a_mined_transaction () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+
+    let transaction = pay(coinbase_output(&chain, 0).0, 1, 2, 50);
+    let block = mine_block(&chain, vec![transaction.clone()], 0, 1);
+    chain.update_with_block(block).unwrap();
+    mine_blocks(&mut chain, 1, 1);
+
+    let (index, found) = chain.find_transaction(&transaction.hash()).unwrap();
+    assert_eq!(index, 1);
+    assert_eq!(found.hash(), transaction.hash());
+    assert!(chain.find_transaction(&Hash::zero()).is_none());
+
+    chain.rollback();
+    chain.rollback();
+    assert!(chain.find_transaction(&transaction.hash()).is_none());
+}
+
+#[test]
+fn finds_blocks_by_hash () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 3, 1);
+
+    for block in chain.blocks() {
+        assert_eq!(chain.get_block_by_hash(&block.hash()), Some(block));
+    }
+    assert!(chain.get_block_by_hash(&Hash::zero()).is_none());
+
+    let tip = chain.last_hash();
+    chain.rollback();
+    assert!(chain.get_block_by_hash(&tip).is_none());
+}
+
+#[test]
+fn proves_a_transaction_is_in_a_block () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 3, 1);
+
+    let transactions = (0..3)
+        .map(|height| pay(coinbase_output(&chain, height).0, 1, 2, 50))
+        .collect::<Vec<Transaction>>();
+    let block = mine_block(&chain, transactions, 0, 1);
+    chain.update_with_block(block.clone()).unwrap();
+
+    for transaction in &block.transactions {
+        let proof = block.merkle_proof(&transaction.hash()).unwrap();
+        assert!(verify_merkle_proof(&transaction.wtxid(), &proof, &block.header.merkle_root));
+    }
+
+    let proof = block.merkle_proof(&block.transactions[1].hash()).unwrap();
+    assert!(!verify_merkle_proof(&block.transactions[2].wtxid(), &proof, &block.header.merkle_root));
+    assert!(block.merkle_proof(&Hash::zero()).is_none());
+}
+
+#[test]
+fn proves_a_transaction_is_in_a_block_on_a_chain_with_another_hasher () {
+    let mut chain = chain().with_hasher(Sha512Truncated);
+    mine_blocks(&mut chain, 2, 1);
+    let coinbase = OutPoint { txid: chain.hash_of(&chain.block(1).unwrap().transactions[0]), index: 0 };
+    let block = mine_block(&chain, vec![pay(coinbase, 1, 2, 50)], 0, 1);
+    chain.update_with_block(block.clone()).unwrap();
+    let hasher = chain.hasher();
+
+    for transaction in &block.transactions {
+        let proof = block.merkle_proof_with(&transaction.hash_with(hasher), hasher).unwrap();
+        let leaf = transaction.wtxid_with(hasher);
+        assert!(verify_merkle_proof_with(&leaf, &proof, &block.header.merkle_root, hasher));
+        assert!(!verify_merkle_proof(&leaf, &proof, &block.header.merkle_root));
+    }
+
+    // The SHA-256 tree isn't the one the header commits to
+    let proof = block.merkle_proof(&block.transactions[1].hash()).unwrap();
+    assert!(!verify_merkle_proof(&block.transactions[1].wtxid(), &proof, &block.header.merkle_root));
+}
+
+#[test]
+fn finds_the_spender_of_an_output () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 2, 1);
+
+    let spent = coinbase_output(&chain, 0).0;
+    let unspent = coinbase_output(&chain, 1).0;
+    assert!(chain.find_spender(&spent).is_none());
+
+    let transaction = pay(spent, 1, 2, 50);
+    let block = mine_block(&chain, vec![transaction.clone()], 0, 1);
+    chain.update_with_block(block).unwrap();
+
+    let (index, spender) = chain.find_spender(&spent).unwrap();
+    assert_eq!(index, 2);
+    assert_eq!(spender.hash(), transaction.hash());
+    assert!(chain.find_spender(&unspent).is_none());
+    assert!(chain.find_spender(&transaction.outpoints()[0]).is_none());
+
+    chain.rollback();
+    assert!(chain.find_spender(&spent).is_none());
+}
+
+#[test]
+fn outputs_flip_to_spent_once_consumed () {
+    let mut chain = chain().with_indexing(true);
+    mine_blocks(&mut chain, 1, 1);
+
+    let outpoint = coinbase_output(&chain, 0).0;
+    let transaction = pay(outpoint, 1, 2, 50);
+    assert!(chain.is_unspent(&outpoint));
+    assert!(!chain.contains_transaction(&transaction.hash()));
+
+    let block = mine_block(&chain, vec![transaction.clone()], 0, 1);
+    chain.update_with_block(block).unwrap();
+
+    assert!(!chain.is_unspent(&outpoint));
+    assert!(chain.is_unspent(&transaction.outpoints()[0]));
+    assert!(chain.contains_transaction(&transaction.hash()));
+    assert!(!chain.is_unspent(&OutPoint { txid: Hash::zero(), index: 0 }));
+}
+
+#[test]
+fn without_indexing_transactions_aren_t_found () {
+    let mut chain = chain().with_indexing(false);
+    mine_blocks(&mut chain, 1, 1);
+
+    let outpoint = coinbase_output(&chain, 0).0;
+    let transaction = pay(outpoint, 1, 2, 50);
+    let block = mine_block(&chain, vec![transaction.clone()], 0, 1);
+    chain.update_with_block(block).unwrap();
+
+    // Validation doesn't depend on the indexes
+    assert!(!chain.is_unspent(&outpoint));
+    assert!(!chain.contains_transaction(&transaction.hash()));
+    assert!(chain.find_transaction(&transaction.hash()).is_none());
+    assert!(chain.find_spender(&outpoint).is_none());
+}
+
+#[test]
+fn tip_accessors_on_an_empty_chain () {
+    let chain = chain();
+
+    assert!(chain.tip().is_none());
+    assert_eq!(chain.height(), None);
+    assert_eq!(chain.last_hash(), Hash::zero());
+}
+
+#[test]
+fn tip_accessors_follow_the_last_block () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+    assert_eq!(chain.height(), Some(0));
+    assert_eq!(chain.last_hash(), chain.block(0).unwrap().hash());
+
+    mine_blocks(&mut chain, 2, 1);
+    let tip = chain.tip().unwrap();
+    assert_eq!(tip.header.index, 2);
+    assert_eq!(chain.height(), Some(2));
+    assert_eq!(chain.last_hash(), tip.hash());
+
+    chain.rollback();
+    assert_eq!(chain.height(), Some(1));
+    assert_eq!(chain.last_hash(), chain.block(1).unwrap().hash());
+}
+
+#[test]
+fn without_indexing_validation_is_unchanged () {
+    let mut chain = chain().with_indexing(false);
+    mine_blocks(&mut chain, 2, 1);
+
+    let outpoint = coinbase_output(&chain, 0).0;
+    let transaction = pay(outpoint, 1, 2, 50);
+    let block = mine_block(&chain, vec![transaction.clone()], 0, 1);
+    chain.update_with_block(block).unwrap();
+    assert_eq!(chain.confirmations(&transaction.hash()), None);
+
+    // Spent, whether or not anything records by whom
+    let again = mine_block(&chain, vec![pay(outpoint, 1, 3, 50)], 0, 1);
+    assert!(chain.update_with_block(again).is_err());
+
+    let indexed = fork(common::chain().with_indexing(true), &chain, chain.block_count());
+    assert_eq!(indexed.utxo_set_hash(), chain.utxo_set_hash());
+    assert!(indexed.contains_transaction(&transaction.hash()));
+
+    chain.rollback();
+    assert!(chain.is_unspent(&outpoint));
+}
+
+#[test]
+fn confirmations_grow_with_each_block_mined_after () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+
+    let transaction = pay(coinbase_output(&chain, 0).0, 1, 2, 50);
+    assert_eq!(chain.confirmations(&transaction.hash()), None);
+
+    let block = mine_block(&chain, vec![transaction.clone()], 0, 1);
+    chain.update_with_block(block).unwrap();
+    assert_eq!(chain.confirmations(&transaction.hash()), Some(1));
+
+    for confirmations in 2..5 {
+        mine_blocks(&mut chain, 1, 1);
+        assert_eq!(chain.confirmations(&transaction.hash()), Some(confirmations));
+    }
+
+    chain.rollback();
+    assert_eq!(chain.confirmations(&transaction.hash()), Some(3));
+}
+
+#[test]
+fn walks_back_from_the_tip_to_genesis () {
+    let mut chain = chain();
+    assert_eq!(chain.iter_rev().count(), 0);
+    mine_blocks(&mut chain, 5, 1);
+
+    let indexes = chain.iter_rev().map(|block| block.header.index).collect::<Vec<u32>>();
+    assert_eq!(indexes, vec![4, 3, 2, 1, 0]);
+    assert!(chain.iter_rev().zip(chain.iter_rev().skip(1)).all(|(block, parent)| block.header.prev_block_hash == parent.hash()));
+
+    let last_two = chain.iter_rev().take(2).map(|block| block.hash()).collect::<Vec<Hash>>();
+    assert_eq!(last_two, vec![chain.last_hash(), chain.block(3).unwrap().hash()]);
+}
+
+#[test]
+fn an_address_history_pairs_spends_with_what_they_spent () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+
+    let received = pay(coinbase_output(&chain, 0).0, 1, 2, 50);
+    let block = mine_block(&chain, vec![received.clone()], 0, 1);
+    chain.update_with_block(block).unwrap();
+
+    // 2 pays 30 on to 3 and keeps 20 as change
+    let mut spent = pay(received.outpoints()[0], 2, 3, 30);
+    spent.outputs.push(transaction::Output { to_addr: addr(2), value: 20, script: vec![] });
+    spent.sign(&key(2));
+    let block = mine_block(&chain, vec![spent.clone()], 0, 1);
+    chain.update_with_block(block).unwrap();
+
+    let entry = |kind, block_index, transaction: &Transaction, outpoint, amount| AddressEntry {
+        kind,
+        block_index,
+        tx_hash: transaction.hash(),
+        outpoint,
+        amount,
+    };
+    assert_eq!(chain.address_history(&addr(2)), vec![
+        entry(AddressEntryKind::Received, 1, &received, received.outpoints()[0], 50),
+        entry(AddressEntryKind::Spent, 2, &spent, received.outpoints()[0], 50),
+        entry(AddressEntryKind::Received, 2, &spent, spent.outpoints()[1], 20),
+    ]);
+    assert_eq!(chain.address_history(&addr(3)).len(), 1);
+    assert!(chain.address_history(&addr(4)).is_empty());
+
+    // The spend is still paired once the block paying 2 is pruned
+    chain.prune(1);
+    assert_eq!(chain.address_history(&addr(2)), vec![
+        entry(AddressEntryKind::Spent, 2, &spent, received.outpoints()[0], 50),
+        entry(AddressEntryKind::Received, 2, &spent, spent.outpoints()[1], 20),
+    ]);
+}
+
+#[test]
+fn block_fees_total_what_the_block_s_transactions_paid () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 2, 1);
+    let transactions = vec![
+        pay(coinbase_output(&chain, 0).0, 1, 2, 46),
+        pay(coinbase_output(&chain, 1).0, 1, 3, 43),
+    ];
+    let block = mine_block(&chain, transactions, 11, 4);
+    chain.update_with_block(block).unwrap();
+
+    assert_eq!(chain.block_fees(2), Some(11));
+    assert_eq!(chain.block_fees(1), Some(0));
+    assert_eq!(chain.block_fees(3), None);
+    assert_eq!(chain.get_balance(&addr(4)), chain.block_reward(2) + 11);
+
+    let fees = (1..3).map(|position| chain.transaction_fee(2, position).unwrap()).collect::<Vec<u64>>();
+    assert_eq!(fees.iter().sum::<u64>(), 11);
+    assert!(fees.contains(&4) && fees.contains(&7));
+
+    mine_blocks(&mut chain, 1, 1);
+    chain.prune(1);
+    assert!(chain.block(2).unwrap().transactions.is_empty());
+    assert_eq!(chain.block_fees(2), Some(11));
+}
+
+#[test]
+fn selects_coins_combining_outputs_when_one_isn_t_enough () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 2, 1);
+    let transactions = vec![
+        pay(coinbase_output(&chain, 0).0, 1, 2, 30),
+        pay(coinbase_output(&chain, 1).0, 1, 2, 25),
+    ];
+    let block = mine_block(&chain, transactions, 45, 3);
+    chain.update_with_block(block).unwrap();
+
+    let utxos = chain.utxos_for(&addr(2));
+    assert_eq!(utxos.len(), 2);
+    assert!(utxos.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+    let values = |selected: Vec<(OutPoint, transaction::Output)>| selected.iter().map(|(_, output)| output.value).collect::<Vec<u64>>();
+    assert_eq!(values(chain.select_coins(&addr(2), 20).unwrap()), [30]);
+    assert_eq!(values(chain.select_coins(&addr(2), 50).unwrap()), [30, 25]);
+    assert!(chain.select_coins(&addr(2), 56).is_none());
+    assert!(chain.select_coins(&addr(4), 1).is_none());
+}
+
+#[test]
+fn immature_coinbases_aren_t_selected () {
+    let mut chain = chain().with_coinbase_maturity(2);
+    mine_blocks(&mut chain, 2, 1);
+
+    assert_eq!(chain.utxos_for(&addr(1)).len(), 2);
+    assert_eq!(chain.select_coins(&addr(1), 50).unwrap().len(), 1);
+    assert!(chain.select_coins(&addr(1), 51).is_none());
+}
+
+#[test]
+fn blocks_are_deduplicated_by_hash () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 3, 1);
+    let genesis = chain.block(0).unwrap().clone();
+
+    // Only the header is hashed, so a block stripped of its transactions is
+    // still the same block
+    let mut stripped = genesis.clone();
+    stripped.transactions.clear();
+    assert!(stripped == genesis);
+
+    let mut set = chain.blocks().cloned().collect::<std::collections::HashSet<Block>>();
+    set.insert(genesis.clone());
+    set.insert(stripped);
+    assert_eq!(set.len(), 3);
+    assert!(set.contains(chain.tip().unwrap()));
+
+    let mut renonced = genesis.clone();
+    renonced.header.nonce += 1;
+    assert!(renonced != genesis);
+    assert!(set.insert(renonced));
+}
+
+#[test]
+fn balances_split_confirmed_from_pending () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 2, 1);
+    let mut mempool = Mempool::new();
+
+    // Spends one of 1's coinbases, paying 2 and the change back to 1
+    let mut transaction = pay(coinbase_output(&chain, 0).0, 1, 2, 30);
+    transaction.outputs.push(transaction::Output { to_addr: addr(1), value: 16, script: vec![] });
+    transaction.sign(&key(1));
+    mempool.add_transaction(transaction.clone(), &chain).unwrap();
+
+    let balance = chain.get_balance_detailed(&addr(1), &mempool);
+    assert_eq!(balance, BalanceBreakdown { confirmed: 100, pending_incoming: 16, pending_outgoing: 50 });
+    assert_eq!(balance.projected(), 66);
+    assert_eq!(chain.get_balance(&addr(1)), 100);
+    let balance = chain.get_balance_detailed(&addr(2), &mempool);
+    assert_eq!(balance, BalanceBreakdown { confirmed: 0, pending_incoming: 30, pending_outgoing: 0 });
+
+    // Change spent again in the mempool is no longer coming in
+    let change = OutPoint { txid: transaction.hash(), index: 1 };
+    mempool.add_transaction(pay(change, 1, 3, 12), &chain).unwrap();
+    let balance = chain.get_balance_detailed(&addr(1), &mempool);
+    assert_eq!(balance, BalanceBreakdown { confirmed: 100, pending_incoming: 0, pending_outgoing: 50 });
+    assert_eq!(balance.projected(), 50);
+}
@@ -0,0 +1,287 @@
+mod common;
+
+use blockchainlib::*;
+use common::*;
+
+// Retargets every two blocks towards a minute a block
+fn retargeting () -> Blockchain {
+    chain().with_retargeting(60_000, 2)
+}
+
+// A chain of seven blocks a minute apart, and a shorter branch off its
+// genesis whose fast blocks raised the difficulty, so it has more work
+fn long_and_heavy () -> (Blockchain, Blockchain) {
+    let mut long = retargeting();
+    mine_blocks(&mut long, 1, 1);
+    let mut heavy = fork(retargeting(), &long, 1);
+
+    mine_spaced(&mut long, 6, 60_000, 1);
+    mine_spaced(&mut heavy, 4, 1_000, 2);
+
+    (long, heavy)
+}
+
+#[test]
+fn the_chain_with_more_work_wins_over_the_longer_one () {
+    let (mut long, heavy) = long_and_heavy();
+    assert!(heavy.block_count() < long.block_count());
+    assert!(heavy.total_work() > long.total_work());
+
+    let mut shorter_copy = fork(retargeting(), &heavy, heavy.block_count());
+    assert!(!shorter_copy.try_replace(long.blocks().cloned().collect()).unwrap());
+    assert_eq!(shorter_copy.last_hash(), heavy.last_hash());
+
+    assert!(long.try_replace(heavy.blocks().cloned().collect()).unwrap());
+    assert_eq!(long.last_hash(), heavy.last_hash());
+    assert_eq!(long.total_work(), heavy.total_work());
+    assert_eq!(long.difficulty(), heavy.difficulty());
+}
+
+#[test]
+fn reorgs_deeper_than_the_limit_are_refused () {
+    let mut chain = chain().with_max_reorg_depth(2);
+    mine_blocks(&mut chain, 5, 1);
+    let (tip, utxo_set_hash) = (chain.last_hash(), chain.utxo_set_hash());
+
+    // Forks off genesis, so would roll back four blocks
+    let mut deep = fork(common::chain(), &chain, 1);
+    mine_blocks(&mut deep, 6, 2);
+    assert!(matches!(chain.try_replace(deep.blocks().cloned().collect()), Err(BlockValidationErr::ReorgTooDeep)));
+    assert_eq!(chain.block_count(), 5);
+    assert_eq!(chain.last_hash(), tip);
+    assert_eq!(chain.utxo_set_hash(), utxo_set_hash);
+
+    // Forks off block 2, so rolls back two
+    let mut shallow = fork(common::chain(), &chain, 3);
+    mine_blocks(&mut shallow, 3, 2);
+    assert!(chain.try_replace(shallow.blocks().cloned().collect()).unwrap());
+    assert_eq!(chain.last_hash(), shallow.last_hash());
+}
+
+#[test]
+fn chains_with_another_genesis_are_refused () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 2, 1);
+    let genesis = chain.genesis().unwrap().clone();
+    assert_eq!(genesis.hash(), chain.block(0).unwrap().hash());
+
+    let mut other = common::chain();
+    mine_blocks(&mut other, 4, 2);
+    assert!(matches!(chain.try_replace(other.blocks().cloned().collect()), Err(BlockValidationErr::GenesisMismatch)));
+    assert_eq!(chain.block_count(), 2);
+    assert_eq!(chain.genesis(), Some(&genesis));
+
+    // Even rolled back to nothing, the chain keeps to its genesis
+    chain.rollback();
+    chain.rollback();
+    assert!(chain.genesis().is_none());
+    let other_genesis = other.block(0).unwrap().clone();
+    assert!(matches!(chain.update_with_block(other_genesis), Err(BlockValidationErr::GenesisMismatch)));
+    chain.update_with_block(genesis).unwrap();
+}
+
+#[test]
+fn blocks_of_a_heavier_branch_arriving_one_by_one_make_it_active () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 3, 1);
+    let main_tip = chain.last_hash();
+    let mut branch = fork(common::chain(), &chain, 1);
+    mine_blocks(&mut branch, 3, 2);
+    let side = branch.blocks().skip(1).cloned().collect::<Vec<Block>>();
+
+    // As much work as the active chain isn't enough to switch
+    for block in &side[..2] {
+        chain.update_with_block(block.clone()).unwrap();
+        assert!(chain.block_tree().is_side(&block.hash()));
+        assert_eq!(chain.last_hash(), main_tip);
+    }
+    assert_eq!(chain.block_tree().tips().len(), 2);
+
+    chain.update_with_block(side[2].clone()).unwrap();
+    assert_eq!(chain.last_hash(), branch.last_hash());
+    assert_eq!(chain.block_tree().best_tip(), Some(branch.last_hash()));
+    assert!(chain.block_tree().is_side(&main_tip));
+    assert!(!chain.block_tree().is_side(&side[0].hash()));
+    assert_eq!(chain.get_balance(&addr(2)), 150);
+    assert_eq!(chain.get_balance(&addr(1)), 50);
+    assert_eq!(chain.utxo_set_hash(), branch.utxo_set_hash());
+}
+
+#[test]
+fn side_blocks_are_checked_against_their_own_branch () {
+    let (mut long, heavy) = long_and_heavy();
+    let long_tip = long.last_hash();
+
+    // The heavy branch's later blocks state a difficulty the long chain
+    // never reached at those heights
+    for block in heavy.blocks().skip(1) {
+        long.update_with_block(block.clone()).unwrap();
+    }
+    assert_eq!(long.last_hash(), heavy.last_hash());
+    assert_eq!(long.difficulty(), heavy.difficulty());
+    assert!(long.block_tree().is_side(&long_tip));
+
+    // A side block is still held to its own parent's timestamp
+    let parent = long.block(1).unwrap().header.timestamp;
+    let block = mine_block_at(&fork(retargeting(), &long, 2), vec![], 0, 3, parent);
+    assert!(matches!(long.update_with_block(block), Err(BlockValidationErr::TimestampNotAfterParent)));
+}
+
+#[test]
+fn a_pruned_chain_still_switches_branches () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 4, 1);
+    let mut branch = fork(common::chain(), &chain, 3);
+    mine_blocks(&mut branch, 2, 2);
+
+    chain.prune(2);
+    for block in branch.blocks().skip(3) {
+        chain.update_with_block(block.clone()).unwrap();
+    }
+    assert_eq!(chain.last_hash(), branch.last_hash());
+    assert_eq!(chain.get_balance(&addr(2)), 100);
+    assert_eq!(chain.utxo_set_hash(), branch.utxo_set_hash());
+}
+
+#[test]
+fn reorganizes_to_a_chosen_branch_and_back () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+    let mut branch = fork(common::chain(), &chain, 1);
+    let block = mine_block(&chain, vec![pay(coinbase_output(&chain, 0).0, 1, 3, 50)], 0, 1);
+    chain.update_with_block(block).unwrap();
+    mine_blocks(&mut chain, 1, 1);
+    mine_blocks(&mut branch, 3, 2);
+    let main = fork(common::chain(), &chain, 3);
+    let hashes = |blocks: &[Block]| blocks.iter().map(|block| block.hash()).collect::<Vec<Hash>>();
+
+    for block in branch.blocks().skip(1) {
+        chain.update_with_block(block.clone()).unwrap();
+    }
+    assert_eq!(chain.last_hash(), branch.last_hash());
+
+    // Back to the lighter branch, by hand
+    let result = chain.reorganize_to(&main.last_hash()).unwrap();
+    assert_eq!(hashes(&result.disconnected), hashes(&branch.blocks().skip(1).cloned().collect::<Vec<Block>>()));
+    assert_eq!(hashes(&result.connected), hashes(&main.blocks().skip(1).cloned().collect::<Vec<Block>>()));
+    assert_eq!(chain.last_hash(), main.last_hash());
+    assert_eq!(chain.utxo_set_hash(), main.utxo_set_hash());
+    assert_eq!(chain.get_balance(&addr(3)), 50);
+
+    let result = chain.reorganize_to(&branch.last_hash()).unwrap();
+    assert_eq!(result.connected.len(), 3);
+    assert_eq!(chain.utxo_set_hash(), branch.utxo_set_hash());
+    assert_eq!(chain.get_balance(&addr(3)), 0);
+
+    // Only branch tips will do, and the active tip changes nothing
+    assert!(chain.reorganize_to(&chain.last_hash()).unwrap().connected.is_empty());
+    let inner = branch.block(2).unwrap().hash();
+    assert!(matches!(chain.reorganize_to(&inner), Err(BlockValidationErr::UnknownBranchTip)));
+    assert!(matches!(chain.reorganize_to(&Hash::digest(b"unknown")), Err(BlockValidationErr::UnknownBranchTip)));
+    assert_eq!(chain.last_hash(), branch.last_hash());
+}
+
+#[test]
+fn only_fully_validated_branches_can_be_reorganized_to () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 3, 1);
+    let tip = chain.last_hash();
+    let mut branch = fork(common::chain(), &chain, 1);
+    mine_blocks(&mut branch, 2, 2);
+
+    // No more work than the active chain, so only their headers were checked
+    for block in branch.blocks().skip(1) {
+        chain.update_with_block(block.clone()).unwrap();
+    }
+    assert!(chain.block_tree().tips().contains(&branch.last_hash()));
+    assert!(!chain.block_tree().is_validated(&branch.last_hash()));
+    assert!(chain.block_tree().is_validated(&tip));
+
+    assert!(matches!(chain.reorganize_to(&branch.last_hash()), Err(BlockValidationErr::UnvalidatedBranchTip)));
+    assert_eq!(chain.last_hash(), tip);
+    assert_eq!(chain.get_balance(&addr(2)), 0);
+}
+
+#[test]
+fn a_failed_reorg_leaves_the_chain_untouched () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 3, 1);
+    let before = (chain.last_hash(), chain.utxo_set_hash(), chain.total_work(), chain.difficulty());
+    let snapshot = chain.utxo_snapshot();
+
+    // More work than the chain, but its last block spends nothing real
+    let mut candidate = fork(common::chain(), &chain, 1);
+    mine_blocks(&mut candidate, 2, 2);
+    let unknown = OutPoint { txid: Hash::digest(b"unknown"), index: 0 };
+    let mut blocks = candidate.blocks().cloned().collect::<Vec<Block>>();
+    blocks.push(mine_block(&candidate, vec![pay(unknown, 2, 2, 50)], 0, 2));
+
+    assert!(matches!(chain.try_replace(blocks), Err(BlockValidationErr::SpendOfUnknownOutput { .. })));
+    assert_eq!((chain.last_hash(), chain.utxo_set_hash(), chain.total_work(), chain.difficulty()), before);
+    assert!(chain.utxo_snapshot() == snapshot);
+    assert_eq!(chain.get_balance(&addr(2)), 0);
+    assert_eq!(chain.block_count(), 3);
+
+    // Valid with as much work, so simply not preferred
+    assert!(!chain.try_replace(candidate.blocks().cloned().collect()).unwrap());
+    assert_eq!(chain.last_hash(), before.0);
+
+    // Fed block by block, a branch switches at its third block and fails at
+    // its second, which overpays its coinbase
+    let mut branch = fork(common::chain(), &chain, 1);
+    mine_blocks(&mut branch, 1, 2);
+    let side = branch.blocks().nth(1).unwrap().clone();
+    let invalid = mine_block(&branch, vec![], 1, 2);
+    let child = |parent: &Block| {
+        let index = parent.header.index + 1;
+        let mut block = Block::new(index, tick(), parent.hash(), vec![coinbase(2, 50, index)], parent.header.difficulty);
+        block.mine_with(parent.header.difficulty, chain.hasher()).unwrap();
+        block
+    };
+    let on_invalid = child(&invalid);
+    let on_that = child(&on_invalid);
+
+    chain.update_with_block(side.clone()).unwrap();
+    chain.update_with_block(invalid.clone()).unwrap();
+    let err = format!("{:?}", chain.update_with_block(on_invalid.clone()).unwrap_err());
+    assert_eq!((chain.last_hash(), chain.utxo_set_hash(), chain.total_work(), chain.difficulty()), before);
+
+    // Both are known to be invalid now, and so is anything built on them,
+    // without switching branches again
+    assert_eq!(format!("{:?}", chain.block_tree().rejection(&invalid.hash()).unwrap()), err);
+    assert_eq!(format!("{:?}", chain.update_with_block(invalid.clone()).unwrap_err()), err);
+    assert_eq!(format!("{:?}", chain.update_with_block(on_invalid.clone()).unwrap_err()), err);
+    assert_eq!(format!("{:?}", chain.update_with_block(on_that.clone()).unwrap_err()), err);
+    assert!(!chain.block_tree().contains(&on_that.hash()));
+    assert!(chain.block_tree().rejection(&side.hash()).is_none());
+
+    let tips = chain.block_tree().tips();
+    assert!(tips.contains(&before.0) && tips.contains(&side.hash()));
+    assert!(!tips.contains(&invalid.hash()) && !tips.contains(&on_invalid.hash()));
+    let best = chain.block_tree().best_tip().unwrap();
+    assert_eq!(chain.block_tree().cumulative_work(&best), Some(chain.total_work()));
+    assert_eq!(chain.block_count(), 3);
+}
+
+#[test]
+fn rolling_back_forgets_the_side_blocks_built_on_the_removed_block () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 3, 1);
+    let mut branch = fork(common::chain(), &chain, 2);
+    mine_blocks(&mut branch, 2, 2);
+    let side = branch.blocks().skip(2).cloned().collect::<Vec<Block>>();
+
+    chain.update_with_block(side[0].clone()).unwrap();
+    assert!(chain.block_tree().is_side(&side[0].hash()));
+
+    chain.rollback().unwrap();
+    chain.rollback().unwrap();
+    assert!(!chain.block_tree().contains(&side[0].hash()));
+    assert_eq!(chain.block_tree().tips(), vec![chain.last_hash()]);
+
+    // Its child now has no known parent, so it's refused rather than
+    // stranded on a branch that leads nowhere
+    assert!(chain.update_with_block(side[1].clone()).is_err());
+    assert!(!chain.block_tree().contains(&side[1].hash()));
+    assert_eq!(chain.block_count(), 1);
+}
@@ -0,0 +1,84 @@
+#![cfg(feature = "rpc")]
+
+mod common;
+
+use blockchainlib::*;
+use common::*;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+
+// A chain of `blocks` blocks mined by 1
+fn seeded (blocks: usize) -> Blockchain {
+    let mut chain = chain();
+    mine_blocks(&mut chain, blocks, 1);
+    chain
+}
+
+// A server over `chain`, and its mempool
+fn server (chain: Blockchain) -> (RpcServer, Arc<Mutex<Mempool>>) {
+    let mempool = Arc::new(Mutex::new(Mempool::new()));
+    (RpcServer::new(SharedBlockchain::new(chain), mempool.clone()), mempool)
+}
+
+// The raw JSON response to calling `method` with `params`
+fn call (server: &RpcServer, method: &str, params: Value) -> String {
+    server.handle(&json!({ "jsonrpc": "2.0", "method": method, "params": params, "id": 7 }).to_string())
+}
+
+fn parse (response: &str) -> Value {
+    serde_json::from_str(response).unwrap()
+}
+
+#[test]
+fn counts_the_blocks_of_a_seeded_chain () {
+    let (server, _) = server(seeded(3));
+    assert_eq!(call(&server, "getblockcount", json!([])), r#"{"jsonrpc":"2.0","result":3,"id":7}"#);
+}
+
+#[test]
+fn looks_blocks_up_by_hash () {
+    let chain = seeded(2);
+    let hash = chain.last_hash();
+    let (server, _) = server(chain);
+
+    let block = parse(&call(&server, "getblock", json!([hash.to_string()])));
+    assert_eq!(block["result"]["index"], 1);
+    assert_eq!(block["result"]["hash"], hash.to_string());
+    assert_eq!(block["id"], 7);
+
+    let missing = parse(&call(&server, "getblock", json!([Hash::digest(b"unknown").to_string()])));
+    assert_eq!(missing["error"]["code"], -1);
+    let malformed = parse(&call(&server, "getblock", json!(["not a hash"])));
+    assert_eq!(malformed["error"]["code"], -32602);
+}
+
+#[test]
+fn reports_balances () {
+    let (server, _) = server(seeded(3));
+    assert!(call(&server, "getbalance", json!([addr(1).to_string()])).contains(r#""result":150"#));
+    assert!(call(&server, "getbalance", json!([addr(2).to_string()])).contains(r#""result":0"#));
+    assert_eq!(parse(&call(&server, "getbalance", json!([])))["error"]["code"], -32602);
+}
+
+#[test]
+fn sends_raw_transactions_to_the_mempool () {
+    let chain = seeded(2);
+    let transaction = pay(coinbase_output(&chain, 1).0, 1, 2, 49);
+    let hash = transaction.hash();
+    let (server, mempool) = server(chain);
+    let raw = |transaction: &Transaction| hex::encode(bincode::serialize(transaction).unwrap());
+
+    let sent = parse(&call(&server, "sendrawtransaction", json!([raw(&transaction)])));
+    assert_eq!(sent["result"], hash.to_string());
+    assert!(mempool.lock().unwrap().contains(&hash));
+
+    // Well formed, but spending an output that doesn't exist
+    let unknown = OutPoint { txid: Hash::digest(b"unknown"), index: 0 };
+    let rejected = parse(&call(&server, "sendrawtransaction", json!([raw(&pay(unknown, 1, 2, 10))])));
+    assert_eq!(rejected["error"]["code"], -1);
+    assert!(rejected.get("result").is_none());
+
+    let malformed = parse(&call(&server, "sendrawtransaction", json!(["zz"])));
+    assert_eq!(malformed["error"]["code"], -32602);
+    assert_eq!(mempool.lock().unwrap().len(), 1);
+}
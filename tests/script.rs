@@ -0,0 +1,61 @@
+mod common;
+
+use blockchainlib::*;
+use blockchainlib::script::{self, ScriptError};
+use blockchainlib::transaction::Output;
+use common::*;
+
+// A chain whose block 1 locks 50 with `script`, and the output it locks
+fn locked (script: Vec<u8>) -> (Blockchain, OutPoint) {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+
+    let mut transaction = pay(coinbase_output(&chain, 0).0, 1, 2, 50);
+    transaction.outputs[0] = Output { to_addr: addr(2), value: 50, script };
+    transaction.sign(&key(1));
+    let outpoint = transaction.outpoints()[0];
+
+    let block = mine_block(&chain, vec![transaction], 0, 1);
+    chain.update_with_block(block).unwrap();
+    (chain, outpoint)
+}
+
+#[test]
+fn the_key_a_script_locks_to_can_spend () {
+    let (mut chain, outpoint) = locked(script::p2pkh(&key(3).verifying_key()));
+
+    let block = mine_block(&chain, vec![pay(outpoint, 3, 4, 50)], 0, 1);
+    chain.update_with_block(block).unwrap();
+    assert_eq!(chain.get_balance(&addr(4)), 50);
+}
+
+#[test]
+fn other_keys_fail_the_script () {
+    let (mut chain, outpoint) = locked(script::p2pkh(&key(3).verifying_key()));
+
+    // Not even the informational to_addr's key may spend it
+    for from in [2, 4] {
+        let block = mine_block(&chain, vec![pay(outpoint, from, 4, 50)], 0, 1);
+        assert!(matches!(
+            chain.update_with_block(block),
+            Err(BlockValidationErr::ScriptFailure { tx_index: 1, outpoint: failed }) if failed == outpoint
+        ));
+    }
+}
+
+#[test]
+fn malformed_scripts_fail () {
+    let truncated = vec![script::OP_1, 5, 1, 2];
+    let hash = Hash::digest(b"signed");
+
+    assert_eq!(script::execute(&[], &truncated, &hash), Err(ScriptError::Truncated));
+    assert_eq!(script::execute(&[], &[0xff], &hash), Err(ScriptError::UnknownOpcode(0xff)));
+    assert_eq!(script::execute(&[script::OP_DUP], &[script::OP_1], &hash), Err(ScriptError::NonPushWitness));
+    assert_eq!(script::execute(&[], &[script::OP_EQUAL], &hash), Err(ScriptError::StackUnderflow));
+    assert_eq!(script::execute(&[], &vec![script::OP_1; script::MAX_SCRIPT_SIZE + 1], &hash), Err(ScriptError::TooLarge));
+    assert_eq!(script::execute(&[], &[script::OP_1], &hash), Ok(()));
+
+    let (mut chain, outpoint) = locked(truncated);
+    let block = mine_block(&chain, vec![pay(outpoint, 2, 4, 50)], 0, 1);
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::ScriptFailure { tx_index: 1, .. })));
+}
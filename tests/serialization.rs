@@ -0,0 +1,136 @@
+mod common;
+
+use blockchainlib::*;
+use common::*;
+
+fn three_block_chain () -> Blockchain {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 3, 1);
+    chain
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn round_trips_a_chain_through_json () {
+    let chain = three_block_chain();
+
+    let json = serde_json::to_string(&chain).unwrap();
+    let loaded: Blockchain = serde_json::from_str(&json).unwrap();
+
+    assert!(json.contains(&chain.last_hash().to_string()));
+    assert_eq!(loaded.blocks().collect::<Vec<_>>(), chain.blocks().collect::<Vec<_>>());
+    assert_eq!(loaded.utxo_commitment(), chain.utxo_commitment());
+    assert_eq!(loaded.difficulty(), chain.difficulty());
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn round_trips_a_block_through_json () {
+    let chain = three_block_chain();
+    let block = chain.tip().unwrap();
+
+    let loaded: Block = serde_json::from_str(&serde_json::to_string(block).unwrap()).unwrap();
+
+    assert_eq!(&loaded, block);
+    assert_eq!(loaded.header, block.header);
+}
+
+#[test]
+fn replays_the_blocks_on_deserialize () {
+    let chain = three_block_chain();
+
+    let loaded: Blockchain = bincode::deserialize(&bincode::serialize(&chain).unwrap()).unwrap();
+
+    assert_eq!(loaded.block_count(), 3);
+    assert_eq!(loaded.utxo_count(), chain.utxo_count());
+    assert_eq!(loaded.get_balance(&addr(1)), chain.get_balance(&addr(1)));
+}
+
+fn temp_path (name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("blockchain-test-{}-{}", std::process::id(), name))
+}
+
+#[test]
+fn saves_and_loads_a_chain () {
+    let chain = three_block_chain();
+    let path = temp_path("save");
+
+    chain.save_to_file(&path).unwrap();
+    let loaded = Blockchain::load_from_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(loaded.last_hash(), chain.last_hash());
+    assert_eq!(loaded.difficulty(), chain.difficulty());
+    assert_eq!(loaded.utxo_commitment(), chain.utxo_commitment());
+}
+
+#[test]
+fn reports_why_a_file_wont_load () {
+    let path = temp_path("corrupt");
+
+    assert!(matches!(Blockchain::load_from_file(&path), Err(LoadError::Io(_))));
+
+    std::fs::write(&path, [0xff; 8]).unwrap();
+    let result = Blockchain::load_from_file(&path);
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(matches!(result, Err(LoadError::Deserialize(_))));
+}
+
+#[test]
+fn exports_a_csv_row_per_transaction () {
+    let mut chain = three_block_chain();
+    let transaction = pay(coinbase_output(&chain, 0).0, 1, 2, 47);
+    let block = mine_block(&chain, vec![transaction.clone()], 3, 1);
+    chain.update_with_block(block).unwrap();
+
+    let mut csv = vec![];
+    chain.export_csv(&mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    let lines = csv.lines().collect::<Vec<&str>>();
+
+    assert_eq!(lines[0], "block_index,tx_hash,input_value,output_value,fee,is_coinbase");
+    assert_eq!(lines.len(), 1 + 5);
+    assert_eq!(lines[1], format!("0,{},0,50,0,true", chain.block(0).unwrap().transactions[0].hash()));
+    assert_eq!(lines[4], format!("3,{},0,53,0,true", chain.block(3).unwrap().transactions[0].hash()));
+    assert_eq!(lines[5], format!("3,{},50,47,3,false", transaction.hash()));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn exports_the_chain_as_json () {
+    let chain = three_block_chain();
+
+    let mut json = vec![];
+    chain.export_json(&mut json).unwrap();
+    assert_eq!(json, serde_json::to_vec_pretty(&chain).unwrap());
+
+    let exported: Blockchain = serde_json::from_slice(&json).unwrap();
+    assert_eq!(exported.last_hash(), chain.last_hash());
+}
+
+#[test]
+fn a_tampered_stored_hash_is_ignored_and_a_tampered_body_is_caught () {
+    let chain = three_block_chain();
+    let block = chain.tip().unwrap().clone();
+    block.validate_self().unwrap();
+
+    // The stored hash is a hex string, written out before the fields it
+    // is derived from
+    let (stored, forged) = (block.hash().to_string(), Hash::digest(b"forged").to_string());
+    let bytes = bincode::serialize(&block).unwrap();
+    let at = bytes.windows(stored.len()).position(|window| window == stored.as_bytes()).unwrap();
+    let mut tampered = bytes.clone();
+    tampered[at..at + forged.len()].copy_from_slice(forged.as_bytes());
+
+    let decoded: Block = bincode::deserialize(&tampered).unwrap();
+    assert_eq!(decoded.hash(), block.hash());
+    decoded.validate_self().unwrap();
+    assert_eq!(bincode::serialize(&decoded).unwrap(), bytes);
+
+    let mut body = decoded;
+    body.transactions[0].outputs[0].value += 1;
+    assert!(matches!(body.validate_self(), Err(BlockValidationErr::InvalidMerkleRoot)));
+    body.transactions.clear();
+    assert!(matches!(body.validate_self(), Err(BlockValidationErr::MissingCoinbase)));
+}
@@ -0,0 +1,32 @@
+mod common;
+
+use blockchainlib::*;
+use common::*;
+
+#[test]
+fn snapshots_keep_the_state_they_were_taken_at () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 2, 1);
+    let tip = chain.last_hash();
+    let outpoint = coinbase_output(&chain, 0).0;
+
+    let snapshot = chain.snapshot();
+    let block = mine_block(&chain, vec![pay(outpoint, 1, 2, 50)], 0, 3);
+    let hash = block.hash();
+    chain.update_with_block(block).unwrap();
+
+    assert_eq!(chain.height(), Some(2));
+    assert_eq!(snapshot.height(), Some(1));
+    assert_eq!(snapshot.block_count(), 2);
+    assert_eq!(snapshot.tip().unwrap().hash(), tip);
+    assert!(snapshot.get_block_by_hash(&tip).is_some());
+    assert!(snapshot.get_block_by_hash(&hash).is_none());
+    assert!(snapshot.is_unspent(&outpoint));
+    assert_eq!(snapshot.get_balance(&addr(1)), 100);
+    assert_eq!(snapshot.get_balance(&addr(2)), 0);
+
+    let later = chain.snapshot();
+    assert_eq!(later.height(), Some(2));
+    assert_eq!(later.get_balance(&addr(2)), 50);
+    assert!(!later.is_unspent(&outpoint));
+}
@@ -0,0 +1,156 @@
+mod common;
+
+use blockchainlib::*;
+use common::*;
+
+#[test]
+fn a_pruned_chain_still_validates_new_blocks () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+    let transaction = pay(coinbase_output(&chain, 0).0, 1, 2, 50);
+    let block = mine_block(&chain, vec![transaction.clone()], 0, 1);
+    chain.update_with_block(block).unwrap();
+    mine_blocks(&mut chain, 3, 1);
+    let commitment = chain.utxo_commitment();
+
+    chain.prune(2);
+
+    assert!(chain.is_pruned());
+    assert!(chain.block(1).unwrap().transactions.is_empty());
+    assert!(!chain.block(3).unwrap().transactions.is_empty());
+    assert_eq!(chain.block(0).unwrap().header.index, 0);
+    assert_eq!(chain.utxo_commitment(), commitment);
+    assert!(chain.find_transaction(&transaction.hash()).is_none());
+
+    let block = mine_block(&chain, vec![pay(coinbase_output(&chain, 4).0, 1, 3, 50)], 0, 1);
+    chain.update_with_block(block).unwrap();
+    assert_eq!(chain.get_balance(&addr(3)), 50);
+}
+
+#[test]
+fn chains_with_the_same_utxo_set_commit_to_the_same_root () {
+    let mut chain = chain();
+    let mut other = common::chain();
+    mine_blocks(&mut chain, 3, 1);
+    mine_blocks(&mut other, 3, 1);
+
+    assert_ne!(chain.last_hash(), other.last_hash());
+    assert_eq!(chain.utxo_commitment(), other.utxo_commitment());
+
+    let snapshot = other.utxo_snapshot();
+    assert!(chain.verify_utxo_snapshot(&snapshot, 2));
+    assert!(!chain.verify_utxo_snapshot(&snapshot, 1));
+
+    let mut tampered = snapshot.clone();
+    tampered[0].1.value += 1;
+    assert!(!chain.verify_utxo_snapshot(&tampered, 2));
+
+    let mut tampered = snapshot;
+    tampered[0].1.to_addr = addr(2);
+    assert!(!chain.verify_utxo_snapshot(&tampered, 2));
+    assert!(!chain.verify_utxo_snapshot(&[], 2));
+}
+
+#[test]
+fn rollback_restores_the_previous_utxo_set () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+    let before = chain.utxo_snapshot();
+    let (work, difficulty) = (chain.total_work(), chain.difficulty());
+
+    let block = mine_block(&chain, vec![pay(coinbase_output(&chain, 0).0, 1, 2, 50)], 0, 3);
+    chain.update_with_block(block.clone()).unwrap();
+
+    assert_eq!(chain.rollback(), Some(block));
+    assert!(chain.utxo_snapshot() == before);
+    assert_eq!(chain.utxo_commitment(), chain.tip().unwrap().header.utxo_root);
+    assert_eq!((chain.total_work(), chain.difficulty()), (work, difficulty));
+    assert_eq!(chain.get_balance(&addr(2)), 0);
+    assert_eq!(chain.get_balance(&addr(1)), 50);
+
+    chain.rollback();
+    assert_eq!(chain.rollback(), None);
+}
+
+#[test]
+fn the_same_history_gives_the_same_utxo_set_hash () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 4, 1);
+    let transactions = (0..4)
+        .map(|height| pay(coinbase_output(&chain, height).0, 1, 2 + height as u64, 50))
+        .collect::<Vec<Transaction>>();
+    let block = mine_block(&chain, transactions, 0, 1);
+    chain.update_with_block(block).unwrap();
+
+    // Replayed into a fresh chain, whose maps hash, and so iterate, differently
+    let replayed = fork(common::chain(), &chain, chain.block_count());
+
+    assert_eq!(chain.utxo_count(), 5);
+    assert_eq!(replayed.utxo_count(), 5);
+    assert!(chain.utxo_snapshot() == replayed.utxo_snapshot());
+    assert_eq!(chain.utxo_set_hash(), replayed.utxo_set_hash());
+
+    let snapshot = chain.utxo_snapshot();
+    assert!(snapshot.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+    // Any difference in history shows
+    let mut other = fork(common::chain(), &chain, 4);
+    mine_blocks(&mut other, 1, 1);
+    assert_ne!(other.utxo_set_hash(), chain.utxo_set_hash());
+}
+
+#[test]
+fn the_supply_audit_adds_up_rewards_but_not_fees () {
+    let mut chain = chain().with_block_reward(50, 2);
+    mine_blocks(&mut chain, 3, 1);
+
+    let transaction = pay(coinbase_output(&chain, 0).0, 1, 2, 47);
+    let block = mine_block(&chain, vec![transaction], 3, 1);
+    chain.update_with_block(block).unwrap();
+
+    // 50 + 50 + 25 + 25; the fee of 3 only moved coins
+    assert_eq!(chain.audit_supply().unwrap(), 150);
+    assert_eq!(chain.total_minted(), 150);
+
+    chain.prune(2);
+    assert!(matches!(chain.audit_supply(), Err(SupplyError::Pruned)));
+}
+
+#[test]
+fn accept_block_returns_the_block_s_utxo_delta () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+    let (spent, spent_output) = coinbase_output(&chain, 0);
+
+    let transaction = pay(spent, 1, 2, 46);
+    let block = mine_block(&chain, vec![transaction.clone()], 4, 3);
+    let coinbase = block.transactions[0].clone();
+    let update = chain.accept_block(block.clone()).unwrap();
+
+    let mut created = vec![
+        (transaction.outpoints()[0], transaction.outputs[0].clone()),
+        (coinbase.outpoints()[0], coinbase.outputs[0].clone()),
+    ];
+    created.sort_unstable_by_key(|(outpoint, _)| *outpoint);
+    assert!(update.created == created);
+    assert_eq!(update.spent, [spent]);
+    assert_eq!(update.height, 1);
+
+    let again = chain.accept_block(block).unwrap();
+    assert!(again.created.is_empty() && again.spent.is_empty());
+
+    // A branch overtaking the block undoes it, so the delta runs the other way
+    let mut branch = fork(common::chain(), &chain, 1);
+    mine_blocks(&mut branch, 2, 4);
+    let side = chain.accept_block(branch.block(1).unwrap().clone()).unwrap();
+    assert!(side.created.is_empty() && side.spent.is_empty());
+
+    let update = chain.accept_block(branch.block(2).unwrap().clone()).unwrap();
+    let mut spent_by_reorg = created.iter().map(|(outpoint, _)| *outpoint).collect::<Vec<OutPoint>>();
+    spent_by_reorg.sort_unstable();
+    assert_eq!(update.spent, spent_by_reorg);
+    assert_eq!(update.created.len(), 3);
+    assert!(update.created.contains(&(spent, spent_output)));
+    assert_eq!(update.height, 2);
+    assert!(chain.utxo_snapshot() == branch.utxo_snapshot());
+}
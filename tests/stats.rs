@@ -0,0 +1,70 @@
+mod common;
+
+use blockchainlib::*;
+use common::*;
+
+#[test]
+fn block_time_stats_over_known_timestamps () {
+    let mut chain = chain();
+    assert_eq!(chain.block_time_stats(), None);
+
+    let start = tick();
+    for offset in [0, 1_000, 4_000, 6_000, 12_000] {
+        let block = mine_block_at(&chain, vec![], 0, 1, start + offset);
+        chain.update_with_block(block).unwrap();
+
+        if offset == 0 {
+            assert_eq!(chain.block_time_stats(), None);
+        }
+    }
+
+    assert_eq!(chain.block_time_stats(), Some(BlockTimeStats {
+        min: 1_000,
+        max: 6_000,
+        mean: 3_000,
+        median: 2_500,
+    }));
+}
+
+#[test]
+fn fee_estimates_take_the_median_of_recent_fees () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 5, 1);
+    assert_eq!(chain.estimate_fee(5), 1);
+
+    // Each coinbase is worth 50, so paying 50 - fee leaves that fee
+    let spend = |chain: &Blockchain, height, fee: u64| pay(coinbase_output(chain, height).0, 1, 2, 50 - fee);
+
+    let transactions = vec![spend(&chain, 0, 1), spend(&chain, 1, 3), spend(&chain, 2, 5)];
+    let block = mine_block(&chain, transactions, 9, 1);
+    chain.update_with_block(block).unwrap();
+
+    let transactions = vec![spend(&chain, 3, 10), spend(&chain, 4, 20)];
+    let block = mine_block(&chain, transactions, 30, 1);
+    chain.update_with_block(block).unwrap();
+
+    assert_eq!(chain.estimate_fee(1), 20);
+    assert_eq!(chain.estimate_fee(2), 5);
+    assert_eq!(chain.estimate_fee(0), 1);
+}
+
+#[test]
+fn the_summary_reports_the_chain_s_vitals () {
+    let mut chain = chain();
+    let empty = chain.summary();
+    assert_eq!((empty.height, empty.tip_hash, empty.utxo_count, empty.total_supply), (None, Hash::zero(), 0, 0));
+
+    mine_blocks(&mut chain, 2, 1);
+    let mut mempool = Mempool::new();
+    mempool.add_transaction(pay(coinbase_output(&chain, 0).0, 1, 2, 50), &chain).unwrap();
+    let summary = chain.summary().with_mempool(&mempool);
+
+    assert_eq!(summary.height, Some(1));
+    assert_eq!(summary.tip_hash, chain.last_hash());
+    assert_eq!(summary.difficulty, u128::MAX);
+    assert_eq!(summary.total_work, 2 * work(u128::MAX));
+    assert_eq!(summary.utxo_count, 2);
+    assert_eq!(summary.total_supply, 100);
+    assert_eq!(summary.mempool_size, Some(1));
+    assert_eq!(chain.summary().mempool_size, None);
+}
@@ -0,0 +1,182 @@
+mod common;
+
+use blockchainlib::*;
+use common::*;
+
+fn hashes (chain: &Blockchain, indexes: &[u32]) -> Vec<Hash> {
+    indexes.iter().map(|&index| chain.block(index).unwrap().hash()).collect()
+}
+
+#[test]
+fn a_short_chain_s_locator_holds_every_block () {
+    let mut chain = chain();
+    assert!(chain.block_locator().is_empty());
+
+    mine_blocks(&mut chain, 3, 1);
+    assert_eq!(chain.block_locator(), hashes(&chain, &[2, 1, 0]));
+}
+
+#[test]
+fn a_long_chain_s_locator_steps_back_exponentially () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 20, 1);
+
+    assert_eq!(chain.block_locator(), hashes(&chain, &[19, 18, 17, 15, 11, 3, 0]));
+}
+
+#[test]
+fn finds_the_fork_point_from_a_locator () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 20, 1);
+
+    // Shares blocks 0 to 11, then has three of its own
+    let mut other = fork(common::chain(), &chain, 12);
+    mine_blocks(&mut other, 3, 2);
+
+    // Its locator is 14, 13, 12, 10, 6, 0, so 10 is the highest shared
+    assert_eq!(chain.find_fork_point(&other.block_locator()), Some(10));
+    assert_eq!(chain.find_fork_point(&chain.block_locator()), Some(19));
+    assert_eq!(chain.find_fork_point(&[Hash::zero()]), None);
+}
+
+#[test]
+fn headers_sync_ahead_of_their_bodies () {
+    let mut source = chain();
+    mine_blocks(&mut source, 2, 1);
+    let transaction = pay(coinbase_output(&source, 0).0, 1, 2, 50);
+    let block = mine_block(&source, vec![transaction], 0, 1);
+    source.update_with_block(block).unwrap();
+
+    let mut chain = fork(common::chain(), &source, 1);
+    let utxo_set_hash = chain.utxo_set_hash();
+
+    for block in source.blocks().skip(1) {
+        chain.accept_header(block.header).unwrap();
+    }
+    assert!(matches!(chain.accept_header(source.block(1).unwrap().header), Err(BlockValidationErr::MismatchedIndex)));
+    assert_eq!(chain.header_count(), 3);
+    assert_eq!(chain.block_count(), 1);
+    assert_eq!(chain.utxo_set_hash(), utxo_set_hash);
+
+    // Transactions from another block leave the header waiting
+    let wrong = source.block(2).unwrap().transactions.clone();
+    assert!(matches!(chain.accept_body(wrong), Err(BlockValidationErr::InvalidMerkleRoot)));
+    assert_eq!(chain.header_count(), 3);
+
+    for block in source.blocks().skip(1) {
+        chain.accept_body(block.transactions.clone()).unwrap();
+    }
+    assert_eq!(chain.block_count(), 3);
+    assert_eq!(chain.last_hash(), source.last_hash());
+    assert_eq!(chain.utxo_set_hash(), source.utxo_set_hash());
+    assert!(chain.accept_body(vec![]).is_err());
+}
+
+#[test]
+fn an_invalid_body_drops_the_headers_after_it () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+
+    // Spends the coinbase with the wrong key, which only the body shows
+    let theft = pay(coinbase_output(&chain, 0).0, 3, 3, 50);
+    let block = mine_block(&chain, vec![theft], 0, 1);
+    chain.accept_header(block.header).unwrap();
+
+    // Headers still have to link up
+    let mut unlinked = block.header;
+    unlinked.index = 2;
+    unlinked.timestamp = tick();
+    assert!(matches!(chain.accept_header(unlinked), Err(BlockValidationErr::MismatchedPreviousHash)));
+
+    assert!(chain.accept_body(block.transactions).is_err());
+    assert_eq!(chain.header_count(), 1);
+    assert_eq!(chain.block_count(), 1);
+}
+
+#[test]
+fn imports_a_run_of_blocks_reporting_progress () {
+    let mut source = chain();
+    mine_blocks(&mut source, 250, 1);
+
+    let mut chain = common::chain();
+    let mut reports = vec![];
+    let imported = chain.import_blocks(source.blocks().cloned(), |done, total| reports.push((done, total)));
+
+    assert_eq!(imported.unwrap(), 250);
+    assert_eq!(reports, vec![(100, 250), (200, 250), (250, 250)]);
+    assert_eq!(chain.last_hash(), source.last_hash());
+}
+
+#[test]
+fn an_import_stops_at_a_bad_block_keeping_those_before_it () {
+    let mut source = chain();
+    mine_blocks(&mut source, 5, 1);
+    let mut blocks = source.blocks().cloned().collect::<Vec<Block>>();
+    blocks[3].header.prev_block_hash = Hash::zero();
+
+    let mut chain = common::chain();
+    let mut reports = vec![];
+    let result = chain.import_blocks(blocks, |done, total| reports.push((done, total)));
+
+    assert!(matches!(result, Err((3, BlockValidationErr::MismatchedPreviousHash))));
+    assert!(reports.is_empty());
+    assert_eq!(chain.block_count(), 3);
+    assert_eq!(chain.last_hash(), source.block(2).unwrap().hash());
+}
+
+#[test]
+fn exported_headers_verify_on_their_own () {
+    let difficulty = u128::MAX / 16;
+    let mut chain = Blockchain::new(difficulty).with_clock(tick);
+    mine_blocks(&mut chain, 5, 1);
+    let genesis = chain.block(0).unwrap().hash();
+
+    let headers = chain.export_headers();
+    assert_eq!(headers.len(), 5);
+    assert!(headers.iter().zip(chain.blocks()).all(|(header, block)| header.hash() == block.hash()));
+    assert_eq!(verify_header_chain(&headers, &genesis), Ok(()));
+
+    assert_eq!(verify_header_chain(&headers, &Hash::zero()), Err(0));
+    assert_eq!(verify_header_chain(&[], &genesis), Err(0));
+
+    let mut unlinked = headers.clone();
+    unlinked[2].prev_block_hash = Hash::zero();
+    assert_eq!(verify_header_chain(&unlinked, &genesis), Err(2));
+
+    let mut unmined = headers.clone();
+    while check_difficulty(&unmined[3].hash(), difficulty) {
+        unmined[3].nonce += 1;
+    }
+    assert_eq!(verify_header_chain(&unmined, &genesis), Err(3));
+
+    let mut skipped = headers;
+    skipped.remove(1);
+    assert_eq!(verify_header_chain(&skipped, &genesis), Err(1));
+}
+
+#[test]
+fn a_chain_loaded_from_a_checkpoint_extends_like_the_original () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 3, 1);
+    let header = chain.tip().unwrap().header;
+    let snapshot = chain.utxo_snapshot();
+    let difficulty = chain.expected_difficulty(3);
+
+    let mut tampered = snapshot.clone();
+    tampered.pop();
+    assert!(matches!(Blockchain::from_checkpoint(header, tampered, difficulty), Err(CheckpointError::CommitmentMismatch)));
+
+    let mut loaded = Blockchain::from_checkpoint(header, snapshot, difficulty).unwrap();
+    assert_eq!(loaded.height(), Some(2));
+    assert_eq!(loaded.last_hash(), chain.last_hash());
+    assert!(loaded.genesis().is_none());
+    assert_eq!(loaded.utxo_commitment(), chain.utxo_commitment());
+    assert_eq!(loaded.get_balance(&addr(1)), 150);
+
+    let block = mine_block(&loaded, vec![pay(coinbase_output(&chain, 0).0, 1, 2, 50)], 0, 3);
+    loaded.update_with_block(block.clone()).unwrap();
+    chain.update_with_block(block).unwrap();
+    assert_eq!(loaded.last_hash(), chain.last_hash());
+    assert_eq!(loaded.utxo_set_hash(), chain.utxo_set_hash());
+    assert_eq!(loaded.get_balance(&addr(2)), 50);
+}
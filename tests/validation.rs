@@ -0,0 +1,774 @@
+mod common;
+
+use blockchainlib::*;
+use blockchainlib::transaction::{Output, TxStructureErr};
+use common::*;
+
+#[test]
+fn output_values_overflowing_are_rejected () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+
+    let mut transaction = pay(coinbase_output(&chain, 0).0, 1, 2, u64::MAX);
+    transaction.outputs.push(Output { to_addr: addr(2), value: 2, script: vec![] });
+    transaction.sign(&key(1));
+    assert_eq!(transaction.output_value(), None);
+
+    let block = mine_block(&chain, vec![transaction], 0, 1);
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::Overflow)));
+}
+
+#[test]
+fn output_values_sum_up_to_the_limit () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+
+    let mut transaction = pay(coinbase_output(&chain, 0).0, 1, 2, u64::MAX - 2);
+    transaction.outputs.push(Output { to_addr: addr(2), value: 2, script: vec![] });
+    assert_eq!(transaction.output_value(), Some(u64::MAX));
+
+    transaction.outputs[1].value = 3;
+    transaction.sign(&key(1));
+    assert_eq!(chain.fee(&transaction), None);
+    assert!(matches!(Mempool::new().add_transaction(transaction, &chain), Err(MempoolErr::InvalidStructure(TxStructureErr::ValueOverflow))));
+}
+
+#[test]
+fn coinbase_values_overflowing_are_rejected () {
+    let mut chain = chain();
+    let mut coinbase = coinbase(1, u64::MAX, 0);
+    coinbase.outputs.push(Output { to_addr: addr(1), value: 1, script: vec![] });
+
+    let block = mine_raw(&chain, vec![coinbase], tick());
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::Overflow)));
+}
+
+#[test]
+fn blocks_stamped_far_in_the_future_are_rejected () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+
+    let block = mine_block_at(&chain, vec![], 0, 1, tick() + 3 * 60 * 60 * 1000);
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::TimestampTooFarInFuture)));
+
+    let block = mine_block_at(&chain, vec![], 0, 1, tick() + 60 * 60 * 1000);
+    chain.update_with_block(block).unwrap();
+}
+
+#[test]
+fn blocks_stamped_before_the_minimum_are_rejected () {
+    let min_timestamp = tick();
+    let mut chain = chain().with_min_timestamp(min_timestamp);
+
+    let block = mine_block_at(&chain, vec![], 0, 1, min_timestamp - 1);
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::TimestampTooOld)));
+    let block = mine_block_at(&chain, vec![], 0, 1, min_timestamp);
+    chain.update_with_block(block).unwrap();
+
+    // Nor can older blocks be loaded under the rule
+    let mut old = common::chain();
+    mine_blocks(&mut old, 2, 1);
+    let rule = old.tip().unwrap().header.timestamp;
+    let result = Blockchain::new(u128::MAX).with_min_timestamp(rule).with_blocks(old.blocks().cloned().collect());
+    assert!(matches!(result, Err((0, BlockValidationErr::TimestampTooOld))));
+}
+
+#[test]
+fn the_future_bound_follows_the_injected_clock () {
+    const NOW: u128 = 1_700_000_000_000;
+    const HOUR: u128 = 60 * 60 * 1000;
+
+    let mut chain = Blockchain::new(u128::MAX)
+        .with_clock(|| NOW)
+        .with_max_future_drift(2 * HOUR as u64);
+    let genesis = mine_block_at(&chain, vec![], 0, 1, NOW);
+    chain.update_with_block(genesis).unwrap();
+
+    let block = mine_block_at(&chain, vec![], 0, 1, NOW + 3 * HOUR);
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::TimestampTooFarInFuture)));
+
+    let block = mine_block_at(&chain, vec![], 0, 1, NOW + HOUR);
+    chain.update_with_block(block).unwrap();
+}
+
+#[test]
+fn spends_need_the_owner_s_signature () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+    let (outpoint, _) = coinbase_output(&chain, 0);
+
+    let forged = pay(outpoint, 2, 2, 50);
+    let block = mine_block(&chain, vec![forged], 0, 1);
+    assert!(matches!(
+        chain.update_with_block(block),
+        Err(BlockValidationErr::InvalidSignature { tx_index: 1, outpoint: spent }) if spent == outpoint
+    ));
+
+    let signed = pay(outpoint, 1, 2, 50);
+    let block = mine_block(&chain, vec![signed], 0, 1);
+    chain.update_with_block(block).unwrap();
+    assert_eq!(chain.get_balance(&addr(2)), 50);
+}
+
+#[test]
+fn the_reward_halves_at_each_interval () {
+    let chain = chain().with_block_reward(50, 10);
+
+    assert_eq!(chain.block_reward(0), 50);
+    assert_eq!(chain.block_reward(9), 50);
+    assert_eq!(chain.block_reward(10), 25);
+    assert_eq!(chain.block_reward(19), 25);
+    assert_eq!(chain.block_reward(20), 12);
+    assert_eq!(chain.block_reward(60), 0);
+    assert_eq!(chain.block_reward(u32::MAX), 0);
+}
+
+#[test]
+fn coinbases_paying_more_than_the_reward_are_rejected () {
+    let mut chain = chain();
+
+    let block = mine_raw(&chain, vec![coinbase(1, 51, 0)], tick());
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::InvalidCoinbaseTransaction)));
+
+    let block = mine_raw(&chain, vec![coinbase(1, 50, 0)], tick());
+    chain.update_with_block(block).unwrap();
+}
+
+#[test]
+fn coinbases_only_become_spendable_once_mature () {
+    let mut chain = chain().with_coinbase_maturity(3);
+    mine_blocks(&mut chain, 1, 1);
+    let (outpoint, _) = coinbase_output(&chain, 0);
+
+    let block = mine_block(&chain, vec![pay(outpoint, 1, 2, 50)], 0, 1);
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::ImmatureCoinbaseSpend { tx_index: 1, .. })));
+
+    mine_blocks(&mut chain, 2, 1);
+    let block = mine_block(&chain, vec![pay(outpoint, 1, 2, 50)], 0, 1);
+    chain.update_with_block(block).unwrap();
+}
+
+#[test]
+fn other_outputs_are_spendable_at_once () {
+    let mut chain = chain().with_coinbase_maturity(1);
+    mine_blocks(&mut chain, 1, 1);
+
+    let first = pay(coinbase_output(&chain, 0).0, 1, 2, 50);
+    let second = pay(first.outpoints()[0], 2, 3, 50);
+    let block = mine_block(&chain, vec![first], 0, 1);
+    chain.update_with_block(block).unwrap();
+
+    let block = mine_block(&chain, vec![second], 0, 1);
+    chain.update_with_block(block).unwrap();
+    assert_eq!(chain.get_balance(&addr(3)), 50);
+}
+
+#[test]
+fn blocks_over_the_transaction_limit_are_rejected () {
+    let mut chain = chain().with_max_block_transactions(3);
+    mine_blocks(&mut chain, 3, 1);
+    let spends = (0..3)
+        .map(|height| pay(coinbase_output(&chain, height).0, 1, 2, 50))
+        .collect::<Vec<Transaction>>();
+
+    let block = mine_block(&chain, spends.clone(), 0, 1);
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::BlockTooLarge)));
+
+    let block = mine_block(&chain, spends[..2].to_vec(), 0, 1);
+    chain.update_with_block(block).unwrap();
+}
+
+#[test]
+fn a_built_genesis_seeds_a_chain () {
+    let genesis = build_genesis(GenesisConfig {
+        difficulty: u128::MAX / 4,
+        initial_reward: 50,
+        recipient: addr(1),
+        timestamp: now(),
+    }).unwrap();
+
+    assert_eq!(genesis.header.prev_block_hash, Hash::zero());
+    let chain = Blockchain::with_genesis(u128::MAX / 4, genesis.clone()).unwrap();
+
+    assert_eq!(chain.genesis(), Some(&genesis));
+    assert_eq!(chain.get_balance(&addr(1)), 50);
+}
+
+#[test]
+fn a_second_coinbase_is_rejected () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+
+    let block = mine_raw(&chain, vec![coinbase(1, 50, 1), coinbase(2, 50, 1)], tick());
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::InvalidCoinbaseTransaction)));
+}
+
+#[test]
+fn the_same_transaction_twice_is_rejected () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+    let transaction = pay(coinbase_output(&chain, 0).0, 1, 2, 50);
+
+    let block = mine_raw(&chain, vec![coinbase(1, 50, 1), transaction.clone(), transaction], tick());
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::DuplicateTransaction)));
+}
+
+#[test]
+fn timestamps_must_beat_the_median_of_recent_blocks () {
+    let mut chain = chain();
+    let start = tick();
+    for i in 0..11 {
+        let block = mine_block_at(&chain, vec![], 0, 1, start + i * 100);
+        chain.update_with_block(block).unwrap();
+    }
+
+    // Earlier than the tip, and no later than the median of the last 11
+    let block = mine_block_at(&chain, vec![], 0, 1, start + 500);
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::TimestampBeforeMedian)));
+
+    // Still earlier than the tip, but past the median
+    let block = mine_block_at(&chain, vec![], 0, 1, start + 501);
+    chain.update_with_block(block).unwrap();
+}
+
+#[test]
+fn the_last_reward_is_clamped_to_the_supply_left () {
+    // Rewards of 50, 50, 25, 25, ... against a cap of 130
+    let mut chain = chain().with_block_reward(50, 2).with_max_supply(130);
+    mine_blocks(&mut chain, 3, 1);
+    assert_eq!(chain.total_minted(), 125);
+
+    // The full reward of 25 would take it to 150
+    let block = mine_block(&chain, vec![], 0, 1);
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::SupplyCapExceeded)));
+
+    // Mining pays just the 5 left, and nothing after that
+    mine_blocks(&mut chain, 2, 2);
+    assert_eq!(chain.total_minted(), 130);
+    assert_eq!(chain.get_balance(&addr(2)), 5);
+}
+
+#[test]
+fn coinbase_data_changes_the_block_hash () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+    let timestamp = tick();
+
+    let tagged = |data: &[u8]| {
+        let mut coinbase = coinbase(1, 50, 1);
+        coinbase.coinbase_data = data.to_vec();
+        mine_raw(&chain, vec![coinbase], timestamp)
+    };
+    let (first, second) = (tagged(b"pool one"), tagged(b"pool two"));
+
+    assert_ne!(first.header.merkle_root, second.header.merkle_root);
+    assert_ne!(first.hash(), second.hash());
+    chain.update_with_block(second).unwrap();
+}
+
+#[test]
+fn coinbase_data_is_bounded_and_only_for_coinbases () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+
+    let mut coinbase = coinbase(1, 50, 1);
+    coinbase.coinbase_data = vec![0; transaction::MAX_COINBASE_DATA + 1];
+    let block = mine_raw(&chain, vec![coinbase.clone()], tick());
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::InvalidCoinbaseData { tx_index: 0 })));
+
+    let mut payment = pay(coinbase_output(&chain, 0).0, 1, 2, 50);
+    payment.coinbase_data = b"tag".to_vec();
+    payment.sign(&key(1));
+    let block = mine_block(&chain, vec![payment], 0, 1);
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::InvalidCoinbaseData { tx_index: 1 })));
+
+    coinbase.coinbase_data.pop();
+    let block = mine_raw(&chain, vec![coinbase], tick());
+    chain.update_with_block(block).unwrap();
+}
+
+// A payment that can't be mined below `lock_height`
+fn locked (outpoint: OutPoint, lock_height: u32) -> Transaction {
+    let mut transaction = pay(outpoint, 1, 2, 50);
+    transaction.lock_height = lock_height;
+    transaction.sign(&key(1));
+    transaction
+}
+
+#[test]
+fn locked_transactions_wait_for_their_height () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 2, 1);
+    let transaction = locked(coinbase_output(&chain, 0).0, 3);
+
+    // The next block is at index 2, one below the lock
+    let block = mine_block(&chain, vec![transaction.clone()], 0, 1);
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::TransactionNotYetValid { tx_index: 1 })));
+
+    mine_blocks(&mut chain, 1, 1);
+    let block = mine_block(&chain, vec![transaction], 0, 1);
+    chain.update_with_block(block).unwrap();
+
+    // Past the lock is fine too
+    mine_blocks(&mut chain, 1, 1);
+    let block = mine_block(&chain, vec![locked(coinbase_output(&chain, 1).0, 3)], 0, 1);
+    chain.update_with_block(block).unwrap();
+}
+
+#[test]
+fn coinbases_ignore_their_lock_height () {
+    let mut chain = chain();
+    let mut coinbase = coinbase(1, 50, 0);
+    coinbase.lock_height = u32::MAX;
+
+    let block = mine_raw(&chain, vec![coinbase], tick());
+    chain.update_with_block(block).unwrap();
+}
+
+#[test]
+fn outputs_below_the_dust_threshold_are_rejected () {
+    let mut chain = chain().with_dust_threshold(10);
+    mine_blocks(&mut chain, 2, 1);
+
+    let split = |chain: &Blockchain, height, change| {
+        let mut transaction = pay(coinbase_output(chain, height).0, 1, 2, 50 - change);
+        transaction.outputs.push(Output { to_addr: addr(1), value: change, script: vec![] });
+        transaction.sign(&key(1));
+        transaction
+    };
+
+    let block = mine_block(&chain, vec![split(&chain, 0, 9)], 0, 1);
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::DustOutput { tx_index: 1 })));
+
+    let block = mine_block(&chain, vec![split(&chain, 0, 10)], 0, 1);
+    chain.update_with_block(block).unwrap();
+
+    // Zero is dust whatever the threshold
+    let mut chain = chain.with_dust_threshold(0);
+    let block = mine_block(&chain, vec![split(&chain, 1, 0)], 0, 1);
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::DustOutput { tx_index: 1 })));
+}
+
+#[test]
+fn coinbases_may_pay_dust () {
+    let mut chain = chain().with_dust_threshold(10);
+    let mut coinbase = coinbase(1, 45, 0);
+    coinbase.outputs.push(Output { to_addr: addr(2), value: 5, script: vec![] });
+
+    let block = mine_raw(&chain, vec![coinbase], tick());
+    chain.update_with_block(block).unwrap();
+}
+
+#[test]
+fn blocks_at_a_checkpoint_must_match_it () {
+    let mut source = chain();
+    mine_blocks(&mut source, 3, 1);
+    let checkpointed = || chain().with_checkpoint(2, source.block(2).unwrap().hash());
+
+    let mut chain = fork(checkpointed(), &source, 2);
+    let other = mine_block(&chain, vec![], 0, 2);
+    assert!(matches!(chain.update_with_block(other.clone()), Err(BlockValidationErr::CheckpointMismatch)));
+    chain.update_with_block(source.block(2).unwrap().clone()).unwrap();
+
+    // Heights without a checkpoint are unaffected
+    mine_blocks(&mut chain, 1, 2);
+
+    // Replaying a history that disagrees fails at the checkpoint
+    let mut history = source.blocks().take(2).cloned().collect::<Vec<Block>>();
+    history.push(other);
+    assert!(matches!(checkpointed().with_blocks(history), Err((2, BlockValidationErr::CheckpointMismatch))));
+}
+
+#[test]
+fn blocks_without_a_coinbase_are_rejected () {
+    let mut chain = chain();
+    let block = mine_raw(&chain, vec![], tick());
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::MissingCoinbase)));
+    assert_eq!(chain.block_count(), 0);
+
+    mine_blocks(&mut chain, 1, 1);
+    let block = mine_raw(&chain, vec![], tick());
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::MissingCoinbase)));
+    assert_eq!(chain.block_count(), 1);
+}
+
+#[test]
+fn coinbases_spending_outputs_are_rejected () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+
+    // Claims the reward and the coinbase before it
+    let mut coinbase = coinbase(1, 100, 1);
+    coinbase.inputs.push(transaction::Input::new(coinbase_output(&chain, 0).0));
+    coinbase.sign(&key(1));
+
+    let block = mine_raw(&chain, vec![coinbase], tick());
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::InvalidCoinbaseTransaction)));
+    assert!(chain.is_unspent(&coinbase_output(&chain, 0).0));
+}
+
+#[test]
+fn coinbases_must_pay_something () {
+    let mut chain = chain();
+    let mut coinbase = coinbase(1, 50, 0);
+    coinbase.outputs.clear();
+
+    let block = mine_raw(&chain, vec![coinbase], tick());
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::InvalidCoinbaseTransaction)));
+}
+
+#[test]
+fn the_coinbase_mark_has_to_agree_with_the_shape () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+
+    // Shaped like a coinbase, but not marked as one
+    let mut unmarked = coinbase(1, 50, 1);
+    unmarked.coinbase = false;
+    let block = mine_raw(&chain, vec![unmarked], tick());
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::InvalidCoinbaseTransaction)));
+
+    // Marked as a coinbase after the first, though it spends like any other
+    let mut marked = pay(coinbase_output(&chain, 0).0, 1, 2, 50);
+    marked.coinbase = true;
+    let block = mine_block(&chain, vec![marked.clone()], 0, 1);
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::InvalidCoinbaseTransaction)));
+    assert!(matches!(Mempool::new().add_transaction(marked, &chain), Err(MempoolErr::CoinbaseTransaction)));
+    assert!(chain.is_unspent(&coinbase_output(&chain, 0).0));
+}
+
+#[test]
+fn only_the_coinbase_may_spend_nothing () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+
+    let mut minted = pay(coinbase_output(&chain, 0).0, 1, 2, 50);
+    minted.inputs.clear();
+
+    let block = mine_block(&chain, vec![minted], 0, 1);
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::NoInputs { tx_index: 1 })));
+    assert_eq!(chain.get_balance(&addr(2)), 0);
+}
+
+#[test]
+fn transactions_must_be_in_canonical_order () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 2, 1);
+
+    let mut independent = [
+        pay(coinbase_output(&chain, 0).0, 1, 2, 50),
+        pay(coinbase_output(&chain, 1).0, 1, 2, 50),
+    ];
+    independent.sort_by_key(Transaction::hash);
+    let parent = independent[0].clone();
+    let child = pay(parent.outpoints()[0], 2, 3, 50);
+
+    let ordered = |chain: &Blockchain, transactions: &[&Transaction]| {
+        let mut block_transactions = vec![coinbase(1, 50, 2)];
+        block_transactions.extend(transactions.iter().map(|transaction| (*transaction).clone()));
+        mine_raw(chain, block_transactions, tick())
+    };
+
+    // Independent ones go by hash
+    let block = ordered(&chain, &[&independent[1], &independent[0]]);
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::NonCanonicalOrdering)));
+
+    // A spender goes after what it spends, whatever their hashes
+    let block = ordered(&chain, &[&child, &independent[0], &independent[1]]);
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::NonCanonicalOrdering)));
+
+    let canonical = canonical_order(vec![child.clone(), independent[1].clone(), parent.clone()], chain.hasher());
+    let position = |transaction: &Transaction| canonical.iter().position(|other| other.hash() == transaction.hash());
+    assert!(position(&parent) < position(&child));
+
+    let block = ordered(&chain, &canonical.iter().collect::<Vec<&Transaction>>());
+    chain.update_with_block(block).unwrap();
+}
+
+#[test]
+fn transactions_can_spend_outputs_made_earlier_in_the_block () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+
+    let first = pay(coinbase_output(&chain, 0).0, 1, 2, 50);
+    let second = pay(first.outpoints()[0], 2, 3, 50);
+    let third = pay(second.outpoints()[0], 3, 4, 50);
+
+    let block = mine_block(&chain, vec![third.clone(), first, second], 0, 1);
+    chain.update_with_block(block).unwrap();
+    assert_eq!(chain.get_balance(&addr(4)), 50);
+    assert_eq!(chain.get_balance(&addr(2)) + chain.get_balance(&addr(3)), 0);
+    assert!(chain.is_unspent(&third.outpoints()[0]));
+}
+
+#[test]
+fn a_coinbase_can_only_be_spent_from_a_later_block () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+    let coinbase = coinbase(1, 50, 1);
+    let spend = pay(coinbase.outpoints()[0], 1, 2, 50);
+
+    let block = mine_raw(&chain, vec![coinbase.clone(), spend.clone()], tick());
+    assert!(matches!(
+        chain.update_with_block(block),
+        Err(BlockValidationErr::SpendOfUnknownOutput { tx_index: 1, outpoint }) if outpoint == coinbase.outpoints()[0]
+    ));
+
+    let block = mine_raw(&chain, vec![coinbase], tick());
+    chain.update_with_block(block).unwrap();
+    let block = mine_block(&chain, vec![spend], 0, 1);
+    chain.update_with_block(block).unwrap();
+    assert_eq!(chain.get_balance(&addr(2)), 50);
+}
+
+#[test]
+fn an_output_made_in_the_block_can_only_be_spent_once () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+
+    let first = pay(coinbase_output(&chain, 0).0, 1, 2, 50);
+    let to_three = pay(first.outpoints()[0], 2, 3, 50);
+    let to_four = pay(first.outpoints()[0], 2, 4, 50);
+
+    let block = mine_block(&chain, vec![first, to_three, to_four], 0, 1);
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::DoubleSpendWithinBlock { .. })));
+}
+
+#[test]
+fn in_block_double_spends_are_told_apart_from_unknown_outputs () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+    let outpoint = coinbase_output(&chain, 0).0;
+
+    let block = mine_block(&chain, vec![pay(outpoint, 1, 2, 50), pay(outpoint, 1, 3, 50)], 0, 1);
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::DoubleSpendWithinBlock { tx_index: 2 })));
+
+    let block = mine_block(&chain, vec![pay(outpoint, 1, 2, 50)], 0, 1);
+    chain.update_with_block(block).unwrap();
+    let block = mine_block(&chain, vec![pay(outpoint, 1, 3, 50)], 0, 1);
+    assert!(matches!(
+        chain.update_with_block(block),
+        Err(BlockValidationErr::SpendOfUnknownOutput { tx_index: 1, outpoint: spent }) if spent == outpoint
+    ));
+}
+
+#[test]
+fn every_kind_of_bad_input_is_still_caught_in_a_large_block () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 20, 1);
+    let spends = (0..19).map(|height| pay(coinbase_output(&chain, height).0, 1, 2, 50)).collect::<Vec<Transaction>>();
+    let with = |extra: Transaction| {
+        let mut transactions = spends.clone();
+        transactions.push(extra);
+        transactions
+    };
+    let last = coinbase_output(&chain, 19).0;
+
+    let mut duplicate = pay(last, 1, 2, 50);
+    duplicate.inputs.push(transaction::Input::new(last));
+    duplicate.sign(&key(1));
+    let block = mine_block(&chain, with(duplicate), 0, 1);
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::DuplicateInput { .. })));
+
+    let block = mine_block(&chain, with(pay(coinbase_output(&chain, 3).0, 1, 3, 50)), 0, 1);
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::DoubleSpendWithinBlock { .. })));
+
+    let unknown = OutPoint { txid: Hash::digest(b"unknown"), index: 0 };
+    let block = mine_block(&chain, with(pay(unknown, 1, 3, 50)), 0, 1);
+    assert!(matches!(
+        chain.update_with_block(block),
+        Err(BlockValidationErr::SpendOfUnknownOutput { outpoint, .. }) if outpoint == unknown
+    ));
+
+    let block = mine_block(&chain, with(pay(last, 1, 3, 50)), 0, 1);
+    chain.update_with_block(block).unwrap();
+    assert_eq!(chain.get_balance(&addr(2)), 19 * 50);
+}
+
+// Spends the coinbases of the first `inputs` blocks, split over `outputs`
+fn fan (chain: &Blockchain, inputs: u32, outputs: u64) -> Transaction {
+    let mut transaction = pay(coinbase_output(chain, 0).0, 1, 2, 10);
+    for height in 1..inputs {
+        transaction.inputs.push(transaction::Input::new(coinbase_output(chain, height).0));
+    }
+    for recipient in 1..outputs {
+        transaction.outputs.push(Output { to_addr: addr(2 + recipient), value: 10, script: vec![] });
+    }
+    transaction.sign(&key(1));
+    transaction
+}
+
+#[test]
+fn transactions_are_limited_in_inputs_and_outputs () {
+    let mut chain = chain().with_max_inputs_outputs(2, 3);
+    mine_blocks(&mut chain, 3, 1);
+
+    for transaction in [fan(&chain, 3, 1), fan(&chain, 1, 4)] {
+        let block = mine_block(&chain, vec![transaction], 0, 1);
+        assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::TooManyInputsOrOutputs { tx_index: 1 })));
+    }
+
+    let at_the_limit = fan(&chain, 2, 3);
+    let fee = 100 - 30;
+    let block = mine_block(&chain, vec![at_the_limit], fee, 1);
+    chain.update_with_block(block).unwrap();
+}
+
+#[test]
+fn coinbases_have_their_own_output_limit () {
+    let mut chain = chain().with_max_inputs_outputs(1, 1).with_max_coinbase_outputs(2);
+    let split = |outputs: usize| {
+        let mut coinbase = coinbase(1, 50 - outputs as u64 + 1, 0);
+        coinbase.outputs.extend((1..outputs).map(|_| Output { to_addr: addr(2), value: 1, script: vec![] }));
+        coinbase
+    };
+
+    let block = mine_raw(&chain, vec![split(3)], tick());
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::TooManyInputsOrOutputs { tx_index: 0 })));
+
+    let block = mine_raw(&chain, vec![split(2)], tick());
+    chain.update_with_block(block).unwrap();
+}
+
+#[test]
+fn coinbases_may_be_limited_to_paying_the_miner () {
+    let split = |outputs: usize| {
+        let mut coinbase = coinbase(1, 1, 0);
+        coinbase.outputs.extend((1..outputs).map(|_| Output { to_addr: addr(2), value: 0, script: vec![] }));
+        coinbase.outputs.truncate(outputs);
+        coinbase
+    };
+
+    let mut chain = chain().with_max_coinbase_outputs(1);
+    let block = mine_raw(&chain, vec![split(2)], tick());
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::TooManyInputsOrOutputs { tx_index: 0 })));
+    let block = mine_raw(&chain, vec![split(0)], tick());
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::InvalidCoinbaseTransaction)));
+    let block = mine_raw(&chain, vec![split(1)], tick());
+    chain.update_with_block(block).unwrap();
+
+    // Left unset, a hundred outputs are allowed
+    let mut chain = common::chain();
+    let block = mine_raw(&chain, vec![split(101)], tick());
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::TooManyInputsOrOutputs { tx_index: 0 })));
+    let block = mine_raw(&chain, vec![split(100)], tick());
+    chain.update_with_block(block).unwrap();
+}
+
+// The next block on `chain`, with its header changed by `edit` before mining
+fn mine_edited<F: FnOnce(&mut BlockHeader)> (chain: &Blockchain, edit: F) -> Block {
+    let mut block = mine_block(chain, vec![], 0, 1);
+    edit(&mut block.header);
+    block.mine_with(block.header.difficulty, chain.hasher()).unwrap();
+    block
+}
+
+#[test]
+fn old_versions_are_rejected_once_a_new_one_activates () {
+    let mut chain = chain().with_min_version(3, BLOCK_VERSION + 1);
+    mine_blocks(&mut chain, 2, 1);
+
+    // Block 2 is still below the activation height
+    let block = mine_edited(&chain, |_| ());
+    assert_eq!(block.header.version, BLOCK_VERSION);
+    chain.update_with_block(block).unwrap();
+
+    let block = mine_edited(&chain, |_| ());
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::UnsupportedVersion)));
+
+    let block = mine_edited(&chain, |header| header.version = BLOCK_VERSION + 1);
+    chain.update_with_block(block).unwrap();
+}
+
+#[test]
+fn blocks_for_another_network_are_rejected () {
+    let mut mainnet = chain();
+    let mut testnet = chain().with_network(TESTNET);
+    assert_eq!(testnet.network(), TESTNET);
+
+    let testnet_block = mine_edited(&testnet, |header| header.network = TESTNET);
+    let mainnet_block = mine_edited(&mainnet, |_| ());
+
+    assert!(matches!(mainnet.update_with_block(testnet_block.clone()), Err(BlockValidationErr::WrongNetwork)));
+    assert!(matches!(testnet.update_with_block(mainnet_block.clone()), Err(BlockValidationErr::WrongNetwork)));
+    testnet.update_with_block(testnet_block).unwrap();
+    mainnet.update_with_block(mainnet_block).unwrap();
+}
+
+#[test]
+fn inputs_are_valued_at_what_the_chain_has_for_them () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 2, 1);
+    let quarter = pay(coinbase_output(&chain, 0).0, 1, 1, 25);
+    let block = mine_block(&chain, vec![quarter.clone()], 25, 1);
+    chain.update_with_block(block).unwrap();
+
+    // Worth 50 and 25 on the chain, whatever the spender would like
+    let mut transaction = pay(coinbase_output(&chain, 1).0, 1, 2, 80);
+    transaction.inputs.push(transaction::Input::new(quarter.outpoints()[0]));
+    transaction.sign(&key(1));
+    let block = mine_block(&chain, vec![transaction], 0, 1);
+    assert!(matches!(
+        chain.update_with_block(block),
+        Err(BlockValidationErr::InsufficientInputValue { tx_index: 1, shortfall: 5 })
+    ));
+
+    // Nor can it name an output the transaction never had
+    let mut inflated = quarter.outpoints()[0];
+    inflated.index = 1;
+    let block = mine_block(&chain, vec![pay(inflated, 1, 2, 50)], 0, 1);
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::SpendOfUnknownOutput { tx_index: 1, .. })));
+}
+
+#[test]
+fn changing_a_signature_leaves_the_txid_alone () {
+    let mut chain = chain().with_indexing(true);
+    mine_blocks(&mut chain, 1, 1);
+    let transaction = pay(coinbase_output(&chain, 0).0, 1, 2, 50);
+
+    // A broken signature no longer verifies
+    let mut broken = transaction.clone();
+    broken.inputs[0].witness[1] ^= 1;
+    assert_eq!(broken.hash(), transaction.hash());
+    assert_ne!(broken.wtxid(), transaction.wtxid());
+    let block = mine_block(&chain, vec![broken], 0, 1);
+    assert!(matches!(chain.update_with_block(block), Err(BlockValidationErr::InvalidSignature { tx_index: 1, .. })));
+
+    // Anyone can pad a witness without making it invalid, but the txid, and
+    // with it the outputs' names, stay the same
+    let mut padded = transaction.clone();
+    padded.inputs[0].witness.splice(0..0, script::push(b"padding"));
+    assert_eq!(padded.hash(), transaction.hash());
+    assert_ne!(padded.wtxid(), transaction.wtxid());
+    let block = mine_block(&chain, vec![padded.clone()], 0, 1);
+    chain.update_with_block(block).unwrap();
+
+    let (height, found) = chain.find_transaction(&transaction.hash()).unwrap();
+    assert_eq!(height, 1);
+    assert_eq!(found.wtxid(), padded.wtxid());
+    assert!(chain.is_unspent(&transaction.outpoints()[0]));
+}
+
+#[test]
+fn transactions_validate_on_their_own_against_the_tip () {
+    let mut chain = chain();
+    mine_blocks(&mut chain, 1, 1);
+    let outpoint = coinbase_output(&chain, 0).0;
+    let utxo_set_hash = chain.utxo_set_hash();
+
+    assert_eq!(chain.validate_transaction(&pay(outpoint, 1, 2, 46)).unwrap(), 4);
+    assert!(matches!(
+        chain.validate_transaction(&pay(outpoint, 1, 2, 51)),
+        Err(BlockValidationErr::InsufficientInputValue { tx_index: 0, shortfall: 1 })
+    ));
+    assert!(matches!(chain.validate_transaction(&pay(outpoint, 2, 2, 50)), Err(BlockValidationErr::InvalidSignature { tx_index: 0, .. })));
+    assert_eq!(chain.utxo_set_hash(), utxo_set_hash);
+
+    // Once the output is spent, spending it again is a double spend
+    let block = mine_block(&chain, vec![pay(outpoint, 1, 2, 50)], 0, 1);
+    chain.update_with_block(block).unwrap();
+    assert!(matches!(
+        chain.validate_transaction(&pay(outpoint, 1, 3, 50)),
+        Err(BlockValidationErr::SpendOfUnknownOutput { tx_index: 0, .. })
+    ));
+}